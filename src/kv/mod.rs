@@ -0,0 +1,662 @@
+//! A small embedded key-value store, a log-structured merge tree on top of the trees.
+//!
+//! Writes go to a memtable, a DurableRBTree in `<dir>/memtable`, so they survive a crash.
+//! Once the memtable holds more than memtable_bytes it is written to an immutable sorted
+//! segment file `seg_<first>-<last>.sst` and cleared. Deletes write a tombstone which
+//! shadows the key in the older segments.
+//!
+//! Reads look at the memtable first and then at the segments from the newest to the
+//! oldest. Compaction merges all segments into one, keeping the newest entry of every key
+//! and dropping tombstones, it runs on a background thread once compact_after segments
+//! were written, or on request with compact().
+//!
+//! A segment is a snapshot of the tree module whose values are an entry tag and the value.
+
+mod segment;
+
+use crate::tree::durable::{DurableRBTree, SyncPolicy, WalError};
+use crate::tree::rb_tree::RBTree;
+use crate::tree::snapshot::{Codec, SnapshotError};
+use segment::Segment;
+use std::fmt::{Display, Formatter};
+use std::ops::{Bound, RangeBounds, RangeInclusive};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::JoinHandle;
+
+const MEMTABLE_DIR: &str = "memtable";
+
+/// a key and its value as returned by scan()
+pub type KeyValue = (Vec<u8>, Vec<u8>);
+
+/// A value or the marker of a deleted key
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Entry {
+    Value(Vec<u8>),
+    Tombstone,
+}
+
+impl Entry {
+    const TAG_VALUE: u8 = 1;
+    const TAG_TOMBSTONE: u8 = 2;
+
+    fn into_value(self) -> Option<Vec<u8>> {
+        match self {
+            Entry::Value(value) => Some(value),
+            Entry::Tombstone => None,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Entry::Value(value) => value.len() + 1,
+            Entry::Tombstone => 1,
+        }
+    }
+}
+
+impl Codec for Entry {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Entry::Value(value) => {
+                buf.push(Entry::TAG_VALUE);
+                buf.extend_from_slice(value);
+            }
+            Entry::Tombstone => buf.push(Entry::TAG_TOMBSTONE),
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        match bytes.split_first() {
+            Some((&Entry::TAG_VALUE, value)) => Ok(Entry::Value(value.to_vec())),
+            Some((&Entry::TAG_TOMBSTONE, [])) => Ok(Entry::Tombstone),
+            _ => Err("invalid entry".to_string()),
+        }
+    }
+}
+
+/// Settings of a KvStore
+#[derive(Clone, Debug)]
+pub struct KvOptions {
+    /// flush the memtable to a segment once its keys and values take this many bytes
+    pub memtable_bytes: usize,
+    /// merge the segments once there are this many
+    pub compact_after: usize,
+    /// compact on a background thread, otherwise only compact() merges segments
+    pub background_compaction: bool,
+    /// when the memtable log is synced
+    pub sync: SyncPolicy,
+}
+
+impl Default for KvOptions {
+    fn default() -> Self {
+        KvOptions {
+            memtable_bytes: 4 << 20,
+            compact_after: 4,
+            background_compaction: true,
+            sync: SyncPolicy::Always,
+        }
+    }
+}
+
+impl KvOptions {
+    pub fn memtable_bytes(mut self, bytes: usize) -> Self {
+        self.memtable_bytes = bytes;
+        self
+    }
+
+    pub fn compact_after(mut self, segments: usize) -> Self {
+        self.compact_after = segments.max(2);
+        self
+    }
+
+    pub fn background_compaction(mut self, enabled: bool) -> Self {
+        self.background_compaction = enabled;
+        self
+    }
+
+    pub fn sync(mut self, policy: SyncPolicy) -> Self {
+        self.sync = policy;
+        self
+    }
+}
+
+/// Why the store could not be read or written
+#[derive(Debug)]
+pub enum KvError {
+    Io(std::io::Error),
+    /// the memtable log failed
+    Wal(WalError),
+    /// a segment file is damaged
+    Segment {
+        path: PathBuf,
+        err: SnapshotError,
+    },
+}
+
+impl Display for KvError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KvError::Io(err) => write!(f, "store i/o error: {}", err),
+            KvError::Wal(err) => write!(f, "memtable: {}", err),
+            KvError::Segment { path, err } => write!(f, "segment {}: {}", path.display(), err),
+        }
+    }
+}
+
+impl std::error::Error for KvError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KvError::Io(err) => Some(err),
+            KvError::Wal(err) => Some(err),
+            KvError::Segment { err, .. } => Some(err),
+        }
+    }
+}
+
+impl From<std::io::Error> for KvError {
+    fn from(err: std::io::Error) -> Self {
+        KvError::Io(err)
+    }
+}
+
+impl From<WalError> for KvError {
+    fn from(err: WalError) -> Self {
+        KvError::Wal(err)
+    }
+}
+
+struct State {
+    memtable: DurableRBTree<Vec<u8>, Entry>,
+    /// the bytes taken by the keys and entries in the memtable
+    mem_bytes: usize,
+    /// oldest first
+    segments: Vec<Arc<Segment>>,
+    /// the id of the next flush
+    next_id: u64,
+}
+
+#[derive(Default)]
+struct Signal {
+    requested: bool,
+    shutdown: bool,
+}
+
+struct Shared {
+    dir: PathBuf,
+    options: KvOptions,
+    state: RwLock<State>,
+    /// held while segments are merged, one compaction at a time
+    compaction: Mutex<()>,
+    signal: Mutex<Signal>,
+    wakeup: Condvar,
+}
+
+/// A key-value store in a directory, see the module docs
+pub struct KvStore {
+    shared: Arc<Shared>,
+    compactor: Option<JoinHandle<()>>,
+}
+
+impl KvStore {
+    /// Opens the store in dir, the directory is created if it does not exist.
+    /// Leftovers of an interrupted flush or compaction are removed.
+    pub fn open(dir: impl AsRef<Path>, options: KvOptions) -> Result<KvStore, KvError> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut found: Vec<RangeInclusive<u64>> = Vec::new();
+        for dir_entry in std::fs::read_dir(&dir)? {
+            let path = dir_entry?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if let Some(ids) = Segment::parse_name(name) {
+                found.push(ids);
+            } else if name.starts_with("seg_") && name.ends_with(".tmp") {
+                std::fs::remove_file(&path)?;
+            }
+        }
+        // the inputs of a compaction that was not cleaned up are covered by its output
+        found.sort_by_key(|ids| (*ids.start(), u64::MAX - *ids.end()));
+        let mut segments: Vec<Arc<Segment>> = Vec::new();
+        for ids in found {
+            let path = dir.join(Segment::file_name(&ids));
+            match segments.last() {
+                Some(last) if last.ids.end() >= ids.start() => std::fs::remove_file(&path)?,
+                _ => segments.push(Arc::new(Segment::open(path, ids)?)),
+            }
+        }
+        let next_id = segments.last().map_or(0, |last| last.ids.end() + 1);
+
+        let memtable: DurableRBTree<Vec<u8>, Entry> =
+            DurableRBTree::open(dir.join(MEMTABLE_DIR), options.sync)?;
+        let mem_bytes = memtable
+            .tree()
+            .iter()
+            .map(|(key, entry)| key.len() + entry.len())
+            .sum();
+
+        let background = options.background_compaction;
+        let shared = Arc::new(Shared {
+            dir,
+            options,
+            state: RwLock::new(State {
+                memtable,
+                mem_bytes,
+                segments,
+                next_id,
+            }),
+            compaction: Mutex::new(()),
+            signal: Mutex::new(Signal::default()),
+            wakeup: Condvar::new(),
+        });
+        let compactor = if background {
+            let shared = shared.clone();
+            Some(std::thread::spawn(move || shared.run_compactor()))
+        } else {
+            None
+        };
+        let store = KvStore { shared, compactor };
+        store.shared.request_compaction();
+        Ok(store)
+    }
+
+    /// the value of key
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, KvError> {
+        let state = self.shared.read_state();
+        if let Some(entry) = state.memtable.find(&key.to_vec()) {
+            return Ok(entry.clone().into_value());
+        }
+        for segment in state.segments.iter().rev() {
+            if let Some(entry) = segment.get(key)? {
+                return Ok(entry.into_value());
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<(), KvError> {
+        self.write(key.to_vec(), Entry::Value(value.to_vec()))
+    }
+
+    /// Deletes key. The tombstone is written even if the key is not found,
+    /// it is dropped again by the compaction.
+    pub fn delete(&self, key: &[u8]) -> Result<(), KvError> {
+        self.write(key.to_vec(), Entry::Tombstone)
+    }
+
+    /// the entries with keys in range in ascending key order
+    pub fn scan<R: RangeBounds<Vec<u8>>>(&self, range: R) -> Result<Vec<KeyValue>, KvError> {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        let state = self.shared.read_state();
+        // newer entries replace older ones
+        let mut merged: RBTree<Vec<u8>, Entry> = RBTree::new();
+        for segment in state.segments.iter() {
+            let first = segment.index.partition_point(|entry| match &bounds.0 {
+                Bound::Included(start) => entry.key < *start,
+                Bound::Excluded(start) => entry.key <= *start,
+                Bound::Unbounded => false,
+            });
+            for idx in first..segment.index.len() {
+                let key = &segment.index[idx].key;
+                if !bounds.contains(key) {
+                    break;
+                }
+                merged.insert(key.clone(), segment.entry(idx)?);
+            }
+        }
+        for (key, entry) in state.memtable.tree().range(bounds) {
+            merged.insert(key.clone(), entry.clone());
+        }
+        Ok(merged
+            .iter()
+            .filter_map(|(key, entry)| Some((key.clone(), entry.clone().into_value()?)))
+            .collect())
+    }
+
+    /// writes the memtable to a segment, even if it is below the threshold
+    pub fn flush(&self) -> Result<(), KvError> {
+        let mut state = self.shared.write_state();
+        self.shared.flush(&mut state)
+    }
+
+    /// merges all segments into one, waits for a running background compaction
+    pub fn compact(&self) -> Result<(), KvError> {
+        self.shared.compact(2)
+    }
+
+    /// the number of segment files
+    pub fn segment_count(&self) -> usize {
+        self.shared.read_state().segments.len()
+    }
+
+    fn write(&self, key: Vec<u8>, entry: Entry) -> Result<(), KvError> {
+        let mut state = self.shared.write_state();
+        let key_len = key.len();
+        let added = key_len + entry.len();
+        if let Some(old) = state.memtable.insert(key, entry)? {
+            state.mem_bytes -= key_len + old.len();
+        }
+        state.mem_bytes += added;
+        if state.mem_bytes >= self.shared.options.memtable_bytes {
+            self.shared.flush(&mut state)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for KvStore {
+    fn drop(&mut self) {
+        if let Some(compactor) = self.compactor.take() {
+            self.shared
+                .signal
+                .lock()
+                .expect("signal lock poisoned")
+                .shutdown = true;
+            self.shared.wakeup.notify_all();
+            let _ = compactor.join();
+        }
+    }
+}
+
+impl Shared {
+    fn read_state(&self) -> std::sync::RwLockReadGuard<'_, State> {
+        self.state.read().expect("state lock poisoned")
+    }
+
+    fn write_state(&self) -> std::sync::RwLockWriteGuard<'_, State> {
+        self.state.write().expect("state lock poisoned")
+    }
+
+    /// Writes the memtable to a new segment and clears it. A crash before the memtable
+    /// is cleared replays entries that are in the segment already, which is harmless.
+    fn flush(&self, state: &mut State) -> Result<(), KvError> {
+        let tree = state.memtable.tree();
+        let count = tree.len() as u64;
+        if count == 0 {
+            return Ok(());
+        }
+        let id = state.next_id;
+        let segment = Segment::write(&self.dir, id..=id, count, |writer| {
+            for (key, entry) in tree.iter() {
+                writer.push(key, entry).map_err(|err| KvError::Segment {
+                    path: self.dir.join(Segment::file_name(&(id..=id))),
+                    err,
+                })?;
+            }
+            Ok(())
+        })?;
+        state.segments.push(Arc::new(segment));
+        state.next_id += 1;
+        state.memtable.clear()?;
+        state.mem_bytes = 0;
+        if state.segments.len() >= self.options.compact_after {
+            self.request_compaction();
+        }
+        Ok(())
+    }
+
+    /// Merges the current segments if there are at least min_segments of them.
+    /// Reads and writes continue meanwhile, segments flushed in the meantime are
+    /// newer than the merged ones and are kept.
+    fn compact(&self, min_segments: usize) -> Result<(), KvError> {
+        let _compaction = self.compaction.lock().expect("compaction lock poisoned");
+        let inputs: Vec<Arc<Segment>> = self.read_state().segments.clone();
+        if inputs.len() < min_segments.max(2) {
+            return Ok(());
+        }
+        let ids = *inputs[0].ids.start()..=*inputs[inputs.len() - 1].ids.end();
+
+        // pick the newest entry of every key, no older segment is left for a
+        // tombstone to shadow, so they are dropped
+        let mut cursors = vec![0usize; inputs.len()];
+        let mut picked: Vec<(usize, usize)> = Vec::new();
+        loop {
+            let mut newest: Option<(usize, &[u8])> = None;
+            for (seg, segment) in inputs.iter().enumerate() {
+                if let Some(entry) = segment.index.get(cursors[seg]) {
+                    if newest.is_none_or(|(_, key)| entry.key.as_slice() <= key) {
+                        newest = Some((seg, &entry.key));
+                    }
+                }
+            }
+            let Some((seg, key)) = newest else {
+                break;
+            };
+            if !inputs[seg].index[cursors[seg]].tombstone {
+                picked.push((seg, cursors[seg]));
+            }
+            let key = key.to_vec();
+            for (seg, segment) in inputs.iter().enumerate() {
+                if segment
+                    .index
+                    .get(cursors[seg])
+                    .is_some_and(|entry| entry.key == key)
+                {
+                    cursors[seg] += 1;
+                }
+            }
+        }
+
+        let merged = Segment::write(&self.dir, ids.clone(), picked.len() as u64, |writer| {
+            for (seg, idx) in picked {
+                let entry = inputs[seg].entry(idx)?;
+                writer
+                    .push(&inputs[seg].index[idx].key, &entry)
+                    .map_err(|err| KvError::Segment {
+                        path: self.dir.join(Segment::file_name(&ids)),
+                        err,
+                    })?;
+            }
+            Ok(())
+        })?;
+
+        {
+            let mut state = self.write_state();
+            state.segments.splice(..inputs.len(), [Arc::new(merged)]);
+        }
+        // a crash before the inputs are gone leaves them covered by the merged segment,
+        // open() removes them
+        for segment in inputs {
+            std::fs::remove_file(&segment.path)?;
+        }
+        crate::tree::durable::sync_dir(&self.dir)?;
+        Ok(())
+    }
+
+    fn request_compaction(&self) {
+        self.signal.lock().expect("signal lock poisoned").requested = true;
+        self.wakeup.notify_all();
+    }
+
+    /// the background compaction, a failed compaction is retried with the next flush
+    fn run_compactor(&self) {
+        loop {
+            {
+                let mut signal = self.signal.lock().expect("signal lock poisoned");
+                while !signal.requested && !signal.shutdown {
+                    signal = self.wakeup.wait(signal).expect("signal lock poisoned");
+                }
+                if signal.shutdown {
+                    return;
+                }
+                signal.requested = false;
+            }
+            let _ = self.compact(self.options.compact_after);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn options() -> KvOptions {
+        KvOptions::default()
+            .memtable_bytes(64)
+            .background_compaction(false)
+            .sync(SyncPolicy::Never)
+    }
+
+    fn key(idx: u32) -> Vec<u8> {
+        format!("key{:04}", idx).into_bytes()
+    }
+
+    fn segment_files(dir: &Path) -> usize {
+        std::fs::read_dir(dir)
+            .expect("failed to list the directory")
+            .filter(|entry| {
+                let name = entry
+                    .as_ref()
+                    .expect("failed to read the entry")
+                    .file_name();
+                Segment::parse_name(name.to_str().expect("unexpected file name")).is_some()
+            })
+            .count()
+    }
+
+    #[test]
+    fn kv_test_reopen() {
+        let dir = tempfile::tempdir().expect("failed to create a temp dir");
+        {
+            let store = KvStore::open(dir.path(), options()).expect("failed to open");
+            for idx in 0..50 {
+                store
+                    .put(&key(idx), &idx.to_le_bytes())
+                    .expect("failed to put");
+            }
+            for idx in (0..50).step_by(3) {
+                store.delete(&key(idx)).expect("failed to delete");
+            }
+            assert!(store.segment_count() > 1);
+        }
+        let store = KvStore::open(dir.path(), options()).expect("failed to reopen");
+        for idx in 0..50u32 {
+            let expected = (idx % 3 != 0).then(|| idx.to_le_bytes().to_vec());
+            assert_eq!(store.get(&key(idx)).expect("failed to get"), expected);
+        }
+        assert_eq!(store.get(b"missing").expect("failed to get"), None);
+        let scanned = store.scan(key(10)..key(20)).expect("failed to scan");
+        let expected: Vec<(Vec<u8>, Vec<u8>)> = (10..20)
+            .filter(|idx| idx % 3 != 0)
+            .map(|idx| (key(idx), idx.to_le_bytes().to_vec()))
+            .collect();
+        assert_eq!(scanned, expected);
+    }
+
+    #[test]
+    fn kv_test_tombstones() {
+        let dir = tempfile::tempdir().expect("failed to create a temp dir");
+        let store =
+            KvStore::open(dir.path(), options().memtable_bytes(1 << 20)).expect("failed to open");
+        store.put(b"a", b"1").expect("failed to put");
+        store.put(b"b", b"2").expect("failed to put");
+        store.flush().expect("failed to flush");
+        store.delete(b"a").expect("failed to delete");
+        store.put(b"b", b"3").expect("failed to put");
+        store.flush().expect("failed to flush");
+        assert_eq!(store.segment_count(), 2);
+        assert_eq!(store.get(b"a").expect("failed to get"), None);
+        assert_eq!(store.get(b"b").expect("failed to get"), Some(b"3".to_vec()));
+        assert_eq!(
+            store.scan(..).expect("failed to scan"),
+            vec![(b"b".to_vec(), b"3".to_vec())]
+        );
+
+        store.compact().expect("failed to compact");
+        assert_eq!(store.segment_count(), 1);
+        assert_eq!(segment_files(dir.path()), 1);
+        let state = store.shared.read_state();
+        let keys: Vec<&[u8]> = state.segments[0]
+            .index
+            .iter()
+            .map(|entry| entry.key.as_slice())
+            .collect();
+        assert_eq!(keys, vec![b"b".as_slice()]);
+    }
+
+    #[test]
+    fn kv_test_background_compaction() {
+        let dir = tempfile::tempdir().expect("failed to create a temp dir");
+        let store = KvStore::open(
+            dir.path(),
+            options().compact_after(3).background_compaction(true),
+        )
+        .expect("failed to open");
+        for round in 0..4u32 {
+            for idx in 0..40 {
+                store
+                    .put(&key(idx), &(idx + round).to_le_bytes())
+                    .expect("failed to put");
+            }
+        }
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while store.segment_count() >= 3 {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "no background compaction"
+            );
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        for idx in 0..40 {
+            assert_eq!(
+                store.get(&key(idx)).expect("failed to get"),
+                Some((idx + 3).to_le_bytes().to_vec())
+            );
+        }
+        drop(store);
+        let store = KvStore::open(dir.path(), options()).expect("failed to reopen");
+        assert_eq!(store.scan(..).expect("failed to scan").len(), 40);
+    }
+
+    #[test]
+    fn kv_test_interrupted_compaction() {
+        let dir = tempfile::tempdir().expect("failed to create a temp dir");
+        {
+            let store = KvStore::open(dir.path(), options().memtable_bytes(1 << 20))
+                .expect("failed to open");
+            for id in 0..3u32 {
+                store.put(&key(id), b"old").expect("failed to put");
+                store
+                    .put(b"shared", &id.to_le_bytes())
+                    .expect("failed to put");
+                store.flush().expect("failed to flush");
+            }
+            // keep copies of the inputs as if the compaction stopped before deleting them
+            let paths: Vec<PathBuf> = store
+                .shared
+                .read_state()
+                .segments
+                .iter()
+                .map(|segment| segment.path.clone())
+                .collect();
+            let copies: Vec<Vec<u8>> = paths
+                .iter()
+                .map(|path| std::fs::read(path).expect("failed to read the segment"))
+                .collect();
+            store.compact().expect("failed to compact");
+            for (path, bytes) in paths.iter().zip(copies) {
+                std::fs::write(path, bytes).expect("failed to restore the segment");
+            }
+            std::fs::write(
+                dir.path().join("seg_0000000000000009-0000000000000009.tmp"),
+                b"torn",
+            )
+            .expect("failed to write");
+        }
+        assert_eq!(segment_files(dir.path()), 4);
+        let store = KvStore::open(dir.path(), options()).expect("failed to reopen");
+        assert_eq!(store.segment_count(), 1);
+        assert_eq!(segment_files(dir.path()), 1);
+        assert_eq!(
+            store.get(b"shared").expect("failed to get"),
+            Some(2u32.to_le_bytes().to_vec())
+        );
+        assert_eq!(store.scan(..).expect("failed to scan").len(), 4);
+        assert!(!dir
+            .path()
+            .join("seg_0000000000000009-0000000000000009.tmp")
+            .exists());
+    }
+}
@@ -0,0 +1,140 @@
+use super::{Entry, KvError};
+use crate::tree::durable::sync_dir;
+use crate::tree::snapshot::{self, Codec, SnapshotError, Writer};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A key in the index of a segment
+pub(crate) struct IndexEntry {
+    pub key: Vec<u8>,
+    /// position and length of the encoded entry in the file
+    offset: u64,
+    len: u32,
+    pub tombstone: bool,
+}
+
+/// An immutable sorted file of entries, written as a snapshot whose values are Entries.
+/// The keys are kept in memory, the values are read from the file on demand.
+///
+/// The file name holds the ids of the memtable flushes it contains, a flush writes
+/// `seg_<id>-<id>.sst`, merging segments writes one covering all their ids.
+pub(crate) struct Segment {
+    pub ids: RangeInclusive<u64>,
+    pub path: PathBuf,
+    pub index: Vec<IndexEntry>,
+    file: Mutex<File>,
+}
+
+impl Segment {
+    pub fn file_name(ids: &RangeInclusive<u64>) -> String {
+        format!("seg_{:016}-{:016}.sst", ids.start(), ids.end())
+    }
+
+    /// the ids covered by a segment file name, None for other files
+    pub fn parse_name(name: &str) -> Option<RangeInclusive<u64>> {
+        let (first, last) = name
+            .strip_prefix("seg_")?
+            .strip_suffix(".sst")?
+            .split_once('-')?;
+        Some(first.parse().ok()?..=last.parse().ok()?)
+    }
+
+    /// Writes count entries pushed by fill in ascending key order to a new segment.
+    /// The file appears under its final name only once it is complete and synced.
+    pub fn write(
+        dir: &Path,
+        ids: RangeInclusive<u64>,
+        count: u64,
+        fill: impl FnOnce(&mut Writer<BufWriter<File>>) -> Result<(), KvError>,
+    ) -> Result<Segment, KvError> {
+        let path = dir.join(Segment::file_name(&ids));
+        let tmp = path.with_extension("tmp");
+        let segment_err = |err: SnapshotError| KvError::Segment {
+            path: tmp.clone(),
+            err,
+        };
+        let mut writer =
+            Writer::new(BufWriter::new(File::create(&tmp)?), count).map_err(segment_err)?;
+        fill(&mut writer)?;
+        writer
+            .finish()
+            .map_err(segment_err)?
+            .into_inner()
+            .map_err(|err| KvError::Io(err.into_error()))?
+            .sync_all()?;
+        std::fs::rename(&tmp, &path)?;
+        sync_dir(dir)?;
+        Segment::open(path, ids)
+    }
+
+    /// Opens a segment and reads its keys, the whole file is verified against its checksum
+    /// before the first key is indexed
+    pub fn open(path: PathBuf, ids: RangeInclusive<u64>) -> Result<Segment, KvError> {
+        let mut file = File::open(&path)?;
+        let mut index: Vec<IndexEntry> = Vec::new();
+        let res =
+            snapshot::read_records(BufReader::new(&mut file), |record, key, value, offset| {
+                if index.last().is_some_and(|last| last.key.as_slice() >= key) {
+                    return Err(SnapshotError::Unsorted { record });
+                }
+                let tombstone = match value.first() {
+                    Some(&Entry::TAG_VALUE) => false,
+                    Some(&Entry::TAG_TOMBSTONE) => true,
+                    _ => {
+                        return Err(SnapshotError::Decode {
+                            record,
+                            msg: "invalid entry".to_string(),
+                        })
+                    }
+                };
+                index.push(IndexEntry {
+                    key: key.to_vec(),
+                    offset,
+                    len: value.len() as u32,
+                    tombstone,
+                });
+                Ok(())
+            });
+        if let Err(err) = res {
+            return Err(KvError::Segment { path, err });
+        }
+        Ok(Segment {
+            ids,
+            path,
+            index,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// the entry for key, None if the segment does not hold it
+    pub fn get(&self, key: &[u8]) -> Result<Option<Entry>, KvError> {
+        match self
+            .index
+            .binary_search_by(|entry| entry.key.as_slice().cmp(key))
+        {
+            Ok(idx) => Ok(Some(self.entry(idx)?)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// reads the entry at position idx of the index
+    pub fn entry(&self, idx: usize) -> Result<Entry, KvError> {
+        let entry = &self.index[idx];
+        let mut buf = vec![0u8; entry.len as usize];
+        {
+            let mut file = self.file.lock().expect("segment lock poisoned");
+            file.seek(SeekFrom::Start(entry.offset))?;
+            file.read_exact(&mut buf)?;
+        }
+        Entry::decode(&buf).map_err(|msg| KvError::Segment {
+            path: self.path.clone(),
+            err: SnapshotError::Decode {
+                record: idx as u64,
+                msg,
+            },
+        })
+    }
+}
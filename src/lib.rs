@@ -1,17 +1,35 @@
+//! Without the default std feature the crate is #![no_std] and needs only alloc,
+//! tree::static_rb_tree::StaticRBTree does not allocate at all.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod kv;
 pub mod list;
 pub mod tree;
+
+/// the parts of the std prelude that live in alloc
+pub(crate) mod prelude {
+    pub use alloc::boxed::Box;
+    pub use alloc::format;
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec;
+    pub use alloc::vec::Vec;
+}
+
 pub mod util {
+    use crate::prelude::*;
+
     pub fn make_list_string(len: usize) -> Vec<String> {
-        let mut res = Vec::new();
-        res.reserve(len);
+        let mut res = Vec::with_capacity(len);
         for idx in 1..=len {
             res.push(idx.to_string())
         }
         res
     }
     pub fn make_list_usize(len: usize) -> Vec<usize> {
-        let mut res = Vec::new();
-        res.reserve(len);
+        let mut res = Vec::with_capacity(len);
         for idx in 1..=len {
             res.push(idx)
         }
@@ -1,4 +1,6 @@
 pub mod linked_list {
+    use crate::prelude::*;
+
     type Link<T> = Option<Box<Entry<T>>>;
 
     struct Entry<T> {
@@ -92,7 +94,7 @@ pub mod linked_list {
     }
 
     impl<T> LinkedList<T> {
-        pub fn iter(&self) -> Iter<T> {
+        pub fn iter(&self) -> Iter<'_, T> {
             Iter {
                 next: self.list.as_deref(),
             }
@@ -115,7 +117,7 @@ pub mod linked_list {
     }
 
     impl<T> LinkedList<T> {
-        pub fn iter_mut(&mut self) -> IterMut<T> {
+        pub fn iter_mut(&mut self) -> IterMut<'_, T> {
             IterMut {
                 next: self.list.as_deref_mut(),
             }
@@ -132,6 +134,27 @@ pub mod linked_list {
             })
         }
     }
+
+    /// the list serializes as a sequence from the head to the tail
+    #[cfg(feature = "serde")]
+    impl<T: serde::Serialize> serde::Serialize for LinkedList<T> {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(self.iter())
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for LinkedList<T> {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            // push prepends, so the values are pushed from the tail to the head
+            let values = Vec::<T>::deserialize(deserializer)?;
+            let mut list = LinkedList::new();
+            for value in values.into_iter().rev() {
+                list.push(value);
+            }
+            Ok(list)
+        }
+    }
 }
 
 #[cfg(test)]
@@ -244,4 +267,21 @@ mod test {
             assert_eq!(left, right);
         }
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_json() {
+        let mut list = LinkedList::new();
+        for val in make_list_string(3) {
+            list.push(val);
+        }
+        let json = serde_json::to_string(&list).expect("failed to serialize");
+        assert_eq!(json, r#"["3","2","1"]"#);
+        let copy: LinkedList<String> = serde_json::from_str(&json).expect("failed to deserialize");
+        assert_eq!(
+            copy.iter().collect::<Vec<_>>(),
+            list.iter().collect::<Vec<_>>()
+        );
+        assert!(serde_json::from_str::<LinkedList<u32>>("{}").is_err());
+    }
 }
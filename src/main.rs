@@ -1,7 +1,56 @@
+use colored::*;
+use rand::seq::SliceRandom;
 use rand::Rng;
-use rust_tree::tree::rb_tree::RBTree;
+use rust_tree::tree::avl_tree::AVLTree;
+use rust_tree::tree::dot::DotOptions;
+use rust_tree::tree::rb_tree::{Color, RBTree, RotDir, TreeEvent};
+use rust_tree::tree::splay_tree::SplayTree;
+use rust_tree::tree::svg::SvgOptions;
+use std::hint::black_box;
+use std::path::PathBuf;
+use std::time::Instant;
+
+const USAGE: &str = "usage:
+  rust_tree                       insert 40 random keys and print the tree
+  rust_tree animate [OPTIONS] [KEYS]
+                                  insert or remove KEYS one by one and print every tree,
+                                  ten random keys are inserted if no KEYS are given
+  rust_tree zipf [KEYS] [LOOKUPS] [SKEW]
+                                  time lookups of Zipf distributed keys in a SplayTree,
+                                  an RBTree and an AVLTree, against uniform lookups,
+                                  and show the average number of nodes visited per
+                                  lookup, defaults: 100000 keys, 1000000 lookups,
+                                  skew 2.0. From a skew of about 1.5 the splay tree
+                                  visits the fewest nodes, but every lookup rewrites
+                                  the links on its path, which the time shows
+options:
+  --insert                        insert the following keys (the default)
+  --remove                        remove the following keys
+  --dot DIR                       also write every frame to DIR/frame_NNN.dot
+  --svg DIR                       also write every frame to DIR/frame_NNN.svg";
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let res = match args.first().map(|arg| arg.as_str()) {
+        None => {
+            random_tree();
+            Ok(())
+        }
+        Some("animate") => animate(&args[1..]),
+        Some("zipf") => zipf(&args[1..]),
+        Some("-h") | Some("--help") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some(arg) => Err(format!("unknown command: {}", arg)),
+    };
+    if let Err(msg) = res {
+        eprintln!("{}\n{}", msg, USAGE);
+        std::process::exit(2);
+    }
+}
+
+fn random_tree() {
     let mut tree = RBTree::new();
     let mut rng = rand::thread_rng();
 
@@ -14,8 +63,11 @@ fn main() {
                 assert_eq!(tree.insert(val, val.to_string()), None);
                 if let std::result::Result::Err(msg) = tree.check_rules() {
                     eprintln!(
-                        "RB violation after insert of {}, msg: {}\n{:?}",
-                        val, msg, tree
+                        "RB violation after insert of {}, msg: {}\n{}\nshape: {}",
+                        val,
+                        msg,
+                        tree.pretty(),
+                        tree.to_shape()
                     );
                     panic!("{}", msg)
                 }
@@ -24,5 +76,273 @@ fn main() {
             }
         }
     }
-    println!("{:?}", tree);
+    println!("{}", tree.pretty());
+}
+
+enum Op {
+    Insert(u32),
+    Remove(u32),
+}
+
+enum Export {
+    Dot(PathBuf),
+    Svg(PathBuf),
+}
+
+fn animate(args: &[String]) -> Result<(), String> {
+    let mut ops = Vec::new();
+    let mut export = None;
+    let mut remove = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--insert" => remove = false,
+            "--remove" => remove = true,
+            "--dot" | "--svg" => {
+                let dir = PathBuf::from(args.next().ok_or(format!("{} needs a directory", arg))?);
+                export = Some(if arg == "--dot" {
+                    Export::Dot(dir)
+                } else {
+                    Export::Svg(dir)
+                });
+            }
+            _ => {
+                let key = arg
+                    .parse::<u32>()
+                    .map_err(|_| format!("invalid key: {}", arg))?;
+                ops.push(if remove {
+                    Op::Remove(key)
+                } else {
+                    Op::Insert(key)
+                });
+            }
+        }
+    }
+    if ops.is_empty() {
+        let mut rng = rand::thread_rng();
+        ops = (0..10).map(|_| Op::Insert(rng.gen_range(1..100))).collect();
+    }
+    if let Some(Export::Dot(dir) | Export::Svg(dir)) = &export {
+        std::fs::create_dir_all(dir)
+            .map_err(|err| format!("failed to create {}: {}", dir.display(), err))?;
+    }
+
+    // the attached Vec records the events of every operation
+    let mut tree = RBTree::new().with_observer(Vec::new());
+    for (frame, op) in ops.iter().enumerate() {
+        let title = match op {
+            Op::Insert(key) => {
+                tree.insert(*key, ());
+                format!("insert {}", key)
+            }
+            Op::Remove(key) => {
+                tree.remove(key);
+                format!("remove {}", key)
+            }
+        };
+        let steps: Vec<TreeEvent<u32>> = std::mem::take(tree.observer_mut());
+        if let Err(msg) = tree.check_rules() {
+            return Err(format!("RB violation after {}: {}", title, msg));
+        }
+
+        // nodes touched by recolors and rotations
+        let mut touched: Vec<u32> = Vec::new();
+        for step in &steps {
+            if let TreeEvent::NodeInserted { key }
+            | TreeEvent::Recolored { key, .. }
+            | TreeEvent::Rotated { pivot: key, .. } = step
+            {
+                if !touched.contains(key) {
+                    touched.push(*key);
+                }
+            }
+        }
+        let states: Vec<String> = steps
+            .iter()
+            .filter_map(|step| match step {
+                TreeEvent::InsertCase { key, state } => Some(format!("{:?} at {}", state, key)),
+                _ => None,
+            })
+            .collect();
+        let caption = if states.is_empty() {
+            title.clone()
+        } else {
+            format!("{}: {}", title, states.join(", "))
+        };
+
+        println!("{}", format!("frame {}: {}", frame, caption).bold());
+        for step in &steps {
+            println!("  {}", describe(step));
+        }
+        if steps.is_empty() {
+            println!("  no change");
+        }
+        println!("{}\n", tree.pretty().hide_values().highlight(&touched));
+
+        match &export {
+            Some(Export::Dot(dir)) => {
+                let options = DotOptions::new()
+                    .hide_values()
+                    .nil_leaves()
+                    .highlight(touched)
+                    .label(caption);
+                write_frame(dir, frame, "dot", tree.to_dot_with(&options))?;
+            }
+            Some(Export::Svg(dir)) => {
+                let options = SvgOptions::new().highlight(touched).title(caption);
+                write_frame(dir, frame, "svg", tree.to_svg_with(&options))?;
+            }
+            None => (),
+        }
+    }
+    Ok(())
+}
+
+fn describe(step: &TreeEvent<u32>) -> String {
+    match step {
+        TreeEvent::NodeInserted { key } => format!("inserted {}", key),
+        TreeEvent::NodeRemoved { key } => format!("removed {}", key),
+        TreeEvent::InsertCase { key, state } => format!("{:?} handled at {}", state, key),
+        TreeEvent::Recolored { key, color } => format!(
+            "recolored {} {}",
+            key,
+            match color {
+                Color::Red => "red",
+                Color::Black => "black",
+            }
+        ),
+        TreeEvent::Rotated { dir, pivot } => format!(
+            "{} rotation, {} moves up",
+            match dir {
+                RotDir::Left => "left",
+                RotDir::Right => "right",
+            },
+            pivot
+        ),
+    }
+}
+
+fn write_frame(
+    dir: &std::path::Path,
+    frame: usize,
+    ext: &str,
+    content: String,
+) -> Result<(), String> {
+    let path = dir.join(format!("frame_{:03}.{}", frame, ext));
+    std::fs::write(&path, content)
+        .map_err(|err| format!("failed to write {}: {}", path.display(), err))
+}
+
+fn zipf(args: &[String]) -> Result<(), String> {
+    fn arg<T: std::str::FromStr>(args: &[String], idx: usize, default: T) -> Result<T, String> {
+        match args.get(idx) {
+            Some(arg) => arg
+                .parse()
+                .map_err(|_| format!("invalid zipf argument: {}", arg)),
+            None => Ok(default),
+        }
+    }
+    if args.len() > 3 {
+        return Err(format!("unexpected zipf argument: {}", args[3]));
+    }
+    let count: u32 = arg(args, 0, 100_000)?;
+    let lookups: usize = arg(args, 1, 1_000_000)?;
+    let skew: f64 = arg(args, 2, 2.0)?;
+    if count == 0 {
+        return Err("zipf needs at least one key".to_string());
+    }
+    let mut rng = rand::thread_rng();
+
+    // the rank r is drawn with a weight of 1 / r^skew, the ranks are spread over the
+    // key space so the hot keys are not the ones inserted first
+    let mut keys: Vec<u32> = (0..count).collect();
+    keys.shuffle(&mut rng);
+    let mut cdf = Vec::with_capacity(keys.len());
+    let mut total = 0.0;
+    for rank in 1..=keys.len() {
+        total += 1.0 / (rank as f64).powf(skew);
+        cdf.push(total);
+    }
+    let zipf: Vec<u32> = (0..lookups)
+        .map(|_| {
+            let sample = rng.gen_range(0.0..total);
+            keys[cdf.partition_point(|sum| *sum < sample).min(keys.len() - 1)]
+        })
+        .collect();
+    let uniform: Vec<u32> = (0..lookups).map(|_| rng.gen_range(0..count)).collect();
+
+    let mut splay_tree = SplayTree::new();
+    let mut rb_tree = RBTree::new();
+    let mut avl_tree = AVLTree::new();
+    for key in &keys {
+        splay_tree.insert(*key, *key);
+        rb_tree.insert(*key, *key);
+        avl_tree.insert(*key, *key);
+    }
+
+    // nanoseconds per lookup
+    fn time(workload: &[u32], mut find: impl FnMut(&u32) -> Option<u32>) -> f64 {
+        let start = Instant::now();
+        for key in workload {
+            black_box(find(key));
+        }
+        start.elapsed().as_nanos() as f64 / workload.len().max(1) as f64
+    }
+    println!(
+        "{} keys, {} lookups, skew {}\n{:<10} {:>12} {:>12}",
+        count, lookups, skew, "tree", "zipf ns", "uniform ns"
+    );
+    let rows = [
+        (
+            "SplayTree",
+            time(&zipf, |key| splay_tree.find(key).copied()),
+            time(&uniform, |key| splay_tree.find(key).copied()),
+        ),
+        (
+            "RBTree",
+            time(&zipf, |key| rb_tree.find(key).copied()),
+            time(&uniform, |key| rb_tree.find(key).copied()),
+        ),
+        (
+            "AVLTree",
+            time(&zipf, |key| avl_tree.find(key).copied()),
+            time(&uniform, |key| avl_tree.find(key).copied()),
+        ),
+    ];
+    for (name, zipf, uniform) in rows {
+        println!("{:<10} {:>12.1} {:>12.1}", name, zipf, uniform);
+    }
+
+    // the nodes visited per lookup, where the splay tree gains on skewed access,
+    // the splay tree is measured before each lookup as splaying moves the key
+    fn depth(workload: &[u32], lookup: impl FnMut(&u32) -> usize) -> f64 {
+        let total: usize = workload.iter().map(lookup).sum();
+        total as f64 / workload.len().max(1) as f64
+    }
+    let mut splay_depth = |workload: &[u32]| {
+        depth(workload, |key| {
+            let depth = splay_tree.search_depth(key);
+            splay_tree.find(key);
+            depth
+        })
+    };
+    let depths = [
+        (splay_depth(&zipf), splay_depth(&uniform)),
+        (
+            depth(&zipf, |key| rb_tree.search_depth(key)),
+            depth(&uniform, |key| rb_tree.search_depth(key)),
+        ),
+        (
+            depth(&zipf, |key| avl_tree.search_depth(key)),
+            depth(&uniform, |key| avl_tree.search_depth(key)),
+        ),
+    ];
+    println!(
+        "\n{:<10} {:>12} {:>12}",
+        "tree", "zipf depth", "uniform depth"
+    );
+    for (name, (zipf, uniform)) in ["SplayTree", "RBTree", "AVLTree"].iter().zip(depths) {
+        println!("{:<10} {:>12.2} {:>12.2}", name, zipf, uniform);
+    }
+    Ok(())
 }
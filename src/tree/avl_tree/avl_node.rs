@@ -0,0 +1,321 @@
+use super::SubNode;
+use crate::prelude::*;
+use crate::tree::rb_tree::Color;
+use crate::tree::TreeNode;
+use alloc::sync::Arc;
+use core::cmp::Ordering;
+use core::fmt::Debug;
+
+#[derive(Clone)]
+pub struct AVLNode<K: PartialOrd, V> {
+    pub key: K,
+    pub value: V,
+    /// the number of nodes on the longest path down to a leaf, 1 for a leaf
+    pub height: u32,
+    pub smaller: SubNode<K, V>,
+    pub larger: SubNode<K, V>,
+}
+
+impl<K: PartialOrd, V> AVLNode<K, V> {
+    pub fn new(key: K, value: V) -> AVLNode<K, V> {
+        AVLNode {
+            key,
+            value,
+            height: 1,
+            smaller: None,
+            larger: None,
+        }
+    }
+
+    pub(crate) fn height(link: &SubNode<K, V>) -> u32 {
+        link.as_ref().map_or(0, |node| node.height)
+    }
+
+    /// the height of the larger subtree minus the height of the smaller one
+    pub(crate) fn balance(&self) -> i64 {
+        Self::height(&self.larger) as i64 - Self::height(&self.smaller) as i64
+    }
+
+    fn update_height(&mut self) {
+        self.height = Self::height(&self.smaller).max(Self::height(&self.larger)) + 1;
+    }
+}
+
+/// all modifications go through Arc::make_mut, nodes shared with a cloned tree
+/// are copied before they are changed
+impl<K: PartialOrd + Clone, V: Clone> AVLNode<K, V> {
+    /// insert into the subtree hanging off link, rebalancing on the way back up,
+    /// visited counts the nodes key is compared with
+    pub(crate) fn insert(
+        link: &mut SubNode<K, V>,
+        key: K,
+        value: V,
+        visited: &mut u64,
+    ) -> Option<V> {
+        let node = match link {
+            Some(node) => Arc::make_mut(node),
+            None => {
+                *link = Some(Arc::new(AVLNode::new(key, value)));
+                return None;
+            }
+        };
+        *visited += 1;
+        let replaced = if key < node.key {
+            Self::insert(&mut node.smaller, key, value, visited)
+        } else if key > node.key {
+            Self::insert(&mut node.larger, key, value, visited)
+        } else {
+            // the shape does not change
+            return Some(core::mem::replace(&mut node.value, value));
+        };
+        if replaced.is_none() {
+            Self::rebalance_link(link);
+        }
+        replaced
+    }
+
+    /// remove key from the subtree hanging off link, rebalancing on the way back up,
+    /// the nodes on the path are copied if shared, so look for key first
+    pub(crate) fn remove(link: &mut SubNode<K, V>, key: &K) -> Option<V> {
+        let node = Arc::make_mut(link.as_mut()?);
+        let res = if *key < node.key {
+            Self::remove(&mut node.smaller, key)?
+        } else if *key > node.key {
+            Self::remove(&mut node.larger, key)?
+        } else if node.smaller.is_some() && node.larger.is_some() {
+            // node has two siblings - replace node with next larger, delete next larger
+            let (key, value) = Self::remove_smallest(&mut node.larger);
+            node.key = key;
+            core::mem::replace(&mut node.value, value)
+        } else {
+            return Some(Self::unlink(link).1);
+        };
+        Self::rebalance_link(link);
+        Some(res)
+    }
+
+    /// remove the smallest node from the subtree hanging off link
+    fn remove_smallest(link: &mut SubNode<K, V>) -> (K, V) {
+        let node = Arc::make_mut(link.as_mut().expect("unexpected empty link"));
+        if node.smaller.is_some() {
+            let res = Self::remove_smallest(&mut node.smaller);
+            Self::rebalance_link(link);
+            res
+        } else {
+            Self::unlink(link)
+        }
+    }
+
+    /// unlink a node that has at most one sibling, the sibling takes its place
+    fn unlink(link: &mut SubNode<K, V>) -> (K, V) {
+        let mut node = Arc::unwrap_or_clone(link.take().expect("unexpected empty link"));
+        // a single sibling is a leaf, it is balanced already
+        *link = node.smaller.take().or_else(|| node.larger.take());
+        (node.key, node.value)
+    }
+
+    fn rebalance_link(link: &mut SubNode<K, V>) {
+        let node = link.take().expect("unexpected empty link");
+        *link = Some(node.rebalance());
+    }
+
+    /// Updates the height and rotates if the subtrees differ by two in height.
+    /// A subtree leaning the other way is rotated first, as a double rotation.
+    fn rebalance(mut self: Arc<Self>) -> Arc<Self> {
+        let node = Arc::make_mut(&mut self);
+        node.update_height();
+        let balance = node.balance();
+        let res = if balance > 1 {
+            if node
+                .larger
+                .as_ref()
+                .is_some_and(|larger| larger.balance() < 0)
+            {
+                let larger = node.larger.take().expect("unexpected empty link");
+                node.larger = Some(Self::rotated(larger.right_rotate()));
+            }
+            self.left_rotate()
+        } else if balance < -1 {
+            if node
+                .smaller
+                .as_ref()
+                .is_some_and(|smaller| smaller.balance() > 0)
+            {
+                let smaller = node.smaller.take().expect("unexpected empty link");
+                node.smaller = Some(Self::rotated(smaller.left_rotate()));
+            }
+            self.right_rotate()
+        } else {
+            return self;
+        };
+        Self::rotated(res)
+    }
+
+    fn rotated(res: Result<Arc<Self>, (Arc<Self>, &'static str)>) -> Arc<Self> {
+        res.unwrap_or_else(|(_, err)| panic!("failed to rotate: {}", err))
+    }
+
+    /// rotate left - larger side moves up
+    /// rotate anti clockwise (in this notation)
+    ///    (self)            (l)
+    ///     ├─<(l)            ├─<(l,l)
+    ///     │   ├─<(l,l)      └─>(self)
+    ///     │   └─>(l,s)          ├─<(l,s)
+    ///     └─>(s)                └─>(s)
+    pub(crate) fn left_rotate(
+        mut self: Arc<Self>,
+    ) -> core::result::Result<Arc<Self>, (Arc<Self>, &'static str)> {
+        if self.larger.is_some() {
+            let node = Arc::make_mut(&mut self);
+            let mut new_root = node.larger.take().expect("unexpected empty link");
+            let new_root_node = Arc::make_mut(&mut new_root);
+            node.larger = new_root_node.smaller.take();
+            node.update_height();
+            new_root_node.smaller = Some(self);
+            new_root_node.update_height();
+            Ok(new_root)
+        } else {
+            Err((self, "cannot left rotate - larger subnode is nil"))
+        }
+    }
+
+    /// rotate right - smaller side moves up
+    /// rotate clockwise (in this notation)
+    ///    (self)           (s)
+    ///     ├─<(l)           ├─<(self)
+    ///     └─>(s)           │   ├─<(l)
+    ///         ├─<(s,l)     │   └─>(s,l)
+    ///         └─>(s,s)     └─>(s,s)
+    pub(crate) fn right_rotate(
+        mut self: Arc<Self>,
+    ) -> core::result::Result<Arc<Self>, (Arc<Self>, &'static str)> {
+        if self.smaller.is_some() {
+            let node = Arc::make_mut(&mut self);
+            let mut new_root = node.smaller.take().expect("unexpected empty link");
+            let new_root_node = Arc::make_mut(&mut new_root);
+            node.smaller = new_root_node.larger.take();
+            node.update_height();
+            new_root_node.larger = Some(self);
+            new_root_node.update_height();
+            Ok(new_root)
+        } else {
+            Err((self, "cannot right rotate - smaller subnode is nil"))
+        }
+    }
+}
+
+impl<K: PartialOrd + Debug, V> AVLNode<K, V> {
+    /// Checks the order of the keys, the stored heights and that the subtrees of every
+    /// node differ by at most one in height. Returns the height of the subtree.
+    pub fn check_rules(&self, lower: Option<&K>, upper: Option<&K>) -> Result<u32, String> {
+        let below = |smaller: &K, larger: &K| smaller.partial_cmp(larger) == Some(Ordering::Less);
+        if lower.is_some_and(|lower| !below(lower, &self.key))
+            || upper.is_some_and(|upper| !below(&self.key, upper))
+        {
+            return Err(format!(
+                "AVL violation: key {:?} out of order between {:?} and {:?}",
+                self.key, lower, upper
+            ));
+        }
+        let smaller = match &self.smaller {
+            Some(node) => node.check_rules(lower, Some(&self.key))?,
+            None => 0,
+        };
+        let larger = match &self.larger {
+            Some(node) => node.check_rules(Some(&self.key), upper)?,
+            None => 0,
+        };
+        if smaller.abs_diff(larger) > 1 {
+            return Err(format!(
+                "AVL violation: unbalanced subtrees @{:?} {}!={}",
+                self.key, smaller, larger
+            ));
+        }
+        let height = smaller.max(larger) + 1;
+        if height != self.height {
+            return Err(format!(
+                "AVL violation: stored height {} @{:?}, expected {}",
+                self.height, self.key, height
+            ));
+        }
+        Ok(height)
+    }
+}
+
+impl<K: PartialOrd, V> TreeNode<K, V> for AVLNode<K, V> {
+    fn key(&self) -> &K {
+        &self.key
+    }
+
+    fn value(&self) -> &V {
+        &self.value
+    }
+
+    fn smaller(&self) -> Option<&Self> {
+        self.smaller.as_deref()
+    }
+
+    fn larger(&self) -> Option<&Self> {
+        self.larger.as_deref()
+    }
+
+    fn color(&self) -> Option<Color> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn leaf(key: u32) -> SubNode<u32, ()> {
+        Some(Arc::new(AVLNode::new(key, ())))
+    }
+
+    fn node(key: u32, smaller: SubNode<u32, ()>, larger: SubNode<u32, ()>) -> SubNode<u32, ()> {
+        let mut node = AVLNode {
+            key,
+            value: (),
+            height: 0,
+            smaller,
+            larger,
+        };
+        node.update_height();
+        Some(Arc::new(node))
+    }
+
+    #[test]
+    fn avl_test_rotate() {
+        // 2 on top of 1 and 4 with the children 3 and 5
+        let tree = node(2, leaf(1), node(4, leaf(3), leaf(5))).expect("unexpected empty tree");
+        let rotated = tree
+            .clone()
+            .left_rotate()
+            .unwrap_or_else(|(_, err)| panic!("{}", err));
+        assert_eq!(rotated.key, 4);
+        assert_eq!(rotated.height, 3);
+        let smaller = rotated.smaller.as_ref().expect("missing smaller node");
+        assert_eq!((smaller.key, smaller.height), (2, 2));
+        assert_eq!(smaller.larger.as_ref().map(|node| node.key), Some(3));
+        // the original tree is untouched
+        assert_eq!(tree.key, 2);
+        assert_eq!(tree.check_rules(None, None), Ok(3));
+
+        let back = rotated
+            .right_rotate()
+            .unwrap_or_else(|(_, err)| panic!("{}", err));
+        assert_eq!(back.key, 2);
+        assert_eq!(back.check_rules(None, None), Ok(3));
+        assert!(Arc::new(AVLNode::new(1, ())).left_rotate().is_err());
+    }
+
+    #[test]
+    fn avl_test_double_rotation() {
+        // 3 leans smaller, its smaller subtree leans larger
+        let mut link = node(3, node(1, None, leaf(2)), None);
+        AVLNode::rebalance_link(&mut link);
+        let root = link.expect("unexpected empty tree");
+        assert_eq!(root.key, 2);
+        assert_eq!(root.check_rules(None, None), Ok(2));
+    }
+}
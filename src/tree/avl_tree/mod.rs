@@ -0,0 +1,353 @@
+use crate::prelude::*;
+use crate::tree::dot::{self, DotOptions};
+#[cfg(feature = "metrics")]
+use crate::tree::metrics::Stats;
+use crate::tree::metrics::{self, Metrics, ShapeReport};
+use alloc::sync::Arc;
+use avl_node::AVLNode;
+use core::fmt::{Debug, Formatter};
+use core::ops::Index;
+
+type SubNode<K, V> = Option<Arc<AVLNode<K, V>>>;
+
+mod avl_node;
+
+/// A height balanced binary search tree, the subtrees of every node differ by at most
+/// one in height. Lookups visit at most about 1.44 log2(n) nodes, against 2 log2(n) in
+/// an RBTree, inserts and removes rotate more often.
+pub struct AVLTree<K: PartialOrd, V> {
+    pub(crate) root: SubNode<K, V>,
+    metrics: Metrics,
+}
+
+impl<K: PartialOrd, V> Default for AVLTree<K, V> {
+    fn default() -> Self {
+        AVLTree::new()
+    }
+}
+
+impl<K: PartialOrd, V> AVLTree<K, V> {
+    pub fn new() -> AVLTree<K, V> {
+        AVLTree {
+            root: None,
+            metrics: Metrics::default(),
+        }
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.find(key).is_some()
+    }
+
+    pub fn find(&self, key: &K) -> Option<&V> {
+        let mut depth = 0;
+        let mut node = self.root.as_deref();
+        while let Some(curr) = node {
+            depth += 1;
+            if *key < curr.key {
+                node = curr.smaller.as_deref();
+            } else if *key > curr.key {
+                node = curr.larger.as_deref();
+            } else {
+                break;
+            }
+        }
+        self.metrics.record_search(depth);
+        node.map(|curr| &curr.value)
+    }
+
+    pub fn smallest(&self) -> Option<(&K, &V)> {
+        let mut curr = self.root.as_deref()?;
+        while let Some(subnode) = &curr.smaller {
+            curr = subnode;
+        }
+        Some((&curr.key, &curr.value))
+    }
+
+    /// the entry with the largest key below key
+    pub fn smaller(&self, key: &K) -> Option<(&K, &V)> {
+        let mut candidate = None;
+        let mut depth = 0;
+        let mut node = self.root.as_deref();
+        while let Some(curr) = node {
+            depth += 1;
+            if curr.key < *key {
+                candidate = Some(curr);
+                node = curr.larger.as_deref();
+            } else {
+                node = curr.smaller.as_deref();
+            }
+        }
+        self.metrics.record_comparisons(depth);
+        candidate.map(|node| (&node.key, &node.value))
+    }
+
+    pub fn largest(&self) -> Option<(&K, &V)> {
+        let mut curr = self.root.as_deref()?;
+        while let Some(subnode) = &curr.larger {
+            curr = subnode;
+        }
+        Some((&curr.key, &curr.value))
+    }
+
+    /// the entry with the smallest key above key
+    pub fn larger(&self, key: &K) -> Option<(&K, &V)> {
+        let mut candidate = None;
+        let mut depth = 0;
+        let mut node = self.root.as_deref();
+        while let Some(curr) = node {
+            depth += 1;
+            if curr.key > *key {
+                candidate = Some(curr);
+                node = curr.smaller.as_deref();
+            } else {
+                node = curr.larger.as_deref();
+            }
+        }
+        self.metrics.record_comparisons(depth);
+        candidate.map(|node| (&node.key, &node.value))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// the number of nodes on the longest path from the root, 0 for an empty tree
+    pub fn height(&self) -> usize {
+        AVLNode::height(&self.root) as usize
+    }
+
+    /// the number of nodes a search for key visits, the search is not counted
+    pub fn search_depth(&self, key: &K) -> usize {
+        metrics::search_depth::<K, V, _>(self.root.as_deref(), key)
+    }
+
+    /// height, node count and nodes per level, the black height is always None
+    pub fn shape_report(&self) -> ShapeReport {
+        metrics::shape_report::<K, V, _>(self.root.as_deref())
+    }
+
+    /// counters collected since the tree was created or reset_stats was called,
+    /// rotations are not counted
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> Stats {
+        self.metrics.stats()
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn reset_stats(&mut self) {
+        self.metrics.reset()
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.push_smaller(self.root.as_deref());
+        iter
+    }
+}
+
+impl<K: PartialOrd + Debug, V> AVLTree<K, V> {
+    /// checks the order, the heights and the balance of all nodes, returns the height
+    pub fn check_rules(&self) -> core::result::Result<usize, String> {
+        match &self.root {
+            Some(root) => Ok(root.check_rules(None, None)? as usize),
+            None => Ok(0),
+        }
+    }
+}
+
+impl<K: PartialOrd + Debug, V: Debug> AVLTree<K, V> {
+    /// the tree as a Graphviz digraph, render it with `dot -Tsvg`
+    pub fn to_dot(&self) -> String {
+        self.to_dot_with(&DotOptions::default())
+    }
+
+    pub fn to_dot_with(&self, options: &DotOptions<K>) -> String {
+        dot::to_dot::<K, V, _>("AVLTree", self.root.as_deref(), options)
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> AVLTree<K, V> {
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let mut depth = 0;
+        let res = AVLNode::insert(&mut self.root, key, value, &mut depth);
+        self.metrics.record_search(depth);
+        res
+    }
+
+    pub fn find_mut(&mut self, key: &K) -> Option<&mut V> {
+        // search first, a miss must not copy the path shared with a clone
+        self.find(key)?;
+        let mut curr = Arc::make_mut(self.root.as_mut()?);
+        loop {
+            let link = if *key < curr.key {
+                &mut curr.smaller
+            } else if *key > curr.key {
+                &mut curr.larger
+            } else {
+                return Some(&mut curr.value);
+            };
+            curr = Arc::make_mut(link.as_mut().expect("unexpected missing key"));
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        // search first, a miss must not copy the path shared with a clone
+        self.find(key)?;
+        AVLNode::remove(&mut self.root, key)
+    }
+}
+
+impl<K: PartialOrd, V> Clone for AVLTree<K, V> {
+    /// the clone shares all nodes with self, nodes are copied when either tree modifies them
+    fn clone(&self) -> Self {
+        AVLTree {
+            root: self.root.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+/// ascending iterator over the entries of an AVLTree
+pub struct Iter<'a, K: PartialOrd, V> {
+    // nodes still to be visited, the next one on top
+    stack: Vec<&'a AVLNode<K, V>>,
+}
+
+impl<'a, K: PartialOrd, V> Iter<'a, K, V> {
+    fn push_smaller(&mut self, mut node: Option<&'a AVLNode<K, V>>) {
+        while let Some(curr) = node {
+            self.stack.push(curr);
+            node = curr.smaller.as_deref();
+        }
+    }
+}
+
+impl<'a, K: PartialOrd, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_smaller(node.larger.as_deref());
+        Some((&node.key, &node.value))
+    }
+}
+
+impl<'a, K: PartialOrd, V> IntoIterator for &'a AVLTree<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: PartialOrd, V: PartialEq> PartialEq for AVLTree<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl<K: PartialOrd, V> Index<&K> for AVLTree<K, V> {
+    type Output = V;
+
+    fn index(&self, key: &K) -> &V {
+        self.find(key).expect("key not found in AVLTree")
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> Extend<(K, V)> for AVLTree<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> FromIterator<(K, V)> for AVLTree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tree = AVLTree::new();
+        tree.extend(iter);
+        tree
+    }
+}
+
+impl<K: PartialOrd + Debug, V: Debug> Debug for AVLTree<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tree::test_map::{self, test_map_impl};
+    use rand::seq::SliceRandom;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn check(tree: &AVLTree<u32, u32>) -> usize {
+        tree.check_rules()
+            .unwrap_or_else(|err| panic!("{}\n{:?}", err, tree))
+    }
+
+    #[test]
+    fn avl_test_insert_remove() {
+        let mut tree = AVLTree::new();
+        // ascending keys degenerate an unbalanced tree, here they give a perfect one
+        for key in 0..1023 {
+            assert_eq!(tree.insert(key, key), None);
+            check(&tree);
+        }
+        assert_eq!(tree.height(), 10);
+        assert_eq!(tree.insert(5, 50), Some(5));
+        *tree.find_mut(&5).expect("key not found") = 5;
+        assert!(tree
+            .iter()
+            .map(|(key, value)| (*key, *value))
+            .eq((0..1023).map(|key| (key, key))));
+
+        let mut keys: Vec<u32> = (0..1023).collect();
+        keys.shuffle(&mut StdRng::seed_from_u64(43));
+        let snapshot = tree.clone();
+        for (idx, key) in keys.iter().enumerate() {
+            assert_eq!(tree.remove(key), Some(*key));
+            assert_eq!(tree.remove(key), None);
+            let height = check(&tree);
+            // the AVL bound, 1.44 log2(n + 2)
+            let len = (keys.len() - idx - 1) as f64;
+            assert!(height as f64 <= 1.45 * (len + 2.0).log2());
+        }
+        assert!(tree.is_empty());
+        // the clone is untouched
+        assert_eq!(snapshot.check_rules(), Ok(10));
+        assert_eq!(snapshot.iter().count(), 1023);
+    }
+
+    test_map_impl!(AVLTree<u32, u32>, check);
+
+    fn node_ptrs<K: PartialOrd, V>(link: &SubNode<K, V>, ptrs: &mut Vec<usize>) {
+        if let Some(node) = link {
+            ptrs.push(Arc::as_ptr(node) as usize);
+            node_ptrs(&node.smaller, ptrs);
+            node_ptrs(&node.larger, ptrs);
+        }
+    }
+
+    #[test]
+    fn avl_test_clone() {
+        test_map::check_clone(|tree: &AVLTree<u32, u32>| {
+            let mut ptrs = Vec::new();
+            node_ptrs(&tree.root, &mut ptrs);
+            ptrs
+        });
+    }
+
+    #[test]
+    fn avl_test_against_rbtree() {
+        let (tree, rb_tree, _) =
+            test_map::check_against_rbtree::<AVLTree<u32, u32>>(11, 5000, 400, 0.55);
+        assert!(rb_tree.iter().all(|(key, _)| tree.contains(key)));
+        let report = tree.shape_report();
+        assert_eq!(report.height, tree.height());
+        assert_eq!(report.black_height, None);
+    }
+}
@@ -0,0 +1,484 @@
+//! A B-tree, every node holds up to CAP entries in a contiguous vector, so a search
+//! touches a few nodes only instead of one node per level of a binary tree.
+
+use crate::prelude::*;
+use core::fmt::{Debug, Formatter};
+use core::ops::{Bound, Index, RangeBounds};
+use multiway_node::{Insert, MultiwayNode};
+
+mod multiway_node;
+
+type Node<K, V, const CAP: usize> = MultiwayNode<K, V, CAP>;
+
+/// A B-tree with up to CAP entries per node, CAP must be at least 3.
+/// All leaves are at the same depth, every node but the root is at least half full.
+#[derive(Clone)]
+pub struct MultiwayTree<K: PartialOrd, V, const CAP: usize = 11> {
+    root: Option<Box<Node<K, V, CAP>>>,
+    len: usize,
+}
+
+impl<K: PartialOrd, V, const CAP: usize> Default for MultiwayTree<K, V, CAP> {
+    fn default() -> Self {
+        MultiwayTree::new()
+    }
+}
+
+impl<K: PartialOrd, V, const CAP: usize> MultiwayTree<K, V, CAP> {
+    const VALID_CAP: () = assert!(CAP >= 3, "a MultiwayTree needs a CAP of at least 3");
+
+    pub fn new() -> MultiwayTree<K, V, CAP> {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID_CAP;
+        MultiwayTree { root: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// the number of levels, 0 for an empty tree
+    pub fn height(&self) -> usize {
+        let mut height = 0;
+        let mut node = self.root.as_deref();
+        while let Some(curr) = node {
+            height += 1;
+            node = curr.children.first().map(|child| child.as_ref());
+        }
+        height
+    }
+
+    pub fn traverse_asc(&self, func: &mut dyn FnMut(&K, &V)) {
+        for (key, value) in self.iter() {
+            func(key, value);
+        }
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.find(key).is_some()
+    }
+
+    pub fn find(&self, key: &K) -> Option<&V> {
+        let mut node = self.root.as_deref()?;
+        loop {
+            let (pos, found) = node.search(key);
+            if found {
+                return Some(&node.entries[pos].1);
+            }
+            node = node.children.get(pos)?;
+        }
+    }
+
+    pub fn find_mut(&mut self, key: &K) -> Option<&mut V> {
+        let mut node = self.root.as_deref_mut()?;
+        loop {
+            let (pos, found) = node.search(key);
+            if found {
+                return Some(&mut node.entries[pos].1);
+            }
+            node = node.children.get_mut(pos)?;
+        }
+    }
+
+    pub fn smallest(&self) -> Option<(&K, &V)> {
+        let mut node = self.root.as_deref()?;
+        while let Some(child) = node.children.first() {
+            node = child;
+        }
+        node.entries.first().map(|(key, value)| (key, value))
+    }
+
+    /// the entry with the largest key below key
+    pub fn smaller(&self, key: &K) -> Option<(&K, &V)> {
+        let mut candidate = None;
+        let mut node = self.root.as_deref();
+        while let Some(curr) = node {
+            let pos = curr.entries.partition_point(|(curr, _)| *curr < *key);
+            if pos > 0 {
+                candidate = Some(&curr.entries[pos - 1]);
+            }
+            node = curr.children.get(pos).map(|child| child.as_ref());
+        }
+        candidate.map(|(key, value)| (key, value))
+    }
+
+    pub fn largest(&self) -> Option<(&K, &V)> {
+        let mut node = self.root.as_deref()?;
+        while let Some(child) = node.children.last() {
+            node = child;
+        }
+        node.entries.last().map(|(key, value)| (key, value))
+    }
+
+    /// the entry with the smallest key above key
+    pub fn larger(&self, key: &K) -> Option<(&K, &V)> {
+        let mut candidate = None;
+        let mut node = self.root.as_deref();
+        while let Some(curr) = node {
+            let pos = curr.entries.partition_point(|(curr, _)| *curr <= *key);
+            if let Some(entry) = curr.entries.get(pos) {
+                candidate = Some(entry);
+            }
+            node = curr.children.get(pos).map(|child| child.as_ref());
+        }
+        candidate.map(|(key, value)| (key, value))
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let root = self.root.get_or_insert_with(|| Box::new(Node::leaf()));
+        match root.insert(key, value) {
+            Insert::Replaced(old) => return Some(old),
+            Insert::Added => (),
+            Insert::Split(median, larger) => {
+                // the tree grows at the root
+                let smaller = core::mem::replace(root, Box::new(Node::leaf()));
+                root.entries.push(median);
+                root.children.push(smaller);
+                root.children.push(larger);
+            }
+        }
+        self.len += 1;
+        None
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let root = self.root.as_mut()?;
+        let removed = root.remove(key)?;
+        if root.entries.is_empty() {
+            // the tree shrinks at the root
+            self.root = root.children.pop();
+        }
+        self.len -= 1;
+        Some(removed)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V, CAP> {
+        let mut walk = Walk { stack: Vec::new() };
+        walk.push_smallest(self.root.as_deref());
+        Iter {
+            walk,
+            remaining: self.len,
+        }
+    }
+
+    /// ascending iterator over the entries with keys in range
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Range<'_, K, V, CAP, R> {
+        let mut walk = Walk { stack: Vec::new() };
+        // push the path to the first key in range, every node with its first entry in range
+        let mut node = self.root.as_deref();
+        while let Some(curr) = node {
+            let pos = curr
+                .entries
+                .partition_point(|(key, _)| match range.start_bound() {
+                    Bound::Included(start) => *key < *start,
+                    Bound::Excluded(start) => *key <= *start,
+                    Bound::Unbounded => false,
+                });
+            walk.stack.push((curr, pos));
+            node = curr.children.get(pos).map(|child| child.as_ref());
+        }
+        Range { walk, range }
+    }
+}
+
+impl<K: PartialOrd + Debug, V, const CAP: usize> MultiwayTree<K, V, CAP> {
+    /// Checks the B-tree rules, returns the height of the tree
+    pub fn check_rules(&self) -> Result<usize, String> {
+        let Some(root) = &self.root else {
+            return Ok(0);
+        };
+        let height = root.check_rules(true, None, None)?;
+        let count = self.iter().count();
+        if count != self.len {
+            return Err(format!("{} entries, expected {}", count, self.len));
+        }
+        Ok(height)
+    }
+}
+
+/// the in-order walk behind Iter and Range
+struct Walk<'a, K, V, const CAP: usize> {
+    // nodes on the path to the next entry and the index of their next entry, the next one on top
+    stack: Vec<(&'a Node<K, V, CAP>, usize)>,
+}
+
+impl<'a, K, V, const CAP: usize> Walk<'a, K, V, CAP> {
+    fn push_smallest(&mut self, mut node: Option<&'a Node<K, V, CAP>>) {
+        while let Some(curr) = node {
+            self.stack.push((curr, 0));
+            node = curr.children.first().map(|child| child.as_ref());
+        }
+    }
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        loop {
+            let (node, idx) = self.stack.last_mut()?;
+            let node: &'a Node<K, V, CAP> = node;
+            if let Some((key, value)) = node.entries.get(*idx) {
+                *idx += 1;
+                let next = *idx;
+                // the subtree between this entry and the next one comes next
+                self.push_smallest(node.children.get(next).map(|child| child.as_ref()));
+                return Some((key, value));
+            }
+            self.stack.pop();
+        }
+    }
+}
+
+/// ascending iterator over the entries of a MultiwayTree
+pub struct Iter<'a, K, V, const CAP: usize> {
+    walk: Walk<'a, K, V, CAP>,
+    remaining: usize,
+}
+
+impl<'a, K, V, const CAP: usize> Iterator for Iter<'a, K, V, CAP> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let res = self.walk.next()?;
+        self.remaining -= 1;
+        Some(res)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V, const CAP: usize> ExactSizeIterator for Iter<'_, K, V, CAP> {}
+
+/// ascending iterator over the entries of a MultiwayTree within a range of keys
+pub struct Range<'a, K, V, const CAP: usize, R> {
+    walk: Walk<'a, K, V, CAP>,
+    range: R,
+}
+
+impl<'a, K: PartialOrd, V, const CAP: usize, R: RangeBounds<K>> Iterator
+    for Range<'a, K, V, CAP, R>
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = self.walk.next()?;
+        let below_end = match self.range.end_bound() {
+            Bound::Included(end) => *key <= *end,
+            Bound::Excluded(end) => *key < *end,
+            Bound::Unbounded => true,
+        };
+        if below_end {
+            Some((key, value))
+        } else {
+            self.walk.stack.clear();
+            None
+        }
+    }
+}
+
+type IntoIterFrame<K, V, const CAP: usize> = (
+    alloc::vec::IntoIter<(K, V)>,
+    alloc::vec::IntoIter<Box<Node<K, V, CAP>>>,
+);
+
+/// moving iterator over the entries of a MultiwayTree in ascending order
+pub struct IntoIter<K, V, const CAP: usize> {
+    // the remaining entries and children of the nodes on the path to the next entry
+    stack: Vec<IntoIterFrame<K, V, CAP>>,
+    remaining: usize,
+}
+
+impl<K, V, const CAP: usize> IntoIter<K, V, CAP> {
+    fn push_smallest(&mut self, mut node: Option<Box<Node<K, V, CAP>>>) {
+        while let Some(curr) = node {
+            let curr = *curr;
+            let mut children = curr.children.into_iter();
+            node = children.next();
+            self.stack.push((curr.entries.into_iter(), children));
+        }
+    }
+}
+
+impl<K, V, const CAP: usize> Iterator for IntoIter<K, V, CAP> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (entries, children) = self.stack.last_mut()?;
+            if let Some(entry) = entries.next() {
+                let next = children.next();
+                self.push_smallest(next);
+                self.remaining -= 1;
+                return Some(entry);
+            }
+            self.stack.pop();
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V, const CAP: usize> ExactSizeIterator for IntoIter<K, V, CAP> {}
+
+impl<K: PartialOrd, V, const CAP: usize> IntoIterator for MultiwayTree<K, V, CAP> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V, CAP>;
+
+    fn into_iter(mut self) -> IntoIter<K, V, CAP> {
+        let mut iter = IntoIter {
+            stack: Vec::new(),
+            remaining: self.len,
+        };
+        iter.push_smallest(self.root.take());
+        iter
+    }
+}
+
+impl<'a, K: PartialOrd, V, const CAP: usize> IntoIterator for &'a MultiwayTree<K, V, CAP> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V, CAP>;
+
+    fn into_iter(self) -> Iter<'a, K, V, CAP> {
+        self.iter()
+    }
+}
+
+// equality compares the entries in ascending order, not the shape
+
+impl<K: PartialOrd, V: PartialEq, const CAP: usize> PartialEq for MultiwayTree<K, V, CAP> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<K: PartialOrd + Eq, V: Eq, const CAP: usize> Eq for MultiwayTree<K, V, CAP> {}
+
+impl<K: PartialOrd, V, const CAP: usize> Index<&K> for MultiwayTree<K, V, CAP> {
+    type Output = V;
+
+    fn index(&self, key: &K) -> &V {
+        self.find(key).expect("key not found in MultiwayTree")
+    }
+}
+
+impl<K: PartialOrd, V, const CAP: usize> Extend<(K, V)> for MultiwayTree<K, V, CAP> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: PartialOrd, V, const CAP: usize> FromIterator<(K, V)> for MultiwayTree<K, V, CAP> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tree = MultiwayTree::new();
+        tree.extend(iter);
+        tree
+    }
+}
+
+impl<K: PartialOrd, V, const CAP: usize, const N: usize> From<[(K, V); N]>
+    for MultiwayTree<K, V, CAP>
+{
+    fn from(entries: [(K, V); N]) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+impl<K: PartialOrd + Debug, V: Debug, const CAP: usize> Debug for MultiwayTree<K, V, CAP> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tree::test_map::{self, test_map_impl};
+    use rand::seq::SliceRandom;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn check<const CAP: usize>(tree: &MultiwayTree<u32, u32, CAP>) {
+        if let Err(err) = tree.check_rules() {
+            panic!("rules violated: {}", err);
+        }
+    }
+
+    #[test]
+    fn mw_test_insert_remove() {
+        let mut keys: Vec<u32> = (0..500).collect();
+        keys.shuffle(&mut StdRng::seed_from_u64(42));
+        let mut tree: MultiwayTree<u32, u32, 3> = MultiwayTree::new();
+        for key in &keys {
+            assert_eq!(tree.insert(*key, *key * 2), None);
+            check(&tree);
+        }
+        assert_eq!(tree.len(), 500);
+        assert_eq!(tree.insert(7, 1), Some(14));
+        assert_eq!(tree[&7], 1);
+        *tree.find_mut(&7).expect("key not found") = 14;
+        assert!(tree.height() <= 9);
+        assert!(tree.iter().map(|(key, _)| *key).eq(0..500));
+
+        for key in keys.iter().filter(|key| *key % 3 != 0) {
+            assert_eq!(tree.remove(key), Some(*key * 2));
+            assert_eq!(tree.remove(key), None);
+            check(&tree);
+        }
+        assert!(tree.iter().map(|(key, _)| *key).eq((0..500).step_by(3)));
+        for key in (0..500).step_by(3) {
+            assert_eq!(tree.remove(&key), Some(key * 2));
+            check(&tree);
+        }
+        assert!(tree.is_empty());
+        assert_eq!(tree.height(), 0);
+    }
+
+    test_map_impl!(MultiwayTree<u32, u32, 4>, check);
+
+    #[test]
+    fn mw_test_against_rbtree() {
+        let (tree, rb_tree, _) =
+            test_map::check_against_rbtree::<MultiwayTree<u32, u32, 4>>(7, 5000, 300, 0.6);
+        assert_eq!(tree.len(), rb_tree.iter().count());
+        assert!(tree.range(50..=150).eq(rb_tree.range(50..=150)));
+        assert!(tree.range(..100).eq(rb_tree.range(..100)));
+        assert!(tree
+            .range((Bound::Excluded(20), Bound::Unbounded))
+            .eq(rb_tree.range((Bound::Excluded(20), Bound::Unbounded))));
+        assert!(tree.into_iter().eq(rb_tree.into_iter()));
+    }
+
+    #[test]
+    fn mw_test_range() {
+        let tree: MultiwayTree<u32, ()> = (0..1000).step_by(2).map(|key| (key, ())).collect();
+        fn keys<'a>(range: impl Iterator<Item = (&'a u32, &'a ())>) -> Vec<u32> {
+            range.map(|(key, _)| *key).collect()
+        }
+        let expected: Vec<u32> = (100..=200).step_by(2).collect();
+        assert_eq!(keys(tree.range(99..201)), expected);
+        assert_eq!(keys(tree.range(100..=200)), expected);
+        assert_eq!(
+            keys(tree.range((Bound::Excluded(100), Bound::Included(104)))),
+            vec![102, 104]
+        );
+        assert_eq!(keys(tree.range(995..)), vec![996, 998]);
+        assert_eq!(keys(tree.range(1000..)), Vec::<u32>::new());
+        assert_eq!(keys(tree.range(5..5)), Vec::<u32>::new());
+        assert_eq!(tree.range(..).count(), 500);
+        assert_eq!(tree.iter().len(), 500);
+        let mut iter = tree.iter();
+        iter.nth(99);
+        assert_eq!(iter.size_hint(), (400, Some(400)));
+        assert_eq!(tree.range(100..200).size_hint(), (0, None));
+        assert_eq!(
+            format!("{:?}", MultiwayTree::<u32, u32>::from([(2, 20), (1, 10)])),
+            "{1: 10, 2: 20}"
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use crate::prelude::*;
+use core::cmp::Ordering;
+use core::fmt::Debug;
+
+/// A node of a MultiwayTree, holds up to CAP entries in ascending order and,
+/// unless it is a leaf, one child more than entries.
+#[derive(Clone)]
+pub(crate) struct MultiwayNode<K, V, const CAP: usize> {
+    pub entries: Vec<(K, V)>,
+    // boxed, inserting and removing children moves pointers and not whole nodes
+    #[allow(clippy::vec_box)]
+    pub children: Vec<Box<MultiwayNode<K, V, CAP>>>,
+}
+
+/// The outcome of an insert below a node
+pub(crate) enum Insert<K, V, const CAP: usize> {
+    Added,
+    Replaced(V),
+    /// the node overflowed, the median entry and the new right sibling go to the parent
+    Split((K, V), Box<MultiwayNode<K, V, CAP>>),
+}
+
+impl<K: PartialOrd, V, const CAP: usize> MultiwayNode<K, V, CAP> {
+    /// every node but the root holds at least MIN entries
+    pub const MIN: usize = CAP / 2;
+
+    pub fn leaf() -> MultiwayNode<K, V, CAP> {
+        MultiwayNode {
+            // one more than CAP, a node overflows before it is split
+            entries: Vec::with_capacity(CAP + 1),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// the index of the first entry not smaller than key, true if its key is key
+    pub fn search(&self, key: &K) -> (usize, bool) {
+        let pos = self.entries.partition_point(|(curr, _)| *curr < *key);
+        let found = self.entries.get(pos).is_some_and(|(curr, _)| *curr == *key);
+        (pos, found)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Insert<K, V, CAP> {
+        let (pos, found) = self.search(&key);
+        if found {
+            return Insert::Replaced(core::mem::replace(&mut self.entries[pos].1, value));
+        }
+        if self.is_leaf() {
+            self.entries.insert(pos, (key, value));
+        } else {
+            match self.children[pos].insert(key, value) {
+                Insert::Split(median, right) => {
+                    self.entries.insert(pos, median);
+                    self.children.insert(pos + 1, right);
+                }
+                res => return res,
+            }
+        }
+        if self.entries.len() > CAP {
+            let (median, right) = self.split();
+            Insert::Split(median, right)
+        } else {
+            Insert::Added
+        }
+    }
+
+    /// splits an overflowed node, the upper half moves to the returned sibling
+    fn split(&mut self) -> ((K, V), Box<MultiwayNode<K, V, CAP>>) {
+        let mid = self.entries.len() / 2;
+        let mut right = MultiwayNode::leaf();
+        right.entries.extend(self.entries.drain(mid + 1..));
+        if !self.is_leaf() {
+            right.children = self.children.split_off(mid + 1);
+        }
+        let median = self.entries.pop().expect("unexpected empty node");
+        (median, Box::new(right))
+    }
+
+    /// Removes key below this node. A child left with less than MIN entries is
+    /// refilled on the way back up, only this node itself may underflow.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let (pos, found) = self.search(key);
+        if self.is_leaf() {
+            return found.then(|| self.entries.remove(pos).1);
+        }
+        let removed = if found {
+            // the largest entry of the smaller subtree takes the place of the removed one
+            let replacement = self.children[pos].pop_largest();
+            Some(core::mem::replace(&mut self.entries[pos], replacement).1)
+        } else {
+            self.children[pos].remove(key)
+        };
+        if removed.is_some() {
+            self.fix_child(pos);
+        }
+        removed
+    }
+
+    fn pop_largest(&mut self) -> (K, V) {
+        if self.is_leaf() {
+            return self.entries.pop().expect("unexpected empty node");
+        }
+        let last = self.children.len() - 1;
+        let entry = self.children[last].pop_largest();
+        self.fix_child(last);
+        entry
+    }
+
+    /// refills the child at idx from a sibling that can spare an entry or merges it with one
+    fn fix_child(&mut self, idx: usize) {
+        if self.children[idx].entries.len() >= Self::MIN {
+            return;
+        }
+        if idx > 0 && self.children[idx - 1].entries.len() > Self::MIN {
+            self.rotate_from_smaller(idx);
+        } else if idx + 1 < self.children.len() && self.children[idx + 1].entries.len() > Self::MIN
+        {
+            self.rotate_from_larger(idx);
+        } else if idx > 0 {
+            self.merge(idx - 1);
+        } else {
+            self.merge(idx);
+        }
+    }
+
+    /// moves the separator down into the child at idx and the largest entry of
+    /// its smaller sibling up into the separator
+    fn rotate_from_smaller(&mut self, idx: usize) {
+        let (smaller, rest) = self.children.split_at_mut(idx);
+        let sibling = &mut smaller[idx - 1];
+        let child = &mut rest[0];
+        let entry = sibling.entries.pop().expect("unexpected empty sibling");
+        let separator = core::mem::replace(&mut self.entries[idx - 1], entry);
+        child.entries.insert(0, separator);
+        if let Some(grandchild) = sibling.children.pop() {
+            child.children.insert(0, grandchild);
+        }
+    }
+
+    /// the mirror image of rotate_from_smaller
+    fn rotate_from_larger(&mut self, idx: usize) {
+        let (rest, larger) = self.children.split_at_mut(idx + 1);
+        let child = &mut rest[idx];
+        let sibling = &mut larger[0];
+        let entry = sibling.entries.remove(0);
+        let separator = core::mem::replace(&mut self.entries[idx], entry);
+        child.entries.push(separator);
+        if !sibling.is_leaf() {
+            child.children.push(sibling.children.remove(0));
+        }
+    }
+
+    /// merges the child at idx + 1 and the separator between them into the child at idx
+    fn merge(&mut self, idx: usize) {
+        let larger = self.children.remove(idx + 1);
+        let separator = self.entries.remove(idx);
+        let child = &mut self.children[idx];
+        child.entries.push(separator);
+        child.entries.extend(larger.entries);
+        child.children.extend(larger.children);
+    }
+
+    /// Checks the order of the keys, the fill of the nodes and that all leaves
+    /// are at the same depth. Returns the height of the subtree.
+    pub fn check_rules(
+        &self,
+        is_root: bool,
+        lower: Option<&K>,
+        upper: Option<&K>,
+    ) -> Result<usize, String>
+    where
+        K: Debug,
+    {
+        let keys: Vec<&K> = self.entries.iter().map(|(key, _)| key).collect();
+        if self.entries.len() > CAP || (!is_root && self.entries.len() < Self::MIN) {
+            return Err(format!(
+                "node {:?} holds {} entries, expected {} to {}",
+                keys,
+                self.entries.len(),
+                Self::MIN,
+                CAP
+            ));
+        }
+        if self.entries.is_empty() && !self.is_leaf() {
+            return Err("empty inner node".to_string());
+        }
+        let ordered = lower.into_iter().chain(keys.iter().copied()).chain(upper);
+        let ordered: Vec<&K> = ordered.collect();
+        if ordered
+            .windows(2)
+            .any(|pair| pair[0].partial_cmp(pair[1]) != Some(Ordering::Less))
+        {
+            return Err(format!(
+                "keys {:?} out of order between {:?} and {:?}",
+                keys, lower, upper
+            ));
+        }
+        if self.is_leaf() {
+            return Ok(1);
+        }
+        if self.children.len() != self.entries.len() + 1 {
+            return Err(format!(
+                "node {:?} has {} children",
+                keys,
+                self.children.len()
+            ));
+        }
+        let mut height = None;
+        for (idx, child) in self.children.iter().enumerate() {
+            let child_lower = if idx == 0 { lower } else { Some(keys[idx - 1]) };
+            let child_upper = keys.get(idx).copied().or(upper);
+            let child_height = child.check_rules(false, child_lower, child_upper)?;
+            if height.is_some_and(|height| height != child_height) {
+                return Err(format!("leaves at different depths below {:?}", keys));
+            }
+            height = Some(child_height);
+        }
+        Ok(height.unwrap_or(0) + 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    type Node = MultiwayNode<u32, u32, 3>;
+
+    fn node(keys: &[u32], children: Vec<Node>) -> Node {
+        MultiwayNode {
+            entries: keys.iter().map(|key| (*key, *key)).collect(),
+            children: children.into_iter().map(Box::new).collect(),
+        }
+    }
+
+    fn keys(node: &Node) -> Vec<u32> {
+        node.entries.iter().map(|(key, _)| *key).collect()
+    }
+
+    #[test]
+    fn mw_test_rebalance_children() {
+        // a spare entry is borrowed from the smaller sibling
+        let mut parent = node(&[3], vec![node(&[1, 2], vec![]), node(&[], vec![])]);
+        parent.fix_child(1);
+        assert_eq!(keys(&parent), vec![2]);
+        assert_eq!(keys(&parent.children[0]), vec![1]);
+        assert_eq!(keys(&parent.children[1]), vec![3]);
+
+        // then from the larger one
+        let mut parent = node(&[1], vec![node(&[], vec![]), node(&[2, 3], vec![])]);
+        parent.fix_child(0);
+        assert_eq!(keys(&parent), vec![2]);
+        assert_eq!(keys(&parent.children[0]), vec![1]);
+        assert_eq!(keys(&parent.children[1]), vec![3]);
+
+        // siblings without spare entries are merged
+        let mut parent = node(&[2], vec![node(&[1], vec![]), node(&[], vec![])]);
+        parent.fix_child(1);
+        assert!(parent.entries.is_empty());
+        assert_eq!(keys(&parent.children[0]), vec![1, 2]);
+    }
+}
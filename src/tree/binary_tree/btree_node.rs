@@ -1,7 +1,13 @@
 use super::SubNode;
+use crate::prelude::*;
+use crate::tree::pretty::{Layout, PrettyOptions};
+use crate::tree::rb_tree::Color;
+use crate::tree::TreeNode;
 
-use std::fmt::Debug;
+use alloc::sync::Arc;
+use core::fmt::{Debug, Display, Formatter};
 
+#[derive(Clone)]
 pub struct BTreeNode<K: PartialOrd, V> {
     pub key: K,
     pub value: V,
@@ -19,48 +25,119 @@ impl<K: PartialOrd, V> BTreeNode<K, V> {
         }
     }
 
-    pub fn insert_node_rec(&mut self, node: Box<BTreeNode<K, V>>) -> Option<V> {
+    pub fn traverse_asc(&self, func: &mut dyn FnMut(&K, &V)) {
+        if let Some(smaller) = &self.smaller {
+            smaller.traverse_asc(func);
+        }
+        func(&self.key, &self.value);
+        if let Some(larger) = &self.larger {
+            larger.traverse_asc(func);
+        }
+    }
+
+    /// builds a balanced subtree from the next len entries, which must be in ascending order
+    pub(crate) fn build_sorted(
+        entries: &mut impl Iterator<Item = (K, V)>,
+        len: usize,
+    ) -> SubNode<K, V> {
+        if len == 0 {
+            return None;
+        }
+        let smaller_len = (len - 1) / 2;
+        let smaller = Self::build_sorted(entries, smaller_len);
+        let (key, value) = entries.next().expect("fewer entries than announced");
+        let larger = Self::build_sorted(entries, len - 1 - smaller_len);
+        Some(Arc::new(BTreeNode {
+            key,
+            value,
+            smaller,
+            larger,
+        }))
+    }
+}
+
+/// all modifications go through Arc::make_mut, nodes shared with a cloned tree
+/// are copied before they are changed
+impl<K: PartialOrd + Clone, V: Clone> BTreeNode<K, V> {
+    /// visited counts the nodes node.key is compared with
+    pub fn insert_node_rec(&mut self, node: BTreeNode<K, V>, visited: &mut u64) -> Option<V> {
+        *visited += 1;
         if node.key < self.key {
             if let Some(smaller) = &mut self.smaller {
-                smaller.insert_node_rec(node)
+                Arc::make_mut(smaller).insert_node_rec(node, visited)
             } else {
-                self.smaller = Some(node);
+                self.smaller = Some(Arc::new(node));
                 None
             }
         } else if node.key > self.key {
             if let Some(larger) = &mut self.larger {
-                larger.insert_node_rec(node)
+                Arc::make_mut(larger).insert_node_rec(node, visited)
             } else {
-                self.larger = Some(node);
+                self.larger = Some(Arc::new(node));
                 None
             }
         } else {
             // don't even really need to support this case
-            Some(std::mem::replace(&mut self.value, node.value))
+            Some(core::mem::replace(&mut self.value, node.value))
         }
     }
 
-    pub fn traverse_asc(&self, func: &mut dyn FnMut(&K, &V)) {
-        if let Some(smaller) = &self.smaller {
-            smaller.traverse_asc(func);
-        }
-        func(&self.key, &self.value);
-        if let Some(larger) = &self.larger {
-            larger.traverse_asc(func);
+    pub fn left_rotate(mut self: Arc<Self>) -> core::result::Result<Arc<Self>, &'static str> {
+        if self.larger.is_some() {
+            let node = Arc::make_mut(&mut self);
+            let mut larger = node.larger.take().expect("unexpected empty node 1");
+            let larger_node = Arc::make_mut(&mut larger);
+            node.larger = larger_node.smaller.take();
+            larger_node.smaller = Some(self);
+            Ok(larger)
+        } else {
+            Err("cannot left rotate - larger subnode is nil")
         }
     }
 
-    pub fn right_rotate(mut self: Box<Self>) -> std::result::Result<Box<Self>, &'static str> {
+    pub fn right_rotate(mut self: Arc<Self>) -> core::result::Result<Arc<Self>, &'static str> {
         if self.smaller.is_some() {
-            let mut smaller = self.smaller.take().expect("unexpected empty node 1");
-            self.smaller = smaller.larger.take();
-            smaller.larger = Some(self);
+            let node = Arc::make_mut(&mut self);
+            let mut smaller = node.smaller.take().expect("unexpected empty node 1");
+            let smaller_node = Arc::make_mut(&mut smaller);
+            node.smaller = smaller_node.larger.take();
+            smaller_node.larger = Some(self);
             Ok(smaller)
         } else {
             Err("cannot left rotate - smaller subnode is nil")
         }
     }
 
+    /// Day-Stout-Warren, first phase: right rotations turn the subtree below link into
+    /// a vine, a chain along the larger links. Returns the number of nodes.
+    pub(crate) fn tree_to_vine(mut link: &mut SubNode<K, V>) -> usize {
+        let mut count = 0;
+        while let Some(node) = link.as_ref() {
+            if node.smaller.is_some() {
+                let node = link.take().expect("unexpected empty link");
+                *link = Some(
+                    node.right_rotate()
+                        .expect("unexpected missing smaller node"),
+                );
+            } else {
+                count += 1;
+                link = &mut Arc::make_mut(link.as_mut().expect("unexpected empty link")).larger;
+            }
+        }
+        count
+    }
+
+    /// Day-Stout-Warren, second phase: left rotates every other node of the vine below
+    /// link count times, each pass halves the length of the vine
+    pub(crate) fn compress(mut link: &mut SubNode<K, V>, count: usize) {
+        for _ in 0..count {
+            let node = link.take().expect("unexpected short vine");
+            *link = Some(node.left_rotate().expect("unexpected short vine"));
+            link = &mut Arc::make_mut(link.as_mut().expect("unexpected empty link")).larger;
+        }
+    }
+
+    /// the nodes on the path are copied if shared, so look for key first
     pub fn remove(&mut self, key: &K) -> Option<V> {
         // remove only subnodes - this node has beech checked upstream
         #[cfg(test)]
@@ -77,30 +154,32 @@ impl<K: PartialOrd, V> BTreeNode<K, V> {
         if let Some(true) = child_link.as_ref().map(|root| root.key == *key) {
             // delete the root
             let mut child = child_link.take().expect("unexpected empty link");
-            let (res, new_child) = if child.smaller.is_some() {
-                if child.larger.is_some() {
-                    // root has two siblings - swap root with next larger, delete next larger
-                    let (key, value) = child.remove_next_larger();
-                    child.key = key;
-                    let res = std::mem::replace(&mut child.value, value);
-                    (Some(res), Some(child))
-                } else {
+            let (res, new_child) = if child.smaller.is_some() && child.larger.is_some() {
+                // root has two siblings - swap root with next larger, delete next larger
+                let child_node = Arc::make_mut(&mut child);
+                let (key, value) = child_node.remove_next_larger();
+                child_node.key = key;
+                let res = core::mem::replace(&mut child_node.value, value);
+                (Some(res), Some(child))
+            } else {
+                let mut child = Arc::unwrap_or_clone(child);
+                if child.smaller.is_some() {
                     // root becomes root.smaller
                     (Some(child.value), child.smaller.take())
+                } else if child.larger.is_some() {
+                    // root becomes root.larger
+                    (Some(child.value), child.larger.take())
+                } else {
+                    // the tree is empty
+                    (Some(child.value), None)
                 }
-            } else if child.larger.is_some() {
-                // root becomes root.larger
-                (Some(child.value), child.larger.take())
-            } else {
-                // the tree is empty
-                (Some(child.value), None)
             };
             if new_child.is_some() {
                 *child_link = new_child;
             }
             res
         } else if let Some(child) = child_link {
-            child.remove(key)
+            Arc::make_mut(child).remove(key)
         } else {
             None
         }
@@ -110,12 +189,13 @@ impl<K: PartialOrd, V> BTreeNode<K, V> {
         match self.larger.as_ref().map(|node| node.smaller.is_some()) {
             Some(true) => {
                 // larger has smaller siblings - find the smallest one
-                let mut curr = self.larger.as_mut().expect("unexpected empty link");
+                let mut curr = Arc::make_mut(self.larger.as_mut().expect("unexpected empty link"));
                 while let Some(true) = curr.smaller.as_ref().map(|node| node.smaller.is_some()) {
-                    curr = curr.smaller.as_mut().expect("unexpected empty link");
+                    curr = Arc::make_mut(curr.smaller.as_mut().expect("unexpected empty link"));
                 }
                 // current is the parent of the smallest node
-                let smallest = curr.smaller.take().expect("unexpected empty node");
+                let smallest =
+                    Arc::unwrap_or_clone(curr.smaller.take().expect("unexpected empty node"));
                 if smallest.larger.is_some() {
                     curr.smaller = smallest.larger;
                 }
@@ -123,7 +203,8 @@ impl<K: PartialOrd, V> BTreeNode<K, V> {
             }
             Some(false) => {
                 // self.larger has no smaller siblings - larger is the one
-                let larger = self.larger.take().expect("unexpected empty link");
+                let larger =
+                    Arc::unwrap_or_clone(self.larger.take().expect("unexpected empty link"));
                 if larger.larger.is_some() {
                     self.larger = larger.larger;
                 }
@@ -143,52 +224,94 @@ impl<K: PartialOrd + Debug, V: Debug> BTreeNode<K, V> {
     ///   └─>(kkk,vvv)
     ///       ├─<(kkk,vvv)
     ///       └─>(kkk,vvv)
-
-    fn to_str_buffer(&self, buffer: &mut String, lead: &str, root: bool, smaller: bool) {
-        const J_SMALLER: &str = " └─>";
-        const J_LARGER: &str = " ├─<";
-        const L_SMALLER: &str = "    ";
-        const L_LARGER: &str = " │  ";
-
-        // buffer.push_str(&format!("lead: '{}'\n", lead));
-        if root {
-            buffer.push_str(&format!("({:?},{:?})\n", self.key, self.value));
+    pub(crate) fn to_str_buffer(
+        &self,
+        buffer: &mut String,
+        lead: &str,
+        root: bool,
+        smaller: bool,
+        depth: usize,
+        layout: &mut Layout,
+    ) {
+        let junction = if root {
+            ""
+        } else if smaller {
+            layout.j_smaller
         } else {
-            let junction = if smaller { J_SMALLER } else { J_LARGER };
-            buffer.push_str(&format!(
-                "{}{}({:?},{:?})\n",
-                lead, junction, self.key, self.value
-            ));
+            layout.j_larger
+        };
+
+        if !layout.take_node() {
+            if layout.truncate() {
+                buffer.push_str(&format!("{}{}...\n", lead, junction));
+            }
+            return;
         }
 
+        let node_str = layout.node_text(&self.key, &self.value);
+        buffer.push_str(&format!(
+            "{}{}{}\n",
+            lead,
+            junction,
+            layout.paint_highlight(&self.key, node_str)
+        ));
+
         if self.smaller.is_some() || self.larger.is_some() {
             let sub_lead = if root {
                 lead.to_string()
             } else if smaller {
-                lead.to_string() + L_SMALLER
+                lead.to_string() + layout.l_smaller
             } else {
-                lead.to_string() + L_LARGER
+                lead.to_string() + layout.l_larger
             };
 
+            if !layout.below_max_depth(depth + 1) {
+                buffer.push_str(&format!("{}{}...\n", sub_lead, layout.j_smaller));
+                return;
+            }
+
             if let Some(subnode) = &self.larger {
-                subnode.to_str_buffer(buffer, &sub_lead, false, false);
+                subnode.to_str_buffer(buffer, &sub_lead, false, false, depth + 1, layout);
             } else {
-                buffer.push_str(&format!("{}{}nil\n", sub_lead, J_LARGER));
+                buffer.push_str(&format!("{}{}nil\n", sub_lead, layout.j_larger));
             }
 
             if let Some(subnode) = &self.smaller {
-                subnode.to_str_buffer(buffer, &sub_lead, false, true);
+                subnode.to_str_buffer(buffer, &sub_lead, false, true, depth + 1, layout);
             } else {
-                buffer.push_str(&format!("{}{}nil\n", sub_lead, J_SMALLER));
+                buffer.push_str(&format!("{}{}nil\n", sub_lead, layout.j_smaller));
             }
         }
     }
 }
 
-impl<K: PartialOrd + Debug, V: Debug> ToString for BTreeNode<K, V> {
-    fn to_string(&self) -> String {
+impl<K: PartialOrd, V> TreeNode<K, V> for BTreeNode<K, V> {
+    fn key(&self) -> &K {
+        &self.key
+    }
+
+    fn value(&self) -> &V {
+        &self.value
+    }
+
+    fn smaller(&self) -> Option<&Self> {
+        self.smaller.as_deref()
+    }
+
+    fn larger(&self) -> Option<&Self> {
+        self.larger.as_deref()
+    }
+
+    fn color(&self) -> Option<Color> {
+        None
+    }
+}
+
+impl<K: PartialOrd + Debug, V: Debug> Display for BTreeNode<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let options = PrettyOptions::default();
         let mut buffer = String::new();
-        self.to_str_buffer(&mut buffer, "", true, false);
-        buffer
+        self.to_str_buffer(&mut buffer, "", true, false, 0, &mut Layout::new(&options));
+        write!(f, "{}", buffer)
     }
 }
@@ -1,12 +1,32 @@
-use btree_node::BTreeNode;
-use std::fmt::{Debug, Formatter};
+use crate::prelude::*;
+use crate::tree::diagram;
+use crate::tree::dot::{self, DotOptions};
+#[cfg(feature = "metrics")]
+use crate::tree::metrics::Stats;
+use crate::tree::metrics::{self, Metrics, ShapeReport};
+use crate::tree::pretty::{Layout, Pretty};
+use crate::tree::shape;
+use crate::tree::svg::{self, SvgOptions};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+pub(crate) use btree_node::BTreeNode;
+use core::cmp::Ordering;
+use core::fmt::{Debug, Display, Formatter};
+use core::hash::{Hash, Hasher};
+use core::ops::Index;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::hash::BuildHasher;
 
-type SubNode<K, V> = Option<Box<BTreeNode<K, V>>>;
+type SubNode<K, V> = Option<Arc<BTreeNode<K, V>>>;
 
 mod btree_node;
 
 pub struct BTree<K: PartialOrd, V> {
-    root: SubNode<K, V>,
+    pub(crate) root: SubNode<K, V>,
+    metrics: Metrics,
 }
 
 impl<K: PartialOrd, V> Default for BTree<K, V> {
@@ -17,50 +37,13 @@ impl<K: PartialOrd, V> Default for BTree<K, V> {
 
 impl<K: PartialOrd, V> BTree<K, V> {
     pub fn new() -> BTree<K, V> {
-        BTree { root: None }
-    }
-
-    // TODO: add size, iterator, try_insert, adapt to std collection api
-
-    pub fn insert_rec(&mut self, key: K, value: V) -> Option<V> {
-        let new_node = Box::new(BTreeNode::new(key, value));
-        if let Some(node) = &mut self.root {
-            node.insert_node_rec(new_node)
-        } else {
-            self.root = Some(new_node);
-            None
+        BTree {
+            root: None,
+            metrics: Metrics::default(),
         }
     }
 
-    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
-        if let Some(node) = &mut self.root {
-            let mut curr = node;
-            loop {
-                if key < curr.key {
-                    match &mut curr.smaller {
-                        Some(node) => curr = node,
-                        smaller @ None => {
-                            *smaller = Some(Box::new(BTreeNode::new(key, value)));
-                            return None;
-                        }
-                    }
-                } else if key > curr.key {
-                    match &mut curr.larger {
-                        Some(node) => curr = node,
-                        larger @ None => {
-                            *larger = Some(Box::new(BTreeNode::new(key, value)));
-                            return None;
-                        }
-                    }
-                } else {
-                    return Some(std::mem::replace(&mut curr.value, value));
-                }
-            }
-        } else {
-            self.root = Some(Box::new(BTreeNode::new(key, value)));
-            None
-        }
-    }
+    // TODO: add size, iterator, try_insert, adapt to std collection api
 
     pub fn traverse_asc(&self, func: &mut dyn FnMut(&K, &V)) {
         if let Some(node) = &self.root {
@@ -69,112 +52,36 @@ impl<K: PartialOrd, V> BTree<K, V> {
     }
 
     pub fn contains(&self, key: &K) -> bool {
-        if let Some(node) = &self.root {
-            let mut curr = node;
-            // loop {
-            loop {
-                if *key < curr.key {
-                    if let Some(subnode) = &curr.smaller {
-                        curr = subnode;
-                    } else {
-                        return false;
-                    }
-                } else if *key > curr.key {
-                    if let Some(subnode) = &curr.larger {
-                        curr = subnode;
-                    } else {
-                        return false;
-                    }
-                } else {
-                    return true;
-                }
-            }
-        } else {
-            false
-        }
+        self.find(key).is_some()
     }
 
     pub fn find(&self, key: &K) -> Option<&V> {
-        if let Some(node) = &self.root {
+        let mut depth = 0;
+        let res = if let Some(node) = &self.root {
             let mut curr = node;
             loop {
+                depth += 1;
                 if *key < curr.key {
                     if let Some(subnode) = &curr.smaller {
                         curr = subnode;
                     } else {
-                        return None;
+                        break None;
                     }
                 } else if *key > curr.key {
                     if let Some(subnode) = &curr.larger {
                         curr = subnode;
                     } else {
-                        return None;
-                    }
-                } else {
-                    return Some(&curr.value);
-                }
-            }
-        } else {
-            None
-        }
-    }
-
-    pub fn find_mut(&mut self, key: &K) -> Option<&mut V> {
-        if let Some(node) = &mut self.root {
-            let mut curr = node;
-            loop {
-                if *key < curr.key {
-                    if let Some(subnode) = &mut curr.smaller {
-                        curr = subnode;
-                    } else {
-                        return None;
-                    }
-                } else if *key > curr.key {
-                    if let Some(subnode) = &mut curr.larger {
-                        curr = subnode;
-                    } else {
-                        return None;
+                        break None;
                     }
                 } else {
-                    return Some(&mut curr.value);
+                    break Some(&curr.value);
                 }
             }
         } else {
             None
-        }
-    }
-
-    pub fn remove(&mut self, key: &K) -> Option<V> {
-        if let Some(true) = self.root.as_ref().map(|root| root.key == *key) {
-            // delete the root
-            let mut root = self.root.take().expect("unexpected empty link");
-            let (res, new_root) = if root.smaller.is_some() {
-                if root.larger.is_some() {
-                    // root has two siblings - swap root with next larger, delete next larger
-                    let (key, value) = root.remove_next_larger();
-                    root.key = key;
-                    let res = std::mem::replace(&mut root.value, value);
-                    (Some(res), Some(root))
-                } else {
-                    // root becomes root.smaller
-                    (Some(root.value), root.smaller.take())
-                }
-            } else if root.larger.is_some() {
-                // root becomes root.larger
-                (Some(root.value), root.larger.take())
-            } else {
-                // the tree is empty
-                (Some(root.value), None)
-            };
-            if new_root.is_some() {
-                self.root = new_root;
-            }
-            res
-        } else if let Some(root) = &mut self.root {
-            root.remove(key)
-        } else {
-            None
-        }
+        };
+        self.metrics.record_search(depth);
+        res
     }
 
     fn smallest_node(&self) -> Option<&BTreeNode<K, V>> {
@@ -195,10 +102,12 @@ impl<K: PartialOrd, V> BTree<K, V> {
     }
 
     fn smaller_node(&self, key: &K) -> Option<&BTreeNode<K, V>> {
-        if let Some(root) = &self.root {
+        let mut depth = 0;
+        let res: Option<&BTreeNode<K, V>> = if let Some(root) = &self.root {
             let mut candidate: Option<&BTreeNode<K, V>> = None;
             let mut curr = root;
             loop {
+                depth += 1;
                 // eprintln!("smaller_none({:?}), curr {:?}", key, curr.key);
                 if curr.key < *key {
                     // search larger
@@ -207,7 +116,7 @@ impl<K: PartialOrd, V> BTree<K, V> {
                         candidate = Some(curr);
                         curr = larger;
                     } else {
-                        return Some(curr);
+                        break Some(curr);
                     }
                 } else if curr.key >= *key {
                     // search smaller
@@ -215,13 +124,15 @@ impl<K: PartialOrd, V> BTree<K, V> {
                     if let Some(smaller) = &curr.smaller {
                         curr = smaller;
                     } else {
-                        return candidate;
+                        break candidate;
                     }
                 }
             }
         } else {
             None
-        }
+        };
+        self.metrics.record_comparisons(depth);
+        res
     }
 
     pub fn smaller(&self, key: &K) -> Option<(&K, &V)> {
@@ -245,10 +156,12 @@ impl<K: PartialOrd, V> BTree<K, V> {
     }
 
     fn larger_node(&self, key: &K) -> Option<&BTreeNode<K, V>> {
-        if let Some(root) = &self.root {
+        let mut depth = 0;
+        let res: Option<&BTreeNode<K, V>> = if let Some(root) = &self.root {
             let mut candidate: Option<&BTreeNode<K, V>> = None;
             let mut curr = root;
             loop {
+                depth += 1;
                 // eprintln!("smaller_none({:?}), curr {:?}", key, curr.key);
                 if curr.key > *key {
                     // search smaller
@@ -257,7 +170,7 @@ impl<K: PartialOrd, V> BTree<K, V> {
                         candidate = Some(curr);
                         curr = smaller;
                     } else {
-                        return Some(curr);
+                        break Some(curr);
                     }
                 } else if curr.key <= *key {
                     // search larger
@@ -265,13 +178,15 @@ impl<K: PartialOrd, V> BTree<K, V> {
                     if let Some(larger) = &curr.larger {
                         curr = larger;
                     } else {
-                        return candidate;
+                        break candidate;
                     }
                 }
             }
         } else {
             None
-        }
+        };
+        self.metrics.record_comparisons(depth);
+        res
     }
 
     pub fn larger(&self, key: &K) -> Option<(&K, &V)> {
@@ -281,12 +196,440 @@ impl<K: PartialOrd, V> BTree<K, V> {
     pub fn is_empty(&self) -> bool {
         self.root.is_none()
     }
+
+    /// height, node count and nodes per level, the black height is always None
+    pub fn shape_report(&self) -> ShapeReport {
+        metrics::shape_report::<K, V, _>(self.root.as_deref())
+    }
+
+    /// counters collected since the tree was created or reset_stats was called,
+    /// a BTree never rotates or recolors
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> Stats {
+        self.metrics.stats()
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn reset_stats(&mut self) {
+        self.metrics.reset()
+    }
+
+    /// structural view of the tree, use {} to print it
+    pub fn pretty(&self) -> Pretty<'_, Self> {
+        Pretty::new(self)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.push_smaller(self.root.as_deref());
+        iter
+    }
+}
+
+impl<K: PartialOrd + Debug, V: Debug> BTree<K, V> {
+    /// the tree as a Graphviz digraph, render it with `dot -Tsvg`
+    pub fn to_dot(&self) -> String {
+        self.to_dot_with(&DotOptions::default())
+    }
+
+    pub fn to_dot_with(&self, options: &DotOptions<K>) -> String {
+        dot::to_dot::<K, V, _>("BTree", self.root.as_deref(), options)
+    }
+
+    /// the tree as a standalone SVG document, laid out without Graphviz
+    pub fn to_svg(&self) -> String {
+        self.to_svg_with(&SvgOptions::default())
+    }
+
+    pub fn to_svg_with(&self, options: &SvgOptions<K>) -> String {
+        svg::to_svg::<K, V, _>(self.root.as_deref(), options)
+    }
+}
+
+impl<K: PartialOrd + FromStr, V: FromStr + Default> BTree<K, V> {
+    /// Builds the tree drawn by a pretty() diagram as it is, color markers are ignored
+    /// and hidden values are V::default().
+    pub fn from_diagram(diagram: &str) -> Result<BTree<K, V>, String> {
+        let root = diagram::parse(diagram, |_color, key, value, smaller, larger| {
+            Ok(Arc::new(BTreeNode {
+                key,
+                value,
+                smaller,
+                larger,
+            }))
+        })?;
+        Ok(BTree {
+            root,
+            metrics: Metrics::default(),
+        })
+    }
+}
+
+impl<K: PartialOrd + Display, V: Display> BTree<K, V> {
+    /// the exact shape of the tree as text, from_shape rebuilds it
+    pub fn to_shape(&self) -> String {
+        shape::encode::<K, V, _>(self.root.as_deref())
+    }
+}
+
+impl<K: PartialOrd + FromStr, V: FromStr> BTree<K, V> {
+    /// rebuilds a tree written by to_shape node by node, the key order is not checked
+    pub fn from_shape(shape: &str) -> Result<BTree<K, V>, String> {
+        let root = shape::decode(shape, |color, key, value, smaller, larger| {
+            if color.is_some() {
+                return Err("unexpected color in a binary tree".to_string());
+            }
+            Ok(Arc::new(BTreeNode {
+                key,
+                value,
+                smaller,
+                larger,
+            }))
+        })?;
+        Ok(BTree {
+            root,
+            metrics: Metrics::default(),
+        })
+    }
+}
+
+/// Changes copy the nodes still shared with a clone, which is why insert, find_mut and
+/// remove need K: Clone and V: Clone. Before the trees could be cloned cheaply these
+/// methods had no Clone bounds, callers with keys or values that are not Clone have
+/// to wrap them in an Arc or Rc.
+impl<K: PartialOrd + Clone, V: Clone> BTree<K, V> {
+    /// Builds a balanced tree in O(n) if the keys are strictly ascending.
+    /// Otherwise the entries are inserted one by one, later entries replace earlier ones.
+    pub fn from_sorted(entries: Vec<(K, V)>) -> BTree<K, V> {
+        if entries.windows(2).all(|pair| pair[0].0 < pair[1].0) {
+            let len = entries.len();
+            BTree {
+                root: BTreeNode::build_sorted(&mut entries.into_iter(), len),
+                metrics: Metrics::default(),
+            }
+        } else {
+            entries.into_iter().collect()
+        }
+    }
+
+    pub fn insert_rec(&mut self, key: K, value: V) -> Option<V> {
+        let mut depth = 0;
+        let new_node = BTreeNode::new(key, value);
+        let res = if let Some(node) = &mut self.root {
+            Arc::make_mut(node).insert_node_rec(new_node, &mut depth)
+        } else {
+            self.root = Some(Arc::new(new_node));
+            None
+        };
+        self.metrics.record_search(depth);
+        res
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let mut depth = 0;
+        let res = if let Some(node) = &mut self.root {
+            let mut curr = Arc::make_mut(node);
+            loop {
+                depth += 1;
+                if key < curr.key {
+                    match &mut curr.smaller {
+                        Some(node) => curr = Arc::make_mut(node),
+                        smaller @ None => {
+                            *smaller = Some(Arc::new(BTreeNode::new(key, value)));
+                            break None;
+                        }
+                    }
+                } else if key > curr.key {
+                    match &mut curr.larger {
+                        Some(node) => curr = Arc::make_mut(node),
+                        larger @ None => {
+                            *larger = Some(Arc::new(BTreeNode::new(key, value)));
+                            break None;
+                        }
+                    }
+                } else {
+                    break Some(core::mem::replace(&mut curr.value, value));
+                }
+            }
+        } else {
+            self.root = Some(Arc::new(BTreeNode::new(key, value)));
+            None
+        };
+        self.metrics.record_search(depth);
+        res
+    }
+
+    /// Rebalances the tree in place with Day-Stout-Warren, in O(n) time and O(1) extra
+    /// space. All levels but the deepest one are complete afterwards.
+    pub fn rebalance(&mut self) {
+        self.rebalance_len();
+    }
+
+    /// rebalances the tree and returns the number of entries
+    pub(crate) fn rebalance_len(&mut self) -> usize {
+        let len = BTreeNode::tree_to_vine(&mut self.root);
+        // the nodes beyond the largest perfect tree go to the deepest level first
+        let perfect = (1usize << (usize::BITS - (len + 1).leading_zeros() - 1)) - 1;
+        BTreeNode::compress(&mut self.root, len - perfect);
+        let mut size = perfect;
+        while size > 1 {
+            size /= 2;
+            BTreeNode::compress(&mut self.root, size);
+        }
+        len
+    }
+
+    pub fn find_mut(&mut self, key: &K) -> Option<&mut V> {
+        // search first, a miss must not copy the path shared with a clone
+        self.find(key)?;
+        let mut curr = Arc::make_mut(self.root.as_mut()?);
+        loop {
+            let link = if *key < curr.key {
+                &mut curr.smaller
+            } else if *key > curr.key {
+                &mut curr.larger
+            } else {
+                return Some(&mut curr.value);
+            };
+            curr = Arc::make_mut(link.as_mut().expect("unexpected missing key"));
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        // search first, a miss must not copy the path shared with a clone
+        self.find(key)?;
+        if let Some(true) = self.root.as_ref().map(|root| root.key == *key) {
+            // delete the root
+            let mut root = self.root.take().expect("unexpected empty link");
+            let (res, new_root) = if root.smaller.is_some() && root.larger.is_some() {
+                // root has two siblings - swap root with next larger, delete next larger
+                let root_node = Arc::make_mut(&mut root);
+                let (key, value) = root_node.remove_next_larger();
+                root_node.key = key;
+                let res = core::mem::replace(&mut root_node.value, value);
+                (Some(res), Some(root))
+            } else {
+                let mut root = Arc::unwrap_or_clone(root);
+                if root.smaller.is_some() {
+                    // root becomes root.smaller
+                    (Some(root.value), root.smaller.take())
+                } else if root.larger.is_some() {
+                    // root becomes root.larger
+                    (Some(root.value), root.larger.take())
+                } else {
+                    // the tree is empty
+                    (Some(root.value), None)
+                }
+            };
+            if new_root.is_some() {
+                self.root = new_root;
+            }
+            res
+        } else if let Some(root) = &mut self.root {
+            Arc::make_mut(root).remove(key)
+        } else {
+            None
+        }
+    }
+}
+
+impl<K: PartialOrd, V> Clone for BTree<K, V> {
+    /// the clone shares all nodes with self, nodes are copied when either tree modifies them
+    fn clone(&self) -> Self {
+        BTree {
+            root: self.root.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+/// ascending iterator over the entries of a BTree
+pub struct Iter<'a, K: PartialOrd, V> {
+    // nodes still to be visited, the next one on top
+    stack: Vec<&'a BTreeNode<K, V>>,
+}
+
+impl<'a, K: PartialOrd, V> Iter<'a, K, V> {
+    fn push_smaller(&mut self, mut node: Option<&'a BTreeNode<K, V>>) {
+        while let Some(curr) = node {
+            self.stack.push(curr);
+            node = curr.smaller.as_deref();
+        }
+    }
+}
+
+impl<'a, K: PartialOrd, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_smaller(node.larger.as_deref());
+        Some((&node.key, &node.value))
+    }
+}
+
+/// moving iterator over the entries of a BTree in ascending order,
+/// nodes still shared with a clone are copied
+pub struct IntoIter<K: PartialOrd, V> {
+    stack: Vec<BTreeNode<K, V>>,
+}
+
+impl<K: PartialOrd + Clone, V: Clone> IntoIter<K, V> {
+    fn push_smaller(&mut self, mut link: SubNode<K, V>) {
+        while let Some(node) = link {
+            let mut node = Arc::unwrap_or_clone(node);
+            link = node.smaller.take();
+            self.stack.push(node);
+        }
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut node = self.stack.pop()?;
+        self.push_smaller(node.larger.take());
+        Some((node.key, node.value))
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> IntoIterator for BTree<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(mut self) -> IntoIter<K, V> {
+        let mut iter = IntoIter { stack: Vec::new() };
+        iter.push_smaller(self.root.take());
+        iter
+    }
+}
+
+impl<'a, K: PartialOrd, V> IntoIterator for &'a BTree<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+// equality, ordering and hashing compare the entries in ascending order, not the shape
+
+impl<K: PartialOrd, V: PartialEq> PartialEq for BTree<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl<K: PartialOrd + Eq, V: Eq> Eq for BTree<K, V> {}
+
+impl<K: PartialOrd, V: PartialOrd> PartialOrd for BTree<K, V> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+
+impl<K: Ord, V: Ord> Ord for BTree<K, V> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl<K: PartialOrd + Hash, V: Hash> Hash for BTree<K, V> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut len = 0;
+        for entry in self.iter() {
+            entry.hash(state);
+            len += 1;
+        }
+        state.write_usize(len);
+    }
+}
+
+impl<K: PartialOrd, V> Index<&K> for BTree<K, V> {
+    type Output = V;
+
+    fn index(&self, key: &K) -> &V {
+        self.find(key).expect("key not found in BTree")
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> Extend<(K, V)> for BTree<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> FromIterator<(K, V)> for BTree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tree = BTree::new();
+        tree.extend(iter);
+        tree
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone, const N: usize> From<[(K, V); N]> for BTree<K, V> {
+    fn from(entries: [(K, V); N]) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> From<Vec<(K, V)>> for BTree<K, V> {
+    fn from(entries: Vec<(K, V)>) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> From<BTreeMap<K, V>> for BTree<K, V> {
+    fn from(map: BTreeMap<K, V>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: PartialOrd + Clone, V: Clone, S> From<HashMap<K, V, S>> for BTree<K, V> {
+    fn from(map: HashMap<K, V, S>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> From<BTree<K, V>> for Vec<(K, V)> {
+    fn from(tree: BTree<K, V>) -> Self {
+        tree.into_iter().collect()
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> From<BTree<K, V>> for BTreeMap<K, V> {
+    fn from(tree: BTree<K, V>) -> Self {
+        tree.into_iter().collect()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: PartialOrd + Eq + Hash + Clone, V: Clone, S: BuildHasher + Default> From<BTree<K, V>>
+    for HashMap<K, V, S>
+{
+    fn from(tree: BTree<K, V>) -> Self {
+        tree.into_iter().collect()
+    }
 }
 
 impl<K: PartialOrd + Debug, V: Debug> Debug for BTree<K, V> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        if let Some(root) = &self.root {
-            write!(f, "{}", root.to_string())
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: PartialOrd + Debug, V: Debug> Display for Pretty<'_, BTree<K, V>> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        if let Some(root) = &self.tree.root {
+            let mut buffer = String::new();
+            let mut layout = Layout::new(&self.options);
+            root.to_str_buffer(&mut buffer, "", true, false, 0, &mut layout);
+            write!(f, "{}", buffer)
         } else {
             write!(f, "nil")
         }
@@ -296,6 +639,7 @@ impl<K: PartialOrd + Debug, V: Debug> Debug for BTree<K, V> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::tree::test_map::{self, test_map_impl};
     use rand::Rng;
 
     #[test]
@@ -363,7 +707,7 @@ mod test {
         for value in values {
             assert_eq!(tree.insert(value, value.to_string()), None);
         }
-        eprintln!("{:?}\n", &tree);
+        eprintln!("{}\n", tree.pretty());
 
         for value in values {
             assert_eq!(tree.remove(&value), Some(value.to_string()));
@@ -473,7 +817,7 @@ mod test {
         let mut iter = values.iter();
         let mut key = iter.next().expect("No test cases found");
         for val in iter {
-            if let Some((lkey, lval)) = tree.smaller(&key) {
+            if let Some((lkey, lval)) = tree.smaller(key) {
                 eprintln!("looking for smaller than {:?}, got {:?}", key, lkey);
                 assert_eq!(val, lkey);
                 assert_eq!(val.to_string(), *lval);
@@ -484,7 +828,7 @@ mod test {
             }
         }
 
-        assert_eq!(tree.smaller(&key), None);
+        assert_eq!(tree.smaller(key), None);
 
         for val in values {
             if let Some((lkey, lval)) = tree.smaller(&(val + 1)) {
@@ -511,7 +855,7 @@ mod test {
         let mut iter = values.iter();
         let mut key = iter.next().expect("empty test array");
         for val in iter {
-            if let Some((lkey, lval)) = tree.larger(&key) {
+            if let Some((lkey, lval)) = tree.larger(key) {
                 eprintln!("looking for smaller than {:?}, got {:?}", key, lkey);
                 assert_eq!(val, lkey);
                 assert_eq!(val.to_string(), *lval);
@@ -522,7 +866,7 @@ mod test {
             }
         }
 
-        assert_eq!(tree.larger(&key), None);
+        assert_eq!(tree.larger(key), None);
 
         for val in values {
             if let Some((lkey, lval)) = tree.larger(&(val - 1)) {
@@ -546,9 +890,233 @@ mod test {
             assert_eq!(tree.insert(value, value.to_string()), None);
         }
         for val in values {
-            assert_eq!(tree.contains(&val), true);
+            assert!(tree.contains(&val));
+        }
+        assert!(!tree.contains(&100));
+    }
+
+    #[test]
+    fn bt_test_iter() {
+        let mut values = [10u32, 20, 5, 15, 25, 3, 8];
+
+        let mut tree: BTree<u32, String> = BTree::new();
+        assert_eq!(tree.iter().next(), None);
+        for value in values {
+            assert_eq!(tree.insert(value, value.to_string()), None);
+        }
+
+        values.sort();
+        let entries: Vec<(u32, String)> = tree
+            .iter()
+            .map(|(key, value)| (*key, value.clone()))
+            .collect();
+        let expected: Vec<(u32, String)> = values.iter().map(|v| (*v, v.to_string())).collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn bt_test_std_traits() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let tree = BTree::from([(3u32, "c"), (1, "a"), (2, "b")]);
+        // same content, different insertion order and shape
+        let mut other: BTree<u32, &str> = BTree::from([(1, "a"), (2, "b")]);
+        other.extend([(3, "c")]);
+        assert_eq!(tree, other);
+
+        let hash = |tree: &BTree<u32, &str>| {
+            let mut hasher = DefaultHasher::new();
+            tree.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&tree), hash(&other));
+
+        other.insert(4, "d");
+        assert_ne!(tree, other);
+        assert!(tree < other);
+        assert_eq!(tree.cmp(&other), Ordering::Less);
+        assert!(BTree::from([(1u32, "z")]) > other);
+
+        assert_eq!(tree[&2], "b");
+
+        let vec: Vec<(u32, &str)> = tree.clone().into();
+        assert_eq!(vec, vec![(1, "a"), (2, "b"), (3, "c")]);
+        assert_eq!(BTree::from(vec.clone()), tree);
+
+        let map: BTreeMap<u32, &str> = tree.clone().into();
+        assert_eq!(map.into_iter().collect::<Vec<_>>(), vec);
+        #[cfg(feature = "std")]
+        {
+            let map: std::collections::HashMap<u32, &str> = tree.clone().into();
+            assert_eq!(BTree::from(map), tree);
+        }
+
+        let keys: Vec<u32> = (&tree).into_iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn bt_test_pretty() {
+        let tree = BTree::from([(2u32, 20u32), (1, 10), (4, 40), (3, 30)]);
+        assert_eq!(format!("{:?}", tree), "{1: 10, 2: 20, 3: 30, 4: 40}");
+        assert_eq!(
+            tree.pretty().to_string(),
+            "(2,20)
+ ├─<(4,40)
+ │   ├─<nil
+ │   └─>(3,30)
+ └─>(1,10)
+"
+        );
+        assert_eq!(
+            tree.pretty().max_depth(1).ascii().hide_values().to_string(),
+            "(2)
+ +-<(4)
+ |   `->...
+ `->(1)
+"
+        );
+    }
+
+    #[test]
+    fn bt_test_dot() {
+        let mut tree = BTree::new();
+        // a degenerated tree, the search path runs through all nodes
+        for key in 0..1000u32 {
+            tree.insert(key, ());
+        }
+        let dot = tree.to_dot_with(&DotOptions::new().search_path(999));
+        assert_eq!(dot.matches(" -> ").count(), 999);
+        assert_eq!(dot.matches("color=gold").count(), 2 * 1000 - 1);
+
+        let tree = BTree::from([(2u32, 20u32), (1, 10), (3, 30)]);
+        assert_eq!(
+            tree.to_dot(),
+            r#"digraph BTree {
+    node [style=filled, fontname="monospace"];
+    n0 [label="2\n20", fillcolor=lightgrey, fontcolor=black];
+    n0 -> n1 [label="<"];
+    n0 -> n2 [label=">"];
+    n2 [label="3\n30", fillcolor=lightgrey, fontcolor=black];
+    n1 [label="1\n10", fillcolor=lightgrey, fontcolor=black];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn bt_test_from_sorted() {
+        let entries: Vec<(u32, ())> = (0..1000).map(|key| (key, ())).collect();
+        let tree = BTree::from_sorted(entries.clone());
+        assert_eq!(tree.shape_report().height, 10);
+        assert_eq!(
+            tree.iter().map(|(key, _)| *key).collect::<Vec<_>>(),
+            (0..1000).collect::<Vec<_>>()
+        );
+
+        let tree = BTree::from_sorted(vec![(2, 'a'), (1, 'b'), (2, 'c')]);
+        assert_eq!(
+            tree.iter().collect::<Vec<_>>(),
+            vec![(&1, &'b'), (&2, &'c')]
+        );
+    }
+
+    #[test]
+    fn bt_test_diagram() {
+        let tree = BTree::from([(2u32, 20u32), (1, 10), (4, 40), (3, 30)]);
+        let copy = BTree::<u32, u32>::from_diagram(&tree.pretty().to_string())
+            .expect("failed to parse diagram");
+        assert_eq!(copy.to_shape(), tree.to_shape());
+
+        // color markers are ignored
+        let tree = BTree::<String, u32>::from_diagram(
+            r#"B("b",2)
+ ├─<R("c",3)
+ └─>R("a",1)"#,
+        )
+        .expect("failed to parse diagram");
+        assert_eq!(tree.to_shape(), "Nb=2 Na=1 . . Nc=3 . .");
+    }
+
+    #[test]
+    fn bt_test_shape() {
+        let tree = BTree::from([(2u32, "b c".to_string()), (1, "a=1".to_string())]);
+        assert_eq!(tree.to_shape(), "N2=b%20c N1=a%3D1 . . .");
+        let copy = BTree::<u32, String>::from_shape(&tree.to_shape()).expect("failed to decode");
+        assert_eq!(copy, tree);
+
+        // a degenerated tree is encoded and decoded without recursion
+        let mut tree = BTree::new();
+        for key in 0..1000u32 {
+            tree.insert(key, key);
         }
-        assert_eq!(tree.contains(&100), false);
+        let copy = BTree::<u32, u32>::from_shape(&tree.to_shape()).expect("failed to decode");
+        assert_eq!(copy.shape_report(), tree.shape_report());
+        assert!(BTree::<u32, u32>::from_shape("B1=1 . .").is_err());
+    }
+
+    #[test]
+    fn bt_test_shape_report() {
+        let tree = BTree::from([(4u32, ()), (2, ()), (6, ()), (1, ()), (3, ()), (7, ())]);
+        assert_eq!(
+            tree.shape_report(),
+            ShapeReport {
+                height: 3,
+                black_height: None,
+                nodes: 6,
+                per_depth: vec![1, 2, 3],
+            }
+        );
+
+        let mut tree = BTree::new();
+        for key in 0..1000u32 {
+            tree.insert(key, ());
+        }
+        let report = tree.shape_report();
+        assert_eq!(report.height, 1000);
+        assert_eq!(report.per_depth, vec![1; 1000]);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn bt_test_stats() {
+        let mut tree = BTree::new();
+        for key in 0..10u32 {
+            tree.insert(key, ());
+        }
+        assert_eq!(tree.find(&9), Some(&()));
+        assert_eq!(tree.remove(&20), None);
+        let stats = tree.stats();
+        assert_eq!(stats.searches, 12);
+        // the chain is searched to its end by inserts, find and remove
+        assert_eq!(stats.comparisons, (0..10).sum::<u64>() + 10 + 10);
+        assert_eq!(stats.max_search_depth, 10);
+        assert_eq!(stats.left_rotations + stats.right_rotations, 0);
+        assert_eq!(stats.recolors, 0);
+    }
+
+    fn check(tree: &BTree<u32, u32>) {
+        let keys: Vec<&u32> = tree.iter().map(|(key, _)| key).collect();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    test_map_impl!(BTree<u32, u32>, check);
+
+    fn node_ptrs<K: PartialOrd, V>(link: &SubNode<K, V>, ptrs: &mut Vec<usize>) {
+        if let Some(node) = link {
+            ptrs.push(Arc::as_ptr(node) as usize);
+            node_ptrs(&node.smaller, ptrs);
+            node_ptrs(&node.larger, ptrs);
+        }
+    }
+
+    #[test]
+    fn bt_test_clone() {
+        test_map::check_clone(|tree: &BTree<u32, u32>| {
+            let mut ptrs = Vec::new();
+            node_ptrs(&tree.root, &mut ptrs);
+            ptrs
+        });
     }
 
     #[test]
@@ -570,4 +1138,30 @@ mod test {
             assert_eq!(value, &xpctd_key.to_string());
         });
     }
+
+    #[test]
+    fn bt_test_rebalance() {
+        for len in 0..70u32 {
+            // ascending inserts leave a chain along the larger links
+            let mut tree: BTree<u32, u32> = (0..len).map(|key| (key, key)).collect();
+            let chain = tree.clone();
+            tree.rebalance();
+            let report = tree.shape_report();
+            assert_eq!(report.height, (u32::BITS - len.leading_zeros()) as usize);
+            // all levels but the deepest are complete
+            for (depth, count) in report.per_depth.iter().enumerate().rev().skip(1) {
+                assert_eq!(*count, 1 << depth);
+            }
+            assert!(tree.iter().eq(chain.iter()));
+            assert_eq!(chain.shape_report().height, len as usize);
+        }
+
+        let mut tree: BTree<u32, u32> = [5, 1, 9, 3, 7, 2, 8, 4, 6, 0]
+            .into_iter()
+            .map(|key| (key, key))
+            .collect();
+        tree.rebalance();
+        assert_eq!(tree.shape_report().height, 4);
+        assert!(tree.iter().map(|(key, _)| *key).eq(0..10));
+    }
 }
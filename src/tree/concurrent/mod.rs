@@ -0,0 +1,200 @@
+use crate::tree::rb_tree::RBTree;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex, RwLock};
+
+pub use sharded::ShardedRBTree;
+
+mod sharded;
+
+/// A read only view of a ConcurrentRBTree at one point in time.
+/// Holding or iterating a snapshot never blocks writers.
+pub type Snapshot<K, V> = Arc<RBTree<K, V>>;
+
+/// An RBTree shared between threads.
+///
+/// Readers work on snapshots: the current tree is kept in an Arc that is swapped
+/// atomically by writers, so a reader sees a consistent state for as long as it
+/// holds its snapshot. Writers are serialized, each write clones the current tree,
+/// modifies the clone and publishes it as the new current tree. As clones share
+/// their nodes, a write only copies the nodes it touches.
+pub struct ConcurrentRBTree<K: PartialOrd, V> {
+    current: RwLock<Snapshot<K, V>>,
+    writer: Mutex<()>,
+}
+
+impl<K: PartialOrd + Debug + Clone, V: Debug + Clone> Default for ConcurrentRBTree<K, V> {
+    fn default() -> Self {
+        ConcurrentRBTree::new()
+    }
+}
+
+impl<K: PartialOrd + Debug + Clone, V: Debug + Clone> ConcurrentRBTree<K, V> {
+    pub fn new() -> ConcurrentRBTree<K, V> {
+        ConcurrentRBTree::from_tree(RBTree::new())
+    }
+
+    pub fn from_tree(tree: RBTree<K, V>) -> ConcurrentRBTree<K, V> {
+        ConcurrentRBTree {
+            current: RwLock::new(Arc::new(tree)),
+            writer: Mutex::new(()),
+        }
+    }
+
+    /// the current state of the tree, unaffected by later writes
+    pub fn snapshot(&self) -> Snapshot<K, V> {
+        self.current
+            .read()
+            .expect("concurrent tree lock poisoned")
+            .clone()
+    }
+
+    /// apply func to a copy of the current tree and publish the result,
+    /// use this to make several changes visible to readers at once
+    pub fn update<R>(&self, func: impl FnOnce(&mut RBTree<K, V>) -> R) -> R {
+        let _guard = self.writer.lock().expect("concurrent tree lock poisoned");
+        let mut tree = RBTree::clone(&self.snapshot());
+        let res = func(&mut tree);
+        *self.current.write().expect("concurrent tree lock poisoned") = Arc::new(tree);
+        res
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.update(|tree| tree.insert(key, value))
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.update(|tree| tree.remove(key))
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.snapshot().contains(key)
+    }
+
+    pub fn find(&self, key: &K) -> Option<V> {
+        self.snapshot().find(key).cloned()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshot().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::Rng;
+    use std::collections::BTreeMap;
+    use std::thread;
+
+    #[test]
+    fn ct_test_snapshot() {
+        let tree = ConcurrentRBTree::new();
+        for val in 1..=10u32 {
+            assert_eq!(tree.insert(val, val.to_string()), None);
+        }
+
+        let snapshot = tree.snapshot();
+        let mut iter = snapshot.iter();
+        assert_eq!(iter.next(), Some((&1, &1.to_string())));
+
+        // writers are not blocked by the iterator and it does not see their changes
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for val in 11..=20u32 {
+                    assert_eq!(tree.insert(val, val.to_string()), None);
+                }
+                assert_eq!(tree.remove(&2), Some(2.to_string()));
+            });
+        });
+
+        let rest: Vec<u32> = iter.map(|(key, _)| *key).collect();
+        assert_eq!(rest, (2..=10).collect::<Vec<u32>>());
+        assert!(!tree.contains(&2));
+        assert_eq!(tree.find(&20), Some(20.to_string()));
+        assert_eq!(tree.snapshot().iter().count(), 19);
+    }
+
+    #[test]
+    fn ct_test_update() {
+        let tree = ConcurrentRBTree::new();
+        let count = tree.update(|tree| {
+            for val in 1..=10u32 {
+                tree.insert(val, val);
+            }
+            tree.iter().count()
+        });
+        assert_eq!(count, 10);
+        assert_eq!(tree.find(&5), Some(5));
+    }
+
+    #[test]
+    fn ct_test_stress() {
+        const WRITERS: u32 = 4;
+        const READERS: usize = 4;
+        const OPS: usize = 1000;
+        const RANGE: u32 = 200;
+
+        let tree: ConcurrentRBTree<u32, u32> = ConcurrentRBTree::new();
+
+        let models = thread::scope(|scope| {
+            for _ in 0..READERS {
+                scope.spawn(|| {
+                    let mut rng = rand::thread_rng();
+                    for _ in 0..OPS / 4 {
+                        let snapshot = tree.snapshot();
+                        if let Err(msg) = snapshot.check_rules() {
+                            panic!("tree rule violation in snapshot: {}", msg);
+                        }
+                        let keys: Vec<u32> = snapshot.iter().map(|(key, _)| *key).collect();
+                        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+                        for (key, value) in snapshot.iter() {
+                            assert_eq!(*value, *key * 2);
+                        }
+                        // the snapshot must not change while writers continue
+                        let key = rng.gen_range(0..WRITERS * RANGE);
+                        let found = snapshot.contains(&key);
+                        thread::yield_now();
+                        assert_eq!(snapshot.contains(&key), found);
+                        assert_eq!(snapshot.iter().count(), keys.len());
+                    }
+                });
+            }
+
+            let writers: Vec<_> = (0..WRITERS)
+                .map(|writer| {
+                    let tree = &tree;
+                    scope.spawn(move || {
+                        // every writer owns its own key range and keeps a model of it
+                        let mut rng = rand::thread_rng();
+                        let mut model = BTreeMap::new();
+                        for _ in 0..OPS {
+                            let key = writer * RANGE + rng.gen_range(0..RANGE);
+                            if rng.gen_bool(0.6) {
+                                assert_eq!(tree.insert(key, key * 2), model.insert(key, key * 2));
+                            } else {
+                                assert_eq!(tree.remove(&key), model.remove(&key));
+                            }
+                        }
+                        model
+                    })
+                })
+                .collect();
+
+            writers
+                .into_iter()
+                .map(|handle| handle.join().expect("writer failed"))
+                .collect::<Vec<_>>()
+        });
+
+        let expected: Vec<u32> = models
+            .iter()
+            .flat_map(|model| model.keys().copied())
+            .collect();
+        let snapshot = tree.snapshot();
+        let keys: Vec<u32> = snapshot.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys, expected);
+        if let Err(msg) = snapshot.check_rules() {
+            panic!("tree rule violation: {}", msg);
+        }
+    }
+}
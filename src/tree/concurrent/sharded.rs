@@ -0,0 +1,380 @@
+use crate::tree::rb_tree::RBTree;
+use std::fmt::Debug;
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+const DEFAULT_WINDOW: usize = 4096;
+const DEFAULT_WAYS: usize = 16;
+
+struct ShardTree<K: PartialOrd, V> {
+    tree: RBTree<K, V>,
+    // inserts and removes since the last look for hot shards
+    heat: usize,
+}
+
+struct Shard<K: PartialOrd, V> {
+    // smallest key that belongs to this shard, None for the first shard
+    lower: Option<K>,
+    tree: RwLock<ShardTree<K, V>>,
+}
+
+/// An ordered map partitioned by key range into RBTree shards with a lock each.
+///
+/// Writers to different shards never wait for each other. Every shard counts the
+/// inserts and removes it takes. After each window of writes to the map, a shard that
+/// took more than 1 / ways of them is split in two at its median key, so the hot key
+/// ranges end up spread over more shards and locks. Evenly spread writes settle at
+/// about ways shards.
+///
+/// Iteration and range queries visit the shards in key order, each shard is
+/// locked only while its entries are copied, so they do not see a consistent state
+/// of the whole map while writers are active.
+pub struct ShardedRBTree<K: PartialOrd, V> {
+    // ordered by lower bound, the write lock is only taken to split shards
+    shards: RwLock<Vec<Shard<K, V>>>,
+    // writes to the map since the last look for hot shards
+    writes: AtomicUsize,
+    window: usize,
+    ways: usize,
+}
+
+impl<K: PartialOrd + Debug + Clone, V: Debug + Clone> Default for ShardedRBTree<K, V> {
+    fn default() -> Self {
+        ShardedRBTree::new()
+    }
+}
+
+impl<K: PartialOrd + Debug + Clone, V: Debug + Clone> ShardedRBTree<K, V> {
+    /// start with a single shard, more shards are created by splitting
+    pub fn new() -> ShardedRBTree<K, V> {
+        ShardedRBTree::with_bounds(Vec::new())
+    }
+
+    /// start with one shard per range between the given ascending split keys
+    pub fn with_bounds(bounds: Vec<K>) -> ShardedRBTree<K, V> {
+        let shards = std::iter::once(None)
+            .chain(bounds.into_iter().map(Some))
+            .map(Shard::new)
+            .collect::<Vec<_>>();
+        assert!(
+            shards
+                .windows(2)
+                .all(|pair| pair[0].lower.is_none() || pair[0].lower < pair[1].lower),
+            "shard bounds must be strictly ascending"
+        );
+        ShardedRBTree {
+            shards: RwLock::new(shards),
+            writes: AtomicUsize::new(0),
+            window: DEFAULT_WINDOW,
+            ways: DEFAULT_WAYS,
+        }
+    }
+
+    /// After every window writes to the map, split the shards that took more than
+    /// 1 / ways of them. The defaults are 4096 and 16.
+    pub fn with_hot_splits(mut self, window: usize, ways: usize) -> ShardedRBTree<K, V> {
+        assert!(window > 0, "window must be at least 1");
+        assert!(ways > 1, "ways must be at least 2");
+        self.window = window;
+        self.ways = ways;
+        self
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        let res = {
+            let shards = self.shards.read().expect("shard list lock poisoned");
+            let index = Self::shard_index(&shards, &key);
+            let mut shard = shards[index].write();
+            let res = shard.tree.insert(key, value);
+            shard.heat += 1;
+            res
+        };
+        self.count_write();
+        res
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let res = {
+            let shards = self.shards.read().expect("shard list lock poisoned");
+            let mut shard = shards[Self::shard_index(&shards, key)].write();
+            let res = shard.tree.remove(key);
+            shard.heat += 1;
+            res
+        };
+        self.count_write();
+        res
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        let shards = self.shards.read().expect("shard list lock poisoned");
+        let shard = shards[Self::shard_index(&shards, key)].read();
+        shard.tree.contains(key)
+    }
+
+    pub fn find(&self, key: &K) -> Option<V> {
+        let shards = self.shards.read().expect("shard list lock poisoned");
+        let shard = shards[Self::shard_index(&shards, key)].read();
+        shard.tree.find(key).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        let shards = self.shards.read().expect("shard list lock poisoned");
+        shards.iter().map(|shard| shard.read().tree.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.read().expect("shard list lock poisoned").len()
+    }
+
+    /// call func for all entries in ascending key order
+    pub fn traverse_asc(&self, func: &mut dyn FnMut(&K, &V)) {
+        self.range_asc(.., func)
+    }
+
+    /// Calls func for the entries with keys in range in ascending key order.
+    /// The entries are copied first and func runs without any lock held, so it may
+    /// write to the map, the writes do not show in this traversal.
+    pub fn range_asc<R: RangeBounds<K>>(&self, range: R, func: &mut dyn FnMut(&K, &V)) {
+        for (key, value) in self.range(range) {
+            func(&key, &value);
+        }
+    }
+
+    /// copies of the entries with keys in range in ascending key order
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Vec<(K, V)> {
+        let mut res = Vec::new();
+        let shards = self.shards.read().expect("shard list lock poisoned");
+        let first = match range.start_bound() {
+            Bound::Included(start) | Bound::Excluded(start) => Self::shard_index(&shards, start),
+            Bound::Unbounded => 0,
+        };
+        for shard in &shards[first..] {
+            let beyond_end = match (&shard.lower, range.end_bound()) {
+                (Some(lower), Bound::Included(end)) => *lower > *end,
+                (Some(lower), Bound::Excluded(end)) => *lower >= *end,
+                _ => false,
+            };
+            if beyond_end {
+                break;
+            }
+            res.extend(
+                shard
+                    .read()
+                    .tree
+                    .range((range.start_bound(), range.end_bound()))
+                    .map(|(key, value)| (key.clone(), value.clone())),
+            );
+        }
+        res
+    }
+
+    fn shard_index(shards: &[Shard<K, V>], key: &K) -> usize {
+        // the first shard has no lower bound, so the partition point is at least 1
+        shards.partition_point(|shard| match &shard.lower {
+            Some(lower) => *lower <= *key,
+            None => true,
+        }) - 1
+    }
+
+    /// counts one write to the map, the writer that completes a window splits
+    fn count_write(&self) {
+        if self.writes.fetch_add(1, Ordering::Relaxed) + 1 == self.window {
+            self.split_hot_shards();
+        }
+    }
+
+    fn split_hot_shards(&self) {
+        let mut shards = self.shards.write().expect("shard list lock poisoned");
+        // the writes that came in while we were waiting for the lock are in the
+        // heats already
+        self.writes.store(0, Ordering::Relaxed);
+        let heats: Vec<usize> = shards
+            .iter()
+            .map(|shard| std::mem::take(&mut shard.write().heat))
+            .collect();
+        let total: usize = heats.iter().sum();
+        // walk backwards so the indexes of the shards still to visit stay valid
+        for (index, heat) in heats.into_iter().enumerate().rev() {
+            if heat * self.ways > total && shards[index].read().tree.len() > 1 {
+                let upper = shards[index].split();
+                shards.insert(index + 1, upper);
+            }
+        }
+    }
+}
+
+impl<K: PartialOrd + Debug + Clone, V: Debug + Clone> Shard<K, V> {
+    fn new(lower: Option<K>) -> Shard<K, V> {
+        Shard {
+            lower,
+            tree: RwLock::new(ShardTree {
+                tree: RBTree::new(),
+                heat: 0,
+            }),
+        }
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, ShardTree<K, V>> {
+        self.tree.read().expect("shard lock poisoned")
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, ShardTree<K, V>> {
+        self.tree.write().expect("shard lock poisoned")
+    }
+
+    /// Moves the upper half of the entries into a new shard. Both halves are built
+    /// from the sorted entries in O(n), the shard needs at least 2 entries.
+    fn split(&self) -> Shard<K, V> {
+        let mut shard = self.write();
+        let mut lower: Vec<(K, V)> = std::mem::take(&mut shard.tree).into();
+        let upper = lower.split_off(lower.len() / 2);
+        let res = Shard::new(Some(upper[0].0.clone()));
+        let len = upper.len();
+        res.write().tree = RBTree::build_sorted(upper.into_iter(), len);
+        let len = lower.len();
+        shard.tree = RBTree::build_sorted(lower.into_iter(), len);
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::Rng;
+    use std::collections::BTreeMap;
+    use std::thread;
+
+    #[test]
+    fn st_test_bounds() {
+        let tree = ShardedRBTree::with_bounds(vec![100u32, 200, 300]);
+        assert_eq!(tree.shard_count(), 4);
+        for val in (0..400).step_by(10) {
+            assert_eq!(tree.insert(val, val.to_string()), None);
+        }
+        assert_eq!(tree.len(), 40);
+        assert_eq!(tree.find(&200), Some(200.to_string()));
+        assert_eq!(tree.remove(&200), Some(200.to_string()));
+        assert!(!tree.contains(&200));
+        assert_eq!(tree.len(), 39);
+
+        let keys: Vec<u32> = tree.range(90..=210).into_iter().map(|(k, _)| k).collect();
+        assert_eq!(
+            keys,
+            vec![90, 100, 110, 120, 130, 140, 150, 160, 170, 180, 190, 210]
+        );
+        let keys: Vec<u32> = tree.range(..100).into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, (0..100).step_by(10).collect::<Vec<u32>>());
+
+        let mut all = Vec::new();
+        tree.traverse_asc(&mut |key, _| all.push(*key));
+        assert_eq!(all.len(), 39);
+        assert!(all.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn st_test_split() {
+        let tree = ShardedRBTree::new().with_hot_splits(100, 4);
+        for val in 0..1000u32 {
+            assert_eq!(tree.insert(val * 7 % 1000, val), None);
+        }
+        let shard_lens = |tree: &ShardedRBTree<u32, u32>| -> Vec<usize> {
+            let shards = tree.shards.read().expect("shard list lock poisoned");
+            shards.iter().map(|shard| shard.read().tree.len()).collect()
+        };
+        // the writes are spread over the keys, the shards keep splitting until
+        // none takes more than a quarter of them
+        let before = shard_lens(&tree);
+        assert!((4..=8).contains(&before.len()));
+
+        // writes to 0..10 only split the first shard, down to the hot keys
+        for round in 0..500 {
+            tree.insert(round % 10, round);
+        }
+        let lens = shard_lens(&tree);
+        assert!(lens.len() > before.len() + 3);
+        assert!(lens[0] <= 5);
+        assert_eq!(lens[lens.len() - before.len() + 1..], before[1..]);
+
+        assert_eq!(tree.len(), 1000);
+        let keys: Vec<u32> = tree.range(..).into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, (0..1000).collect::<Vec<u32>>());
+        for val in 10..1000u32 {
+            assert_eq!(tree.find(&val), Some(val * 143 % 1000));
+        }
+    }
+
+    #[test]
+    fn st_test_write_while_traversing() {
+        let tree = ShardedRBTree::with_bounds(vec![10u32]);
+        for val in 0..20u32 {
+            tree.insert(val, val);
+        }
+        // the callback runs without locks, the new entries are not visited
+        let mut visited = 0;
+        tree.traverse_asc(&mut |key, value| {
+            tree.insert(key + 100, *value);
+            visited += 1;
+        });
+        assert_eq!(visited, 20);
+        assert_eq!(tree.len(), 40);
+    }
+
+    #[test]
+    fn st_test_stress() {
+        const WRITERS: u32 = 4;
+        const OPS: usize = 5000;
+        const RANGE: u32 = 1000;
+
+        let tree: ShardedRBTree<u32, u32> = ShardedRBTree::new().with_hot_splits(256, 8);
+
+        let models = thread::scope(|scope| {
+            scope.spawn(|| {
+                for _ in 0..100 {
+                    let entries = tree.range(..);
+                    assert!(entries.windows(2).all(|pair| pair[0].0 < pair[1].0));
+                    assert!(entries.iter().all(|(key, value)| *value == *key * 2));
+                }
+            });
+
+            let writers: Vec<_> = (0..WRITERS)
+                .map(|writer| {
+                    let tree = &tree;
+                    scope.spawn(move || {
+                        // writers share the key space, keys are interleaved by writer
+                        let mut rng = rand::thread_rng();
+                        let mut model = BTreeMap::new();
+                        for _ in 0..OPS {
+                            let key = rng.gen_range(0..RANGE) * WRITERS + writer;
+                            if rng.gen_bool(0.7) {
+                                assert_eq!(tree.insert(key, key * 2), model.insert(key, key * 2));
+                            } else {
+                                assert_eq!(tree.remove(&key), model.remove(&key));
+                            }
+                        }
+                        model
+                    })
+                })
+                .collect();
+
+            writers
+                .into_iter()
+                .map(|handle| handle.join().expect("writer failed"))
+                .collect::<Vec<_>>()
+        });
+
+        let mut expected: Vec<u32> = models
+            .iter()
+            .flat_map(|model| model.keys().copied())
+            .collect();
+        expected.sort();
+        let keys: Vec<u32> = tree.range(..).into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, expected);
+        assert_eq!(tree.len(), expected.len());
+        assert!(tree.shard_count() > 1);
+    }
+}
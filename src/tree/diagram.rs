@@ -0,0 +1,273 @@
+//! Reads back the structural view written by pretty(), as used in the doc comments:
+//!
+//! ```text
+//! B(2,"2")
+//!  ├─<R(4,"4")
+//!  │   ├─<nil
+//!  │   └─>B(3,"3")
+//!  └─>B(1,"1")
+//! ```
+//!
+//! `├─<` leads to the larger and `└─>` to the smaller child, a missing child may be
+//! written as `nil` or left out. The R/B color markers, values and a highlight `*` are
+//! optional, ANSI color codes are ignored, the ASCII junctions `+-<` and `` `-> `` work too.
+//! Keys and values are read with FromStr, Debug quoted strings are unquoted first.
+
+use crate::prelude::*;
+use crate::tree::rb_tree::Color;
+use core::str::FromStr;
+
+/// a node whose children have not all been read
+struct Partial<K, V, T> {
+    depth: usize,
+    /// the node is the larger child of its parent
+    is_larger: bool,
+    color: Option<Color>,
+    key: K,
+    value: V,
+    // a child side is Some once it was read, Some(None) for nil
+    larger: Option<Option<T>>,
+    smaller: Option<Option<T>>,
+}
+
+/// Rebuilds the tree from a diagram, make creates a node from color, key, value
+/// and the smaller and larger subtrees. Hidden values are V::default().
+/// Walks the lines with an explicit stack, degenerated trees do not overflow.
+pub(crate) fn parse<K, V, T>(
+    diagram: &str,
+    mut make: impl FnMut(Option<Color>, K, V, Option<T>, Option<T>) -> Result<T, String>,
+) -> Result<Option<T>, String>
+where
+    K: FromStr,
+    V: FromStr + Default,
+{
+    let mut stack: Vec<Partial<K, V, T>> = Vec::new();
+    let mut root_col = None;
+    let mut root: Option<Option<T>> = None;
+    for (line_no, line) in diagram.lines().enumerate() {
+        let line: Vec<char> = strip_ansi(line).trim_end().chars().collect();
+        let err = |msg: String| format!("line {}: {}", line_no + 1, msg);
+        let Some(start) = line.iter().position(|ch| !matches!(ch, ' ' | '│' | '|')) else {
+            continue;
+        };
+        if root.is_some() {
+            return Err(err("unexpected line after the tree".to_string()));
+        }
+
+        // the depth follows from the column of the junction, the root has none
+        let junction: String = line[start..].iter().take(3).collect();
+        let (depth, is_larger, text) = match (junction.as_str(), root_col) {
+            (_, None) => {
+                root_col = Some(start);
+                (0, false, &line[start..])
+            }
+            ("├─<" | "+-<" | "└─>" | "`->", Some(root_col)) => {
+                let indent = start
+                    .checked_sub(root_col + 1)
+                    .filter(|indent| indent % 4 == 0)
+                    .ok_or_else(|| err("unexpected indentation".to_string()))?;
+                (indent / 4 + 1, junction.ends_with('<'), &line[start + 3..])
+            }
+            (_, Some(_)) => return Err(err("missing junction".to_string())),
+        };
+
+        // nodes at this depth or deeper cannot get more children
+        while stack.last().is_some_and(|node| node.depth >= depth) {
+            root = complete(&mut stack, &mut make).map_err(err)?;
+        }
+        if root.is_some() {
+            return Err(err("unexpected line after the tree".to_string()));
+        }
+        let parent = match stack.last_mut() {
+            Some(parent) if parent.depth + 1 == depth => {
+                let side = if is_larger {
+                    &mut parent.larger
+                } else {
+                    &mut parent.smaller
+                };
+                if side.is_some() {
+                    return Err(err("two children on the same side".to_string()));
+                }
+                Some(side)
+            }
+            None if depth == 0 => None,
+            _ => return Err(err("unexpected indentation".to_string())),
+        };
+
+        let text: String = text.iter().collect();
+        match text.trim_end_matches('*') {
+            "..." => return Err(err("the diagram is truncated".to_string())),
+            "nil" => match parent {
+                Some(side) => *side = Some(None),
+                None => root = Some(None),
+            },
+            text => {
+                let (color, key, value) = parse_node(text).map_err(err)?;
+                stack.push(Partial {
+                    depth,
+                    is_larger,
+                    color,
+                    key,
+                    value,
+                    larger: None,
+                    smaller: None,
+                });
+            }
+        }
+    }
+    while !stack.is_empty() {
+        root = complete(&mut stack, &mut make)?;
+    }
+    root.ok_or_else(|| "empty diagram".to_string())
+}
+
+/// Makes the node on top of the stack and links it to its parent.
+/// Returns the tree once the root was completed.
+fn complete<K, V, T>(
+    stack: &mut Vec<Partial<K, V, T>>,
+    make: &mut impl FnMut(Option<Color>, K, V, Option<T>, Option<T>) -> Result<T, String>,
+) -> Result<Option<Option<T>>, String> {
+    let node = stack.pop().expect("unexpected empty stack");
+    let is_larger = node.is_larger;
+    let node = make(
+        node.color,
+        node.key,
+        node.value,
+        node.smaller.flatten(),
+        node.larger.flatten(),
+    )?;
+    match stack.last_mut() {
+        Some(parent) if is_larger => parent.larger = Some(Some(node)),
+        Some(parent) => parent.smaller = Some(Some(node)),
+        None => return Ok(Some(Some(node))),
+    }
+    Ok(None)
+}
+
+/// parses R(key,value), B(key) or (key,value) and the like
+fn parse_node<K: FromStr, V: FromStr + Default>(
+    text: &str,
+) -> Result<(Option<Color>, K, V), String> {
+    let (color, rest) = match text.chars().next() {
+        Some('R') => (Some(Color::Red), &text[1..]),
+        Some('B') => (Some(Color::Black), &text[1..]),
+        _ => (None, text),
+    };
+    let inner = rest
+        .strip_prefix('(')
+        .and_then(|rest| rest.strip_suffix(')'))
+        .ok_or_else(|| format!("invalid node {}", text))?;
+    let (key, value) = match split_fields(inner) {
+        Some(comma) => (&inner[..comma], Some(&inner[comma + 1..])),
+        None => (inner, None),
+    };
+    let key = unquote(key)
+        .parse()
+        .map_err(|_| format!("invalid key in {}", text))?;
+    let value = match value {
+        Some(value) => unquote(value)
+            .parse()
+            .map_err(|_| format!("invalid value in {}", text))?,
+        None => V::default(),
+    };
+    Ok((color, key, value))
+}
+
+/// the byte index of the comma between key and value, skipping quoted and nested commas
+fn split_fields(text: &str) -> Option<usize> {
+    let mut nesting = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (idx, ch) in text.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            _ if quoted => (),
+            '(' | '[' | '{' => nesting += 1,
+            ')' | ']' | '}' => nesting -= 1,
+            ',' if nesting == 0 => return Some(idx),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// undoes the Debug quoting of strings, other text is returned as is
+fn unquote(text: &str) -> String {
+    let Some(inner) = text
+        .strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+    else {
+        return text.to_string();
+    };
+    let mut res = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            res.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => res.push('\n'),
+            Some('r') => res.push('\r'),
+            Some('t') => res.push('\t'),
+            Some('0') => res.push('\0'),
+            Some('u') => {
+                // \u{hex}
+                let code: String = chars.by_ref().take_while(|ch| *ch != '}').collect();
+                if let Some(ch) = u32::from_str_radix(code.trim_start_matches('{'), 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                {
+                    res.push(ch);
+                }
+            }
+            Some(ch) => res.push(ch),
+            None => res.push('\\'),
+        }
+    }
+    res
+}
+
+/// removes the escape sequences of terminal colors
+fn strip_ansi(line: &str) -> String {
+    let mut res = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\u{1b}' {
+            // ESC [ parameters final byte
+            if chars.next() == Some('[') {
+                for ch in chars.by_ref() {
+                    if ('@'..='~').contains(&ch) {
+                        break;
+                    }
+                }
+            }
+        } else {
+            res.push(ch);
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn diagram_test_fields() {
+        assert_eq!(split_fields(r#"1,"a,b""#), Some(1));
+        assert_eq!(split_fields(r#""a\",b",[1, 2]"#), Some(7));
+        assert_eq!(split_fields("(1, 2)"), None);
+        assert_eq!(unquote(r#""say \"hi\"\n\u{e9}""#), "say \"hi\"\né");
+        assert_eq!(unquote("plain"), "plain");
+        assert_eq!(strip_ansi("\u{1b}[31mR(1)\u{1b}[0m"), "R(1)");
+        assert_eq!(
+            parse_node::<u32, String>(r#"R(4,"4")"#),
+            Ok((Some(Color::Red), 4, "4".to_string()))
+        );
+        assert_eq!(parse_node::<u32, u32>("(4)"), Ok((None, 4, 0)));
+        assert!(parse_node::<u32, u32>("X(4)").is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use crate::prelude::*;
+use crate::tree::rb_tree::Color;
+use crate::tree::TreeNode;
+use core::fmt::Debug;
+
+/// Options for the Graphviz export of a tree
+#[derive(Clone, Debug, PartialEq)]
+pub struct DotOptions<K> {
+    /// draw the empty subtrees as nil leaves
+    pub nil_leaves: bool,
+    /// label the nodes with their keys only
+    pub hide_values: bool,
+    /// outline the nodes with these keys
+    pub highlight: Vec<K>,
+    /// outline the nodes and edges visited when searching for this key
+    pub search_path: Option<K>,
+    /// a caption for the whole graph
+    pub label: Option<String>,
+}
+
+impl<K> Default for DotOptions<K> {
+    fn default() -> Self {
+        DotOptions {
+            nil_leaves: false,
+            hide_values: false,
+            highlight: Vec::new(),
+            search_path: None,
+            label: None,
+        }
+    }
+}
+
+impl<K> DotOptions<K> {
+    pub fn new() -> DotOptions<K> {
+        DotOptions::default()
+    }
+
+    pub fn nil_leaves(mut self) -> Self {
+        self.nil_leaves = true;
+        self
+    }
+
+    pub fn hide_values(mut self) -> Self {
+        self.hide_values = true;
+        self
+    }
+
+    pub fn highlight(mut self, keys: impl IntoIterator<Item = K>) -> Self {
+        self.highlight.extend(keys);
+        self
+    }
+
+    pub fn search_path(mut self, key: K) -> Self {
+        self.search_path = Some(key);
+        self
+    }
+
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+}
+
+const HIGHLIGHT: &str = ", color=gold, penwidth=3";
+
+/// Emits a Graphviz digraph for the tree below root.
+/// The edge to the smaller child is labelled "<" and is emitted first,
+/// so dot places it on the left.
+/// Walks the tree with an explicit stack, degenerated trees do not overflow the call stack.
+pub(crate) fn to_dot<K, V, N>(name: &str, root: Option<&N>, options: &DotOptions<K>) -> String
+where
+    K: PartialOrd + Debug,
+    V: Debug,
+    N: TreeNode<K, V>,
+{
+    let mut buffer = format!(
+        "digraph {} {{\n    node [style=filled, fontname=\"monospace\"];\n",
+        name
+    );
+    if let Some(label) = &options.label {
+        buffer.push_str(&format!(
+            "    label=\"{}\";\n    labelloc=t;\n",
+            escape(label)
+        ));
+    }
+
+    // node, node id, node is on the search path
+    let mut stack = Vec::new();
+    let mut next_id = 0;
+    match root {
+        Some(root) => {
+            stack.push((root, next_id, options.search_path.is_some()));
+            next_id += 1;
+        }
+        None if options.nil_leaves => {
+            push_nil(&mut buffer, next_id, options.search_path.is_some());
+        }
+        None => (),
+    }
+
+    while let Some((node, id, on_path)) = stack.pop() {
+        let label = if options.hide_values {
+            escape(&format!("{:?}", node.key()))
+        } else {
+            let key = escape(&format!("{:?}", node.key()));
+            format!("{}\\n{}", key, escape(&format!("{:?}", node.value())))
+        };
+        let fill = match node.color() {
+            Some(Color::Red) => "fillcolor=red, fontcolor=white",
+            Some(Color::Black) => "fillcolor=black, fontcolor=white",
+            None => "fillcolor=lightgrey, fontcolor=black",
+        };
+        let highlight = on_path || options.highlight.iter().any(|key| *key == *node.key());
+        buffer.push_str(&format!(
+            "    n{} [label=\"{}\", {}{}];\n",
+            id,
+            label,
+            fill,
+            if highlight { HIGHLIGHT } else { "" }
+        ));
+
+        // the search continues into the smaller or larger subtree unless the key was found
+        let (path_smaller, path_larger) = match &options.search_path {
+            Some(key) if on_path => (*key < *node.key(), *key > *node.key()),
+            _ => (false, false),
+        };
+        let children = [
+            (node.smaller(), "<", path_smaller),
+            (node.larger(), ">", path_larger),
+        ];
+        for (child, label, child_on_path) in children {
+            if child.is_none() && !options.nil_leaves {
+                continue;
+            }
+            buffer.push_str(&format!(
+                "    n{} -> n{} [label=\"{}\"{}];\n",
+                id,
+                next_id,
+                label,
+                if child_on_path { HIGHLIGHT } else { "" }
+            ));
+            match child {
+                Some(child) => stack.push((child, next_id, child_on_path)),
+                None => push_nil(&mut buffer, next_id, child_on_path),
+            }
+            next_id += 1;
+        }
+    }
+
+    buffer.push_str("}\n");
+    buffer
+}
+
+fn push_nil(buffer: &mut String, id: usize, highlight: bool) {
+    buffer.push_str(&format!(
+        "    n{} [label=\"nil\", shape=box, fillcolor=black, fontcolor=white, fontsize=8{}];\n",
+        id,
+        if highlight { HIGHLIGHT } else { "" }
+    ));
+}
+
+/// escape a label for a double quoted dot string
+fn escape(label: &str) -> String {
+    let mut res = String::with_capacity(label.len());
+    for ch in label.chars() {
+        match ch {
+            '\\' => res.push_str("\\\\"),
+            '"' => res.push_str("\\\""),
+            '\n' => res.push_str("\\n"),
+            _ => res.push(ch),
+        }
+    }
+    res
+}
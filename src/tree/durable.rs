@@ -0,0 +1,562 @@
+//! An RBTree that survives restarts, every change is appended to a write-ahead log
+//! before it is applied.
+//!
+//! A directory holds the log `wal.log` and the last checkpoint `snapshot.bin`.
+//! The log starts with the magic `RBTWAL\0\0` and a version (4 bytes), followed by frames:
+//!
+//! | field   | size | content                                                    |
+//! |---------|------|------------------------------------------------------------|
+//! | length  | 4    | length of the payload                                      |
+//! | crc     | 4    | CRC-32 (IEEE) of the payload                               |
+//! | payload | ..   | op (1 insert, 2 remove), key length (4), key,              |
+//! |         |      | for inserts value length (4), value                        |
+//!
+//! All integers are little endian. Opening loads the snapshot and replays the log on top,
+//! a torn or corrupted frame ends the log and is cut off. A checkpoint writes a new
+//! snapshot and empties the log. Replaying a log over a snapshot that already contains
+//! its changes gives the same tree, so a crash between the two steps loses nothing.
+
+use crate::tree::rb_tree::RBTree;
+use crate::tree::snapshot::{Codec, Crc32, SnapshotError};
+use std::fmt::{Debug, Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+pub const WAL_FILE: &str = "wal.log";
+pub const SNAPSHOT_FILE: &str = "snapshot.bin";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.tmp";
+
+pub const WAL_MAGIC: [u8; 8] = *b"RBTWAL\0\0";
+pub const WAL_VERSION: u32 = 1;
+const HEADER_LEN: u64 = 12;
+
+const OP_INSERT: u8 = 1;
+const OP_REMOVE: u8 = 2;
+
+/// When the log is flushed to the disk with fsync
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncPolicy {
+    /// after every change, nothing acknowledged is lost
+    Always,
+    /// after this many changes, a crash loses at most the changes since the last sync
+    Every(usize),
+    /// leave it to the operating system, changes survive a crash of the process
+    /// but not of the machine
+    Never,
+}
+
+/// Why the log or the snapshot could not be read or written
+#[derive(Debug)]
+pub enum WalError {
+    Io(std::io::Error),
+    Snapshot(SnapshotError),
+    /// wal.log is not a log of a supported version
+    BadHeader,
+    /// a complete frame with a valid checksum could not be decoded
+    Decode {
+        offset: u64,
+        msg: String,
+    },
+    /// a field or a frame is longer than its 4 byte length can hold
+    TooLong(usize),
+}
+
+impl Display for WalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WalError::Io(err) => write!(f, "log i/o error: {}", err),
+            WalError::Snapshot(err) => write!(f, "{}", err),
+            WalError::BadHeader => write!(f, "not a log or unsupported log version"),
+            WalError::Decode { offset, msg } => {
+                write!(f, "failed to decode the log frame at {}: {}", offset, msg)
+            }
+            WalError::TooLong(len) => write!(f, "{} bytes do not fit in a log frame", len),
+        }
+    }
+}
+
+impl std::error::Error for WalError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WalError::Io(err) => Some(err),
+            WalError::Snapshot(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for WalError {
+    fn from(err: std::io::Error) -> Self {
+        WalError::Io(err)
+    }
+}
+
+impl From<SnapshotError> for WalError {
+    fn from(err: SnapshotError) -> Self {
+        WalError::Snapshot(err)
+    }
+}
+
+/// An RBTree backed by a write-ahead log in a directory
+pub struct DurableRBTree<K: PartialOrd, V> {
+    tree: RBTree<K, V>,
+    dir: PathBuf,
+    wal: File,
+    /// the length of the log up to the last complete frame
+    wal_len: u64,
+    policy: SyncPolicy,
+    /// changes written since the last sync
+    unsynced: usize,
+    /// changes logged since the last checkpoint
+    logged: usize,
+    checkpoint_after: Option<usize>,
+}
+
+impl<K, V> DurableRBTree<K, V>
+where
+    K: PartialOrd + Debug + Clone + Codec,
+    V: Debug + Clone + Codec,
+{
+    /// Opens the tree in dir, the directory is created if it does not exist.
+    /// A torn frame at the end of the log is cut off.
+    pub fn open(
+        dir: impl AsRef<Path>,
+        policy: SyncPolicy,
+    ) -> Result<DurableRBTree<K, V>, WalError> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        // a checkpoint that did not complete, the log still has its changes
+        let _ = std::fs::remove_file(dir.join(SNAPSHOT_TMP_FILE));
+
+        let mut tree = match File::open(dir.join(SNAPSHOT_FILE)) {
+            Ok(file) => RBTree::read_snapshot(BufReader::new(file))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => RBTree::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut wal = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(WAL_FILE))?;
+        let (wal_len, logged) = replay(&mut wal, &mut tree)?;
+        if wal_len < HEADER_LEN {
+            // a new log or one whose header was torn
+            wal.set_len(0)?;
+            wal.seek(SeekFrom::Start(0))?;
+            wal.write_all(&WAL_MAGIC)?;
+            wal.write_all(&WAL_VERSION.to_le_bytes())?;
+            wal.sync_all()?;
+        } else if wal.metadata()?.len() != wal_len {
+            wal.set_len(wal_len)?;
+            wal.sync_all()?;
+        }
+        wal.seek(SeekFrom::Start(wal_len.max(HEADER_LEN)))?;
+
+        Ok(DurableRBTree {
+            tree,
+            dir,
+            wal,
+            wal_len: wal_len.max(HEADER_LEN),
+            policy,
+            unsynced: 0,
+            logged,
+            checkpoint_after: None,
+        })
+    }
+
+    /// checkpoint automatically once this many changes were logged
+    pub fn checkpoint_after(mut self, changes: usize) -> Self {
+        self.checkpoint_after = Some(changes);
+        self
+    }
+
+    pub fn tree(&self) -> &RBTree<K, V> {
+        &self.tree
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.tree.contains(key)
+    }
+
+    pub fn find(&self, key: &K) -> Option<&V> {
+        self.tree.find(key)
+    }
+
+    /// Logs the insert, then applies it. An Err from the sync or the checkpoint that
+    /// follow does not undo the insert, it is in the tree and in the log.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, WalError> {
+        let mut payload = vec![OP_INSERT];
+        push_field(&mut payload, &key)?;
+        push_field(&mut payload, &value)?;
+        self.append(&payload)?;
+        let res = self.tree.insert(key, value);
+        self.after_change()?;
+        Ok(res)
+    }
+
+    /// Logs the remove, then applies it, nothing is logged if key is not in the tree.
+    /// An Err from the sync or the checkpoint that follow does not undo the remove.
+    pub fn remove(&mut self, key: &K) -> Result<Option<V>, WalError> {
+        if !self.tree.contains(key) {
+            return Ok(None);
+        }
+        let mut payload = vec![OP_REMOVE];
+        push_field(&mut payload, key)?;
+        self.append(&payload)?;
+        let res = self.tree.remove(key);
+        self.after_change()?;
+        Ok(res)
+    }
+
+    /// flushes the log to the disk
+    pub fn sync(&mut self) -> Result<(), WalError> {
+        if self.unsynced > 0 {
+            self.wal.sync_data()?;
+            self.unsynced = 0;
+        }
+        Ok(())
+    }
+
+    /// Writes the tree to a new snapshot and empties the log.
+    /// The snapshot replaces the old one by a rename, so there always is a complete one.
+    pub fn checkpoint(&mut self) -> Result<(), WalError> {
+        let tmp = self.dir.join(SNAPSHOT_TMP_FILE);
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        self.tree.write_snapshot(&mut out)?;
+        out.into_inner()
+            .map_err(|err| WalError::Io(err.into_error()))?
+            .sync_all()?;
+        std::fs::rename(&tmp, self.dir.join(SNAPSHOT_FILE))?;
+        sync_dir(&self.dir)?;
+
+        self.wal.set_len(HEADER_LEN)?;
+        self.wal.seek(SeekFrom::Start(HEADER_LEN))?;
+        self.wal.sync_all()?;
+        self.wal_len = HEADER_LEN;
+        self.unsynced = 0;
+        self.logged = 0;
+        Ok(())
+    }
+
+    /// Removes all entries, the log is emptied first and then the snapshot is deleted.
+    /// A crash in between brings back the last checkpoint.
+    pub fn clear(&mut self) -> Result<(), WalError> {
+        self.wal.set_len(HEADER_LEN)?;
+        self.wal.seek(SeekFrom::Start(HEADER_LEN))?;
+        self.wal.sync_all()?;
+        self.tree = RBTree::new();
+        self.wal_len = HEADER_LEN;
+        self.unsynced = 0;
+        self.logged = 0;
+        match std::fs::remove_file(self.dir.join(SNAPSHOT_FILE)) {
+            Ok(()) => sync_dir(&self.dir),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// the length of the log in bytes
+    pub fn wal_len(&self) -> u64 {
+        self.wal_len
+    }
+
+    /// closes the log and returns the tree
+    pub fn into_tree(mut self) -> Result<RBTree<K, V>, WalError> {
+        self.sync()?;
+        Ok(std::mem::take(&mut self.tree))
+    }
+
+    /// Appends a frame. A failed write is cut off again, so later frames are not
+    /// hidden behind a torn one.
+    fn append(&mut self, payload: &[u8]) -> Result<(), WalError> {
+        let len = u32::try_from(payload.len()).map_err(|_| WalError::TooLong(payload.len()))?;
+        let mut crc = Crc32::new();
+        crc.update(payload);
+        let mut frame = Vec::with_capacity(payload.len() + 8);
+        frame.extend_from_slice(&len.to_le_bytes());
+        frame.extend_from_slice(&crc.value().to_le_bytes());
+        frame.extend_from_slice(payload);
+        if let Err(err) = self.wal.write_all(&frame) {
+            let _ = self.wal.set_len(self.wal_len);
+            let _ = self.wal.seek(SeekFrom::Start(self.wal_len));
+            return Err(err.into());
+        }
+        self.wal_len += frame.len() as u64;
+        self.unsynced += 1;
+        self.logged += 1;
+        Ok(())
+    }
+
+    fn after_change(&mut self) -> Result<(), WalError> {
+        match self.policy {
+            SyncPolicy::Always => self.sync()?,
+            SyncPolicy::Every(changes) if self.unsynced >= changes => self.sync()?,
+            _ => (),
+        }
+        match self.checkpoint_after {
+            Some(changes) if self.logged >= changes => self.checkpoint(),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<K: PartialOrd, V> Drop for DurableRBTree<K, V> {
+    fn drop(&mut self) {
+        if self.unsynced > 0 {
+            let _ = self.wal.sync_data();
+        }
+    }
+}
+
+fn push_field<T: Codec>(payload: &mut Vec<u8>, field: &T) -> Result<(), WalError> {
+    let start = payload.len();
+    payload.extend_from_slice(&[0; 4]);
+    field.encode(payload);
+    let len = payload.len() - start - 4;
+    let len = u32::try_from(len).map_err(|_| WalError::TooLong(len))?;
+    payload[start..start + 4].copy_from_slice(&len.to_le_bytes());
+    Ok(())
+}
+
+fn take_field<'a>(payload: &mut &'a [u8]) -> Result<&'a [u8], String> {
+    let len = payload
+        .get(..4)
+        .map(|len| u32::from_le_bytes(len.try_into().expect("unexpected slice length")) as usize)
+        .ok_or("missing field length")?;
+    let field = payload.get(4..4 + len).ok_or("field exceeds the frame")?;
+    *payload = &payload[4 + len..];
+    Ok(field)
+}
+
+/// Applies the frames of the log to tree.
+/// Returns the length up to the last complete frame, 0 if the header is incomplete,
+/// and the number of frames.
+fn replay<K, V>(wal: &mut File, tree: &mut RBTree<K, V>) -> Result<(u64, usize), WalError>
+where
+    K: PartialOrd + Debug + Clone + Codec,
+    V: Debug + Clone + Codec,
+{
+    wal.seek(SeekFrom::Start(0))?;
+    let mut input = BufReader::new(wal);
+    let mut header = [0u8; HEADER_LEN as usize];
+    if !read_full(&mut input, &mut header)? {
+        return Ok((0, 0));
+    }
+    if header[..8] != WAL_MAGIC || header[8..] != WAL_VERSION.to_le_bytes() {
+        return Err(WalError::BadHeader);
+    }
+
+    let mut offset = HEADER_LEN;
+    let mut frames = 0;
+    let mut payload = Vec::new();
+    loop {
+        let mut frame_header = [0u8; 8];
+        if !read_full(&mut input, &mut frame_header)? {
+            break;
+        }
+        let len = u32::from_le_bytes(
+            frame_header[..4]
+                .try_into()
+                .expect("unexpected slice length"),
+        );
+        let expected = u32::from_le_bytes(
+            frame_header[4..]
+                .try_into()
+                .expect("unexpected slice length"),
+        );
+        payload.clear();
+        if (&mut input).take(len as u64).read_to_end(&mut payload)? != len as usize {
+            break;
+        }
+        let mut crc = Crc32::new();
+        crc.update(&payload);
+        if crc.value() != expected {
+            break;
+        }
+
+        let err = |msg: String| WalError::Decode { offset, msg };
+        let (op, mut rest) = payload
+            .split_first()
+            .ok_or_else(|| err("empty frame".to_string()))?;
+        match *op {
+            OP_INSERT => {
+                let key = K::decode(take_field(&mut rest).map_err(err)?).map_err(err)?;
+                let value = V::decode(take_field(&mut rest).map_err(err)?).map_err(err)?;
+                tree.insert(key, value);
+            }
+            OP_REMOVE => {
+                let key = K::decode(take_field(&mut rest).map_err(err)?).map_err(err)?;
+                tree.remove(&key);
+            }
+            op => return Err(err(format!("invalid op {}", op))),
+        }
+        offset += 8 + len as u64;
+        frames += 1;
+    }
+    Ok((offset, frames))
+}
+
+/// fills buf, false if the input ended before
+fn read_full(input: &mut impl Read, buf: &mut [u8]) -> Result<bool, WalError> {
+    match input.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// makes a rename in dir durable
+pub(crate) fn sync_dir(dir: &Path) -> Result<(), WalError> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    type Tree = DurableRBTree<u32, String>;
+
+    fn entries(tree: &RBTree<u32, String>) -> Vec<(u32, String)> {
+        tree.iter()
+            .map(|(key, value)| (*key, value.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn wal_test_reopen() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let expected = {
+            let mut tree = Tree::open(dir.path(), SyncPolicy::Always).expect("failed to open");
+            for key in 0..100 {
+                assert_eq!(
+                    tree.insert(key, key.to_string()).expect("failed to insert"),
+                    None
+                );
+            }
+            for key in (0..100).step_by(3) {
+                assert!(tree.remove(&key).expect("failed to remove").is_some());
+            }
+            assert_eq!(tree.remove(&1000).expect("failed to remove"), None);
+            assert_eq!(
+                tree.insert(1, "one".to_string()).expect("failed to insert"),
+                Some("1".to_string())
+            );
+            entries(tree.tree())
+        };
+        let tree = Tree::open(dir.path(), SyncPolicy::Never).expect("failed to reopen");
+        assert!(tree.tree().check_rules().is_ok());
+        assert_eq!(entries(tree.tree()), expected);
+        assert_eq!(tree.find(&1), Some(&"one".to_string()));
+    }
+
+    #[test]
+    fn wal_test_torn_writes() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        // the log length and the entries after every change
+        let mut states = Vec::new();
+        {
+            let mut tree = Tree::open(dir.path(), SyncPolicy::Every(4)).expect("failed to open");
+            states.push((tree.wal_len(), entries(tree.tree())));
+            for key in 0..12 {
+                tree.insert(key % 7, format!("value {}", key))
+                    .expect("failed to insert");
+                states.push((tree.wal_len(), entries(tree.tree())));
+                if key % 3 == 2 {
+                    tree.remove(&(key / 2)).expect("failed to remove");
+                    states.push((tree.wal_len(), entries(tree.tree())));
+                }
+            }
+        }
+        let wal = std::fs::read(dir.path().join(WAL_FILE)).expect("failed to read log");
+        assert_eq!(wal.len() as u64, states.last().expect("no states").0);
+
+        // a crash can cut the log anywhere, the changes in complete frames survive
+        for cut in 0..=wal.len() {
+            let dir = tempfile::tempdir().expect("failed to create temp dir");
+            let path = dir.path().join(WAL_FILE);
+            std::fs::write(&path, &wal[..cut]).expect("failed to write log");
+            let (len, expected) = states
+                .iter()
+                .rev()
+                .find(|(len, _)| *len <= cut as u64)
+                .cloned()
+                .unwrap_or((HEADER_LEN, Vec::new()));
+
+            let mut tree = Tree::open(dir.path(), SyncPolicy::Always).expect("failed to open");
+            assert_eq!(entries(tree.tree()), expected, "cut at {}", cut);
+            assert_eq!(tree.wal_len(), len);
+            // the torn frame is gone, new changes are not hidden behind it
+            tree.insert(100, "new".to_string())
+                .expect("failed to insert");
+            drop(tree);
+            let tree = Tree::open(dir.path(), SyncPolicy::Always).expect("failed to reopen");
+            assert_eq!(tree.find(&100), Some(&"new".to_string()), "cut at {}", cut);
+        }
+
+        // a damaged last frame is dropped as well
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let mut damaged = wal.clone();
+        *damaged.last_mut().expect("empty log") ^= 0x01;
+        std::fs::write(dir.path().join(WAL_FILE), &damaged).expect("failed to write log");
+        let tree = Tree::open(dir.path(), SyncPolicy::Always).expect("failed to open");
+        assert_eq!(entries(tree.tree()), states[states.len() - 2].1);
+
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        std::fs::write(dir.path().join(WAL_FILE), b"not a log at all")
+            .expect("failed to write log");
+        assert!(matches!(
+            Tree::open(dir.path(), SyncPolicy::Always),
+            Err(WalError::BadHeader)
+        ));
+    }
+
+    #[test]
+    fn wal_test_checkpoint() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let mut tree = Tree::open(dir.path(), SyncPolicy::Never).expect("failed to open");
+        for key in 0..50 {
+            tree.insert(key, key.to_string()).expect("failed to insert");
+        }
+        let log = std::fs::read(dir.path().join(WAL_FILE)).expect("failed to read log");
+        tree.checkpoint().expect("failed to checkpoint");
+        assert_eq!(tree.wal_len(), HEADER_LEN);
+        for key in 50..60 {
+            tree.insert(key, key.to_string()).expect("failed to insert");
+        }
+        tree.remove(&0).expect("failed to remove");
+        let expected = entries(tree.tree());
+        drop(tree);
+
+        let tree = Tree::open(dir.path(), SyncPolicy::Never).expect("failed to reopen");
+        assert_eq!(entries(tree.tree()), expected);
+        drop(tree);
+
+        // a crash after the snapshot was written but before the log was emptied
+        std::fs::write(dir.path().join(WAL_FILE), &log).expect("failed to write log");
+        let tree = Tree::open(dir.path(), SyncPolicy::Never).expect("failed to reopen");
+        assert_eq!(
+            entries(tree.tree()),
+            entries(&(0..50).map(|key| (key, key.to_string())).collect())
+        );
+
+        // periodic checkpoints keep the log short
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let mut tree = Tree::open(dir.path(), SyncPolicy::Never)
+            .expect("failed to open")
+            .checkpoint_after(10);
+        for key in 0..25 {
+            tree.insert(key, key.to_string()).expect("failed to insert");
+        }
+        assert!(dir.path().join(SNAPSHOT_FILE).exists());
+        let tree = tree.into_tree().expect("failed to close");
+        let copy = Tree::open(dir.path(), SyncPolicy::Never).expect("failed to reopen");
+        assert_eq!(entries(copy.tree()), entries(&tree));
+    }
+}
@@ -0,0 +1,275 @@
+//! A read-only map in Eytzinger layout, built once from an RBTree with freeze.
+//!
+//! The keys are stored in breadth-first order of a complete binary tree: the root at
+//! index 0, the children of index i at 2i + 1 and 2i + 2. The values sit at the same
+//! index in a parallel array, so searches touch keys only. The top levels share a few
+//! cache lines and a search descends without a data dependent branch, the path is
+//! collected as bits of the position and the result is recovered from them at the end.
+
+use crate::prelude::*;
+use crate::tree::rb_tree::RBTree;
+use core::fmt::{Debug, Formatter};
+use core::ops::{Bound, Index, RangeBounds};
+
+/// An immutable sorted map, see the module docs for the layout
+pub struct FrozenMap<K: PartialOrd, V> {
+    keys: Vec<K>,
+    values: Vec<V>,
+}
+
+/// the 1-based positions of a complete tree of len nodes in ascending key order
+fn in_order(len: usize) -> impl Iterator<Item = usize> {
+    let first = if len == 0 { 0 } else { leftmost(1, len) };
+    core::iter::successors(Some(first).filter(|pos| *pos != 0), move |pos| {
+        Some(successor(*pos, len)).filter(|pos| *pos != 0)
+    })
+}
+
+/// the leftmost position below pos
+fn leftmost(mut pos: usize, len: usize) -> usize {
+    while 2 * pos <= len {
+        pos *= 2;
+    }
+    pos
+}
+
+/// the next position in key order, 0 after the last one
+fn successor(pos: usize, len: usize) -> usize {
+    if 2 * pos < len {
+        leftmost(2 * pos + 1, len)
+    } else {
+        // up to the first ancestor reached from its smaller child
+        pos >> (pos.trailing_ones() + 1)
+    }
+}
+
+impl<K: PartialOrd, V> FrozenMap<K, V> {
+    /// lays out entries, which must be in ascending key order
+    pub(crate) fn from_sorted(entries: Vec<(K, V)>) -> FrozenMap<K, V> {
+        let len = entries.len();
+        let mut slots: Vec<Option<(K, V)>> = (0..len).map(|_| None).collect();
+        for (pos, entry) in in_order(len).zip(entries) {
+            slots[pos - 1] = Some(entry);
+        }
+        let (keys, values) = slots
+            .into_iter()
+            .map(|slot| slot.expect("unexpected empty slot"))
+            .unzip();
+        FrozenMap { keys, values }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Walks from the root to beyond a leaf, turning to the larger child where
+    /// go_larger is true. Returns the 1-based position past the leaf, its bits are the
+    /// turns taken.
+    #[inline]
+    fn descend(&self, go_larger: impl Fn(&K) -> bool) -> usize {
+        let mut pos = 1;
+        while pos <= self.keys.len() {
+            pos = 2 * pos + usize::from(go_larger(&self.keys[pos - 1]));
+        }
+        pos
+    }
+
+    /// the index of the last node the path turned to the smaller child at
+    #[inline]
+    fn last_smaller_turn(pos: usize) -> Option<usize> {
+        (pos >> (pos.trailing_ones() + 1)).checked_sub(1)
+    }
+
+    /// the index of the last node the path turned to the larger child at
+    #[inline]
+    fn last_larger_turn(pos: usize) -> Option<usize> {
+        (pos >> (pos.trailing_zeros() + 1)).checked_sub(1)
+    }
+
+    fn entry(&self, idx: usize) -> (&K, &V) {
+        (&self.keys[idx], &self.values[idx])
+    }
+
+    /// the index of the smallest key not below key
+    fn ceiling_idx(&self, key: &K) -> Option<usize> {
+        Self::last_smaller_turn(self.descend(|curr| *curr < *key))
+    }
+
+    /// the index of the smallest key above key
+    fn larger_idx(&self, key: &K) -> Option<usize> {
+        Self::last_smaller_turn(self.descend(|curr| *curr <= *key))
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.find(key).is_some()
+    }
+
+    pub fn find(&self, key: &K) -> Option<&V> {
+        self.ceiling_idx(key)
+            .filter(|idx| self.keys[*idx] == *key)
+            .map(|idx| &self.values[idx])
+    }
+
+    pub fn smallest(&self) -> Option<(&K, &V)> {
+        (!self.is_empty()).then(|| self.entry(leftmost(1, self.len()) - 1))
+    }
+
+    pub fn largest(&self) -> Option<(&K, &V)> {
+        let mut pos = 1;
+        while 2 * pos < self.len() {
+            pos = 2 * pos + 1;
+        }
+        (!self.is_empty()).then(|| self.entry(pos - 1))
+    }
+
+    /// the entry with the largest key below key
+    pub fn smaller(&self, key: &K) -> Option<(&K, &V)> {
+        Self::last_larger_turn(self.descend(|curr| *curr < *key)).map(|idx| self.entry(idx))
+    }
+
+    /// the entry with the smallest key above key
+    pub fn larger(&self, key: &K) -> Option<(&K, &V)> {
+        self.larger_idx(key).map(|idx| self.entry(idx))
+    }
+
+    /// the entry with the largest key not above key
+    pub fn floor(&self, key: &K) -> Option<(&K, &V)> {
+        Self::last_larger_turn(self.descend(|curr| *curr <= *key)).map(|idx| self.entry(idx))
+    }
+
+    /// the entry with the smallest key not below key
+    pub fn ceiling(&self, key: &K) -> Option<(&K, &V)> {
+        self.ceiling_idx(key).map(|idx| self.entry(idx))
+    }
+
+    pub fn iter(&self) -> Range<'_, K, V, core::ops::RangeFull> {
+        self.range(..)
+    }
+
+    /// ascending iterator over the entries with keys in range
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Range<'_, K, V, R> {
+        let start = match range.start_bound() {
+            Bound::Included(start) => self.ceiling_idx(start),
+            Bound::Excluded(start) => self.larger_idx(start),
+            Bound::Unbounded => (!self.is_empty()).then(|| leftmost(1, self.len()) - 1),
+        };
+        Range {
+            map: self,
+            pos: start.map_or(0, |idx| idx + 1),
+            range,
+        }
+    }
+}
+
+impl<K: PartialOrd + Debug + Clone, V: Debug + Clone> FrozenMap<K, V> {
+    /// a mutable tree with the same entries, built in O(n)
+    pub fn thaw(self) -> RBTree<K, V> {
+        let len = self.len();
+        let mut slots: Vec<Option<(K, V)>> =
+            self.keys.into_iter().zip(self.values).map(Some).collect();
+        let entries =
+            in_order(len).map(|pos| slots[pos - 1].take().expect("unexpected empty slot"));
+        RBTree::build_sorted(entries, len)
+    }
+}
+
+/// ascending iterator over the entries of a FrozenMap within a range of keys
+pub struct Range<'a, K: PartialOrd, V, R> {
+    map: &'a FrozenMap<K, V>,
+    // the 1-based position of the next entry, 0 when done
+    pos: usize,
+    range: R,
+}
+
+impl<'a, K: PartialOrd, V, R: RangeBounds<K>> Iterator for Range<'a, K, V, R> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == 0 {
+            return None;
+        }
+        let (key, value) = self.map.entry(self.pos - 1);
+        let below_end = match self.range.end_bound() {
+            Bound::Included(end) => *key <= *end,
+            Bound::Excluded(end) => *key < *end,
+            Bound::Unbounded => true,
+        };
+        if below_end {
+            self.pos = successor(self.pos, self.map.len());
+            Some((key, value))
+        } else {
+            self.pos = 0;
+            None
+        }
+    }
+}
+
+impl<'a, K: PartialOrd, V> IntoIterator for &'a FrozenMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Range<'a, K, V, core::ops::RangeFull>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: PartialOrd, V> Index<&K> for FrozenMap<K, V> {
+    type Output = V;
+
+    fn index(&self, key: &K) -> &V {
+        self.find(key).expect("key not found in FrozenMap")
+    }
+}
+
+impl<K: PartialOrd + Debug, V: Debug> Debug for FrozenMap<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fm_test_layout() {
+        let map = RBTree::from_sorted((1..=7).map(|key| (key, key * 10)).collect()).freeze();
+        // breadth first: the root, its children, their children
+        assert_eq!(map.keys, vec![4, 2, 6, 1, 3, 5, 7]);
+        assert_eq!(map.values, vec![40, 20, 60, 10, 30, 50, 70]);
+        let tree = map.thaw();
+        tree.check_rules().expect("invalid thawed tree");
+        assert!(tree.iter().map(|(key, _)| *key).eq(1..=7));
+    }
+
+    #[test]
+    fn fm_test_against_rbtree() {
+        // odd keys only, the even probes fall between them
+        for len in 0..40u32 {
+            let tree: RBTree<u32, u32> = (0..len).map(|key| (2 * key + 1, key)).collect();
+            let map = tree.clone().freeze();
+            assert_eq!(map.len(), len as usize);
+            assert!(map.iter().eq(tree.iter()));
+            assert_eq!(map.smallest(), tree.smallest());
+            assert_eq!(map.largest(), tree.largest());
+            for probe in 0..2 * len + 2 {
+                assert_eq!(map.find(&probe), tree.find(&probe));
+                assert_eq!(map.smaller(&probe), tree.smaller(&probe));
+                assert_eq!(map.larger(&probe), tree.larger(&probe));
+                let floor = tree.find(&probe).map(|value| (&probe, value));
+                assert_eq!(map.floor(&probe), floor.or(tree.smaller(&probe)));
+                assert_eq!(map.ceiling(&probe), floor.or(tree.larger(&probe)));
+                assert!(map.range(probe..).eq(tree.range(probe..)));
+                assert!(map.range(..=probe).eq(tree.range(..=probe)));
+                assert!(map
+                    .range((Bound::Excluded(probe), Bound::Excluded(probe + 6)))
+                    .eq(tree.range((Bound::Excluded(probe), Bound::Excluded(probe + 6)))));
+            }
+            assert_eq!(map.thaw(), tree);
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use super::SubNode;
+use crate::prelude::*;
+use crate::tree::rb_tree::Color::{self, Black, Red};
+use crate::tree::TreeNode;
+use alloc::sync::Arc;
+use core::cmp::Ordering;
+use core::fmt::Debug;
+
+#[derive(Clone)]
+pub struct LLRBNode<K: PartialOrd, V> {
+    pub key: K,
+    pub value: V,
+    /// the color of the link from the parent, a red node is never a larger child
+    pub color: Color,
+    pub smaller: SubNode<K, V>,
+    pub larger: SubNode<K, V>,
+}
+
+fn flip(color: Color) -> Color {
+    match color {
+        Red => Black,
+        Black => Red,
+    }
+}
+
+impl<K: PartialOrd, V> LLRBNode<K, V> {
+    pub fn new(key: K, value: V) -> LLRBNode<K, V> {
+        LLRBNode {
+            key,
+            value,
+            color: Red,
+            smaller: None,
+            larger: None,
+        }
+    }
+
+    pub(crate) fn is_red(link: &SubNode<K, V>) -> bool {
+        link.as_ref().is_some_and(|node| node.color == Red)
+    }
+
+    /// true if the smaller child of the node at link is red
+    fn is_red_smaller(link: &SubNode<K, V>) -> bool {
+        link.as_ref()
+            .is_some_and(|node| Self::is_red(&node.smaller))
+    }
+}
+
+/// Sedgewick's left-leaning red black tree, a 2-3 tree where the red smaller child
+/// and its parent form a 3-node. All modifications go through Arc::make_mut, nodes
+/// shared with a cloned tree are copied before they are changed.
+impl<K: PartialOrd + Clone, V: Clone> LLRBNode<K, V> {
+    /// the larger child of the node at link moves up and takes over its color
+    fn rotate_left(link: &mut SubNode<K, V>) {
+        let mut top = link.take().expect("unexpected empty link");
+        let node = Arc::make_mut(&mut top);
+        let mut larger = node.larger.take().expect("unexpected missing larger child");
+        let up = Arc::make_mut(&mut larger);
+        node.larger = up.smaller.take();
+        up.color = node.color;
+        node.color = Red;
+        up.smaller = Some(top);
+        *link = Some(larger);
+    }
+
+    /// the smaller child of the node at link moves up and takes over its color
+    fn rotate_right(link: &mut SubNode<K, V>) {
+        let mut top = link.take().expect("unexpected empty link");
+        let node = Arc::make_mut(&mut top);
+        let mut smaller = node
+            .smaller
+            .take()
+            .expect("unexpected missing smaller child");
+        let up = Arc::make_mut(&mut smaller);
+        node.smaller = up.larger.take();
+        up.color = node.color;
+        node.color = Red;
+        up.larger = Some(top);
+        *link = Some(smaller);
+    }
+
+    /// splits a 4-node on the way up, or joins a 2-node with its children on the way down
+    fn flip_colors(link: &mut SubNode<K, V>) {
+        let node = Arc::make_mut(link.as_mut().expect("unexpected empty link"));
+        node.color = flip(node.color);
+        for child in [&mut node.smaller, &mut node.larger].into_iter().flatten() {
+            let child = Arc::make_mut(child);
+            child.color = flip(child.color);
+        }
+    }
+
+    /// restores the left-leaning invariants at link on the way back up
+    fn fix_up(link: &mut SubNode<K, V>) {
+        let node = link.as_ref().expect("unexpected empty link");
+        if Self::is_red(&node.larger) && !Self::is_red(&node.smaller) {
+            Self::rotate_left(link);
+        }
+        let node = link.as_ref().expect("unexpected empty link");
+        if Self::is_red(&node.smaller) && Self::is_red_smaller(&node.smaller) {
+            Self::rotate_right(link);
+        }
+        let node = link.as_ref().expect("unexpected empty link");
+        if Self::is_red(&node.smaller) && Self::is_red(&node.larger) {
+            Self::flip_colors(link);
+        }
+    }
+
+    /// makes the smaller child of the node at link or one of its children red
+    fn move_red_smaller(link: &mut SubNode<K, V>) {
+        Self::flip_colors(link);
+        let node = Arc::make_mut(link.as_mut().expect("unexpected empty link"));
+        if Self::is_red_smaller(&node.larger) {
+            Self::rotate_right(&mut node.larger);
+            Self::rotate_left(link);
+            Self::flip_colors(link);
+        }
+    }
+
+    /// makes the larger child of the node at link or one of its children red
+    fn move_red_larger(link: &mut SubNode<K, V>) {
+        Self::flip_colors(link);
+        let node = link.as_ref().expect("unexpected empty link");
+        if Self::is_red_smaller(&node.smaller) {
+            Self::rotate_right(link);
+            Self::flip_colors(link);
+        }
+    }
+
+    /// insert into the subtree hanging off link, the new node is red
+    pub(crate) fn insert(link: &mut SubNode<K, V>, key: K, value: V) -> Option<V> {
+        let node = match link {
+            Some(node) => Arc::make_mut(node),
+            None => {
+                *link = Some(Arc::new(LLRBNode::new(key, value)));
+                return None;
+            }
+        };
+        let replaced = if key < node.key {
+            Self::insert(&mut node.smaller, key, value)
+        } else if key > node.key {
+            Self::insert(&mut node.larger, key, value)
+        } else {
+            // the shape does not change
+            return Some(core::mem::replace(&mut node.value, value));
+        };
+        if replaced.is_none() {
+            Self::fix_up(link);
+        }
+        replaced
+    }
+
+    /// removes the smallest entry below link, which must not be a 2-node
+    fn remove_smallest(link: &mut SubNode<K, V>) -> (K, V) {
+        let node = link.as_ref().expect("unexpected empty link");
+        if node.smaller.is_none() {
+            // without a smaller child the node is a leaf, a larger child would be red
+            let node = Arc::unwrap_or_clone(link.take().expect("unexpected empty link"));
+            return (node.key, node.value);
+        }
+        if !Self::is_red(&node.smaller) && !Self::is_red_smaller(&node.smaller) {
+            Self::move_red_smaller(link);
+        }
+        let node = Arc::make_mut(link.as_mut().expect("unexpected empty link"));
+        let res = Self::remove_smallest(&mut node.smaller);
+        Self::fix_up(link);
+        res
+    }
+
+    /// Removes key from the subtree below link, which must contain it. A red link is
+    /// pushed down along the search path so the removed leaf is never a 2-node.
+    pub(crate) fn remove(link: &mut SubNode<K, V>, key: &K) -> V {
+        let node = link.as_ref().expect("unexpected empty link");
+        let res = if *key < node.key {
+            if !Self::is_red(&node.smaller) && !Self::is_red_smaller(&node.smaller) {
+                Self::move_red_smaller(link);
+            }
+            let node = Arc::make_mut(link.as_mut().expect("unexpected empty link"));
+            Self::remove(&mut node.smaller, key)
+        } else {
+            if Self::is_red(&node.smaller) {
+                Self::rotate_right(link);
+            }
+            let node = link.as_ref().expect("unexpected empty link");
+            if *key == node.key && node.larger.is_none() {
+                let node = Arc::unwrap_or_clone(link.take().expect("unexpected empty link"));
+                return node.value;
+            }
+            if !Self::is_red(&node.larger) && !Self::is_red_smaller(&node.larger) {
+                Self::move_red_larger(link);
+            }
+            let node = Arc::make_mut(link.as_mut().expect("unexpected empty link"));
+            if *key == node.key {
+                // the successor takes the place of the removed entry
+                let (key, value) = Self::remove_smallest(&mut node.larger);
+                node.key = key;
+                core::mem::replace(&mut node.value, value)
+            } else {
+                Self::remove(&mut node.larger, key)
+            }
+        };
+        Self::fix_up(link);
+        res
+    }
+}
+
+impl<K: PartialOrd + Debug, V> LLRBNode<K, V> {
+    /// Checks the order of the keys, that red nodes are smaller children of black
+    /// nodes and that all paths have the same number of black nodes, which is returned.
+    pub fn check_rules(&self, lower: Option<&K>, upper: Option<&K>) -> Result<usize, String> {
+        let below = |smaller: &K, larger: &K| smaller.partial_cmp(larger) == Some(Ordering::Less);
+        if lower.is_some_and(|lower| !below(lower, &self.key))
+            || upper.is_some_and(|upper| !below(&self.key, upper))
+        {
+            return Err(format!(
+                "LLRB violation: key {:?} out of order between {:?} and {:?}",
+                self.key, lower, upper
+            ));
+        }
+        if Self::is_red(&self.larger) {
+            return Err(format!("LLRB violation: red larger child @{:?}", self.key));
+        }
+        if self.color == Red && Self::is_red(&self.smaller) {
+            return Err(format!(
+                "LLRB violation: two successive red nodes @{:?}",
+                self.key
+            ));
+        }
+        let black_count_sm = match &self.smaller {
+            Some(node) => node.check_rules(lower, Some(&self.key))?,
+            None => 0,
+        };
+        let black_count_lg = match &self.larger {
+            Some(node) => node.check_rules(Some(&self.key), upper)?,
+            None => 0,
+        };
+        if black_count_sm != black_count_lg {
+            return Err(format!(
+                "LLRB violation: mismatching black counts @{:?} {}!={}",
+                self.key, black_count_sm, black_count_lg
+            ));
+        }
+        Ok(black_count_sm + if self.color == Black { 1 } else { 0 })
+    }
+}
+
+impl<K: PartialOrd, V> TreeNode<K, V> for LLRBNode<K, V> {
+    fn key(&self) -> &K {
+        &self.key
+    }
+
+    fn value(&self) -> &V {
+        &self.value
+    }
+
+    fn smaller(&self) -> Option<&Self> {
+        self.smaller.as_deref()
+    }
+
+    fn larger(&self) -> Option<&Self> {
+        self.larger.as_deref()
+    }
+
+    fn color(&self) -> Option<Color> {
+        Some(self.color)
+    }
+}
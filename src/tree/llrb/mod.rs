@@ -0,0 +1,347 @@
+use crate::prelude::*;
+use crate::tree::dot::{self, DotOptions};
+use crate::tree::metrics::{self, ShapeReport};
+use crate::tree::rb_tree::Color;
+use crate::tree::shape;
+use alloc::sync::Arc;
+use core::fmt::{Debug, Display, Formatter};
+use core::ops::Index;
+use llrb_node::LLRBNode;
+
+type SubNode<K, V> = Option<Arc<LLRBNode<K, V>>>;
+
+mod llrb_node;
+
+/// A left-leaning red black tree after Sedgewick. It keeps the red black rules of
+/// RBTree and additionally allows red nodes only as smaller children, so it maps one
+/// to one onto a 2-3 tree. Insert and remove are short recursive functions, which
+/// makes it a plain oracle to cross-check RBTree against.
+pub struct LLRBTree<K: PartialOrd, V> {
+    pub(crate) root: SubNode<K, V>,
+}
+
+impl<K: PartialOrd, V> Default for LLRBTree<K, V> {
+    fn default() -> Self {
+        LLRBTree::new()
+    }
+}
+
+impl<K: PartialOrd, V> LLRBTree<K, V> {
+    pub fn new() -> LLRBTree<K, V> {
+        LLRBTree { root: None }
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.find(key).is_some()
+    }
+
+    pub fn find(&self, key: &K) -> Option<&V> {
+        let mut node = self.root.as_deref();
+        while let Some(curr) = node {
+            if *key < curr.key {
+                node = curr.smaller.as_deref();
+            } else if *key > curr.key {
+                node = curr.larger.as_deref();
+            } else {
+                return Some(&curr.value);
+            }
+        }
+        None
+    }
+
+    pub fn smallest(&self) -> Option<(&K, &V)> {
+        let mut curr = self.root.as_deref()?;
+        while let Some(subnode) = &curr.smaller {
+            curr = subnode;
+        }
+        Some((&curr.key, &curr.value))
+    }
+
+    /// the entry with the largest key below key
+    pub fn smaller(&self, key: &K) -> Option<(&K, &V)> {
+        let mut candidate = None;
+        let mut node = self.root.as_deref();
+        while let Some(curr) = node {
+            if curr.key < *key {
+                candidate = Some(curr);
+                node = curr.larger.as_deref();
+            } else {
+                node = curr.smaller.as_deref();
+            }
+        }
+        candidate.map(|node| (&node.key, &node.value))
+    }
+
+    pub fn largest(&self) -> Option<(&K, &V)> {
+        let mut curr = self.root.as_deref()?;
+        while let Some(subnode) = &curr.larger {
+            curr = subnode;
+        }
+        Some((&curr.key, &curr.value))
+    }
+
+    /// the entry with the smallest key above key
+    pub fn larger(&self, key: &K) -> Option<(&K, &V)> {
+        let mut candidate = None;
+        let mut node = self.root.as_deref();
+        while let Some(curr) = node {
+            if curr.key > *key {
+                candidate = Some(curr);
+                node = curr.smaller.as_deref();
+            } else {
+                node = curr.larger.as_deref();
+            }
+        }
+        candidate.map(|node| (&node.key, &node.value))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// height, node count, black height and nodes per level
+    pub fn shape_report(&self) -> ShapeReport {
+        metrics::shape_report::<K, V, _>(self.root.as_deref())
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.push_smaller(self.root.as_deref());
+        iter
+    }
+}
+
+impl<K: PartialOrd + Debug, V> LLRBTree<K, V> {
+    /// checks the red black rules and that red nodes lean left, returns the black height
+    pub fn check_rules(&self) -> core::result::Result<usize, String> {
+        match &self.root {
+            Some(root) if root.color == Color::Red => {
+                Err("LLRB violation: root is red".to_string())
+            }
+            Some(root) => root.check_rules(None, None),
+            None => Ok(0),
+        }
+    }
+}
+
+impl<K: PartialOrd + Debug, V: Debug> LLRBTree<K, V> {
+    /// the tree as a Graphviz digraph, render it with `dot -Tsvg`
+    pub fn to_dot(&self) -> String {
+        self.to_dot_with(&DotOptions::default())
+    }
+
+    pub fn to_dot_with(&self, options: &DotOptions<K>) -> String {
+        dot::to_dot::<K, V, _>("LLRBTree", self.root.as_deref(), options)
+    }
+}
+
+impl<K: PartialOrd + Display, V: Display> LLRBTree<K, V> {
+    /// the exact shape and colors in the notation of the shape module
+    pub fn to_shape(&self) -> String {
+        shape::encode::<K, V, _>(self.root.as_deref())
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> LLRBTree<K, V> {
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let res = LLRBNode::insert(&mut self.root, key, value);
+        self.blacken_root();
+        res
+    }
+
+    pub fn find_mut(&mut self, key: &K) -> Option<&mut V> {
+        // search first, a miss must not copy the path shared with a clone
+        self.find(key)?;
+        let mut curr = Arc::make_mut(self.root.as_mut()?);
+        loop {
+            let link = if *key < curr.key {
+                &mut curr.smaller
+            } else if *key > curr.key {
+                &mut curr.larger
+            } else {
+                return Some(&mut curr.value);
+            };
+            curr = Arc::make_mut(link.as_mut().expect("unexpected missing key"));
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        // the recursion expects the key to be there
+        if !self.contains(key) {
+            return None;
+        }
+        // a red root lets the first step down borrow a red link
+        let root = self.root.as_mut().expect("unexpected empty root");
+        if !LLRBNode::is_red(&root.smaller) && !LLRBNode::is_red(&root.larger) {
+            Arc::make_mut(root).color = Color::Red;
+        }
+        let res = LLRBNode::remove(&mut self.root, key);
+        self.blacken_root();
+        Some(res)
+    }
+
+    fn blacken_root(&mut self) {
+        if LLRBNode::is_red(&self.root) {
+            if let Some(root) = self.root.as_mut() {
+                Arc::make_mut(root).color = Color::Black;
+            }
+        }
+    }
+}
+
+impl<K: PartialOrd, V> Clone for LLRBTree<K, V> {
+    /// the clone shares all nodes with self, nodes are copied when either tree modifies them
+    fn clone(&self) -> Self {
+        LLRBTree {
+            root: self.root.clone(),
+        }
+    }
+}
+
+/// ascending iterator over the entries of an LLRBTree
+pub struct Iter<'a, K: PartialOrd, V> {
+    // nodes still to be visited, the next one on top
+    stack: Vec<&'a LLRBNode<K, V>>,
+}
+
+impl<'a, K: PartialOrd, V> Iter<'a, K, V> {
+    fn push_smaller(&mut self, mut node: Option<&'a LLRBNode<K, V>>) {
+        while let Some(curr) = node {
+            self.stack.push(curr);
+            node = curr.smaller.as_deref();
+        }
+    }
+}
+
+impl<'a, K: PartialOrd, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_smaller(node.larger.as_deref());
+        Some((&node.key, &node.value))
+    }
+}
+
+impl<'a, K: PartialOrd, V> IntoIterator for &'a LLRBTree<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: PartialOrd, V: PartialEq> PartialEq for LLRBTree<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl<K: PartialOrd, V> Index<&K> for LLRBTree<K, V> {
+    type Output = V;
+
+    fn index(&self, key: &K) -> &V {
+        self.find(key).expect("key not found in LLRBTree")
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> Extend<(K, V)> for LLRBTree<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> FromIterator<(K, V)> for LLRBTree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tree = LLRBTree::new();
+        tree.extend(iter);
+        tree
+    }
+}
+
+impl<K: PartialOrd + Debug, V: Debug> Debug for LLRBTree<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tree::test_map::{self, test_map_impl};
+    use rand::seq::SliceRandom;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn check(tree: &LLRBTree<u32, u32>) -> usize {
+        tree.check_rules()
+            .unwrap_or_else(|err| panic!("{}\n{}", err, tree.to_shape()))
+    }
+
+    #[test]
+    fn llrb_test_insert() {
+        let mut tree = LLRBTree::new();
+        tree.extend([(1, 1), (2, 2)]);
+        // the red 2 leaned right and was rotated
+        assert_eq!(tree.to_shape(), "B2=2 R1=1 . . .");
+        tree.insert(3, 3);
+        // the 4-node 1 2 3 was split
+        assert_eq!(tree.to_shape(), "B2=2 B1=1 . . B3=3 . .");
+        assert_eq!(tree.insert(3, 30), Some(3));
+        assert_eq!(tree[&3], 30);
+    }
+
+    #[test]
+    fn llrb_test_insert_remove() {
+        let mut tree = LLRBTree::new();
+        for key in 0..1000 {
+            assert_eq!(tree.insert(key, key), None);
+            check(&tree);
+        }
+        assert!(tree.shape_report().height <= 2 * 10);
+        let mut keys: Vec<u32> = (0..1000).collect();
+        keys.shuffle(&mut StdRng::seed_from_u64(7));
+        let snapshot = tree.clone();
+        for key in &keys {
+            assert_eq!(tree.remove(key), Some(*key));
+            assert_eq!(tree.remove(key), None);
+            check(&tree);
+        }
+        assert!(tree.is_empty());
+        // the clone is untouched
+        check(&snapshot);
+        assert_eq!(snapshot.iter().count(), 1000);
+    }
+
+    test_map_impl!(LLRBTree<u32, u32>, check);
+
+    fn node_ptrs<K: PartialOrd, V>(link: &SubNode<K, V>, ptrs: &mut Vec<usize>) {
+        if let Some(node) = link {
+            ptrs.push(Arc::as_ptr(node) as usize);
+            node_ptrs(&node.smaller, ptrs);
+            node_ptrs(&node.larger, ptrs);
+        }
+    }
+
+    #[test]
+    fn llrb_test_clone() {
+        test_map::check_clone(|tree: &LLRBTree<u32, u32>| {
+            let mut ptrs = Vec::new();
+            node_ptrs(&tree.root, &mut ptrs);
+            ptrs
+        });
+    }
+
+    /// the differential test, RBTree and LLRBTree must agree on every result
+    #[test]
+    fn llrb_test_against_rbtree() {
+        let (tree, rb_tree, _) =
+            test_map::check_against_rbtree::<LLRBTree<u32, u32>>(13, 10000, 500, 0.55);
+        rb_tree
+            .check_rules()
+            .unwrap_or_else(|err| panic!("{}\n{}", err, rb_tree.to_shape()));
+        assert_eq!(tree.shape_report().nodes, rb_tree.iter().count());
+    }
+}
@@ -0,0 +1,202 @@
+use crate::prelude::*;
+#[cfg(feature = "metrics")]
+use crate::tree::rb_tree::RotDir;
+use crate::tree::rb_tree::{Color, TreeEvent, TreeObserver};
+use crate::tree::TreeNode;
+#[cfg(feature = "metrics")]
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Operation counters of a tree, read them with stats().
+/// Without the metrics feature this is an empty struct and recording does nothing.
+/// The counters are atomics so lookups through &self can count and the tree stays Sync.
+#[derive(Default, Clone)]
+pub(crate) struct Metrics {
+    #[cfg(feature = "metrics")]
+    counters: Counters,
+}
+
+#[cfg(feature = "metrics")]
+#[derive(Default)]
+struct Counters {
+    comparisons: AtomicU64,
+    left_rotations: AtomicU64,
+    right_rotations: AtomicU64,
+    recolors: AtomicU64,
+    searches: AtomicU64,
+    depth_sum: AtomicU64,
+    max_depth: AtomicU64,
+}
+
+/// a cloned tree starts with the counts of the original
+#[cfg(feature = "metrics")]
+impl Clone for Counters {
+    fn clone(&self) -> Self {
+        let copy = |counter: &AtomicU64| AtomicU64::new(counter.load(Ordering::Relaxed));
+        Counters {
+            comparisons: copy(&self.comparisons),
+            left_rotations: copy(&self.left_rotations),
+            right_rotations: copy(&self.right_rotations),
+            recolors: copy(&self.recolors),
+            searches: copy(&self.searches),
+            depth_sum: copy(&self.depth_sum),
+            max_depth: copy(&self.max_depth),
+        }
+    }
+}
+
+/// A copy of the counters of a tree
+#[cfg(feature = "metrics")]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// Three-way key comparisons, one per node a search, smaller, larger or range
+    /// compares its key with. Rebalancing compares no keys, it shows in the rotations.
+    pub comparisons: u64,
+    pub left_rotations: u64,
+    pub right_rotations: u64,
+    pub recolors: u64,
+    /// number of contains, find, find_mut, insert and remove calls
+    pub searches: u64,
+    /// the most nodes visited by one search, a hit at the root is depth 1
+    pub max_search_depth: u64,
+    pub avg_search_depth: f64,
+}
+
+#[cfg(feature = "metrics")]
+fn add(counter: &AtomicU64, value: u64) {
+    counter.fetch_add(value, Ordering::Relaxed);
+}
+
+impl Metrics {
+    /// Counts a contains, find, find_mut, insert or remove that compared its key
+    /// with depth nodes. The tree counts the nodes on its own walk.
+    #[inline(always)]
+    pub fn record_search(&self, depth: u64) {
+        #[cfg(feature = "metrics")]
+        {
+            let counters = &self.counters;
+            add(&counters.comparisons, depth);
+            add(&counters.searches, 1);
+            add(&counters.depth_sum, depth);
+            counters.max_depth.fetch_max(depth, Ordering::Relaxed);
+        }
+        #[cfg(not(feature = "metrics"))]
+        let _ = depth;
+    }
+
+    /// counts the key comparisons of smaller, larger and range, which are no searches
+    #[inline(always)]
+    pub fn record_comparisons(&self, count: u64) {
+        #[cfg(feature = "metrics")]
+        add(&self.counters.comparisons, count);
+        #[cfg(not(feature = "metrics"))]
+        let _ = count;
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> Stats {
+        let counters = &self.counters;
+        let searches = counters.searches.load(Ordering::Relaxed);
+        Stats {
+            comparisons: counters.comparisons.load(Ordering::Relaxed),
+            left_rotations: counters.left_rotations.load(Ordering::Relaxed),
+            right_rotations: counters.right_rotations.load(Ordering::Relaxed),
+            recolors: counters.recolors.load(Ordering::Relaxed),
+            searches,
+            max_search_depth: counters.max_depth.load(Ordering::Relaxed),
+            avg_search_depth: if searches == 0 {
+                0.0
+            } else {
+                counters.depth_sum.load(Ordering::Relaxed) as f64 / searches as f64
+            },
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn reset(&mut self) {
+        self.counters = Counters::default();
+    }
+}
+
+/// counts rotations and recolors reported by the node code
+impl<K> TreeObserver<K> for &Metrics {
+    #[inline(always)]
+    fn on_event(&mut self, event: TreeEvent<&K>) {
+        #[cfg(feature = "metrics")]
+        match event {
+            TreeEvent::Rotated {
+                dir: RotDir::Left, ..
+            } => add(&self.counters.left_rotations, 1),
+            TreeEvent::Rotated {
+                dir: RotDir::Right, ..
+            } => add(&self.counters.right_rotations, 1),
+            TreeEvent::Recolored { .. } => add(&self.counters.recolors, 1),
+            _ => (),
+        }
+        #[cfg(not(feature = "metrics"))]
+        let _ = event;
+    }
+}
+
+/// The shape of a tree
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShapeReport {
+    /// number of levels, 0 for an empty tree
+    pub height: usize,
+    /// black nodes on every path from the root to a nil leaf, None for uncolored trees
+    pub black_height: Option<usize>,
+    pub nodes: usize,
+    /// number of nodes on every level, the root is at index 0
+    pub per_depth: Vec<usize>,
+}
+
+/// the number of nodes a search for key compares it with, a hit at the root is 1
+pub(crate) fn search_depth<K: PartialOrd, V, N: TreeNode<K, V>>(
+    root: Option<&N>,
+    key: &K,
+) -> usize {
+    let mut depth = 0;
+    let mut node = root;
+    while let Some(curr) = node {
+        depth += 1;
+        if *key < *curr.key() {
+            node = curr.smaller();
+        } else if *key > *curr.key() {
+            node = curr.larger();
+        } else {
+            break;
+        }
+    }
+    depth
+}
+
+/// Measures the tree below root without recursion.
+/// The black height is taken along the smallest keys, check_rules verifies that
+/// all other paths agree.
+pub(crate) fn shape_report<K, V, N: TreeNode<K, V>>(root: Option<&N>) -> ShapeReport {
+    let mut report = ShapeReport::default();
+    let mut stack: Vec<(&N, usize)> = root.map(|root| (root, 0)).into_iter().collect();
+    while let Some((node, depth)) = stack.pop() {
+        if report.per_depth.len() <= depth {
+            report.per_depth.push(0);
+        }
+        report.per_depth[depth] += 1;
+        report.nodes += 1;
+        for child in [node.smaller(), node.larger()].into_iter().flatten() {
+            stack.push((child, depth + 1));
+        }
+    }
+    report.height = report.per_depth.len();
+
+    let mut node = root;
+    while let Some(curr) = node {
+        match curr.color() {
+            Some(Color::Black) => *report.black_height.get_or_insert(0) += 1,
+            Some(Color::Red) => {
+                report.black_height.get_or_insert(0);
+            }
+            None => break,
+        }
+        node = curr.smaller();
+    }
+    report
+}
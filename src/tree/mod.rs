@@ -1,13 +1,41 @@
 #![allow(dead_code)]
 
+pub mod avl_tree;
+pub mod b_tree;
 pub mod binary_tree;
+#[cfg(feature = "std")]
+pub mod concurrent;
+mod diagram;
+pub mod dot;
+#[cfg(feature = "std")]
+pub mod durable;
+pub mod frozen;
+pub mod llrb;
+pub mod metrics;
+pub mod pretty;
 pub mod rb_tree;
+pub mod scapegoat_tree;
+#[cfg(feature = "serde")]
+mod serialize;
+mod shape;
+#[cfg(feature = "std")]
+pub mod snapshot;
+pub mod splay_tree;
+pub mod static_rb_tree;
+pub mod svg;
+#[cfg(test)]
+mod test_map;
+#[cfg(feature = "std")]
+pub mod treap;
 
-/*
-trait TreeNode<K, V> {
-    fn get_mut_key<'a>(&'a mut self) -> &'a mut K;
-    fn get_mut_value(&mut self) -> &mut V;
-    fn get_mut_smaller(&mut self) -> Option<&mut dyn TreeNode<K, V>>;
-    fn get_mut_larger(&mut self) -> Option<&mut dyn TreeNode<K, V>>;
+use rb_tree::Color;
+
+/// read access to the nodes of the binary trees, shared by the exporters
+pub(crate) trait TreeNode<K, V> {
+    fn key(&self) -> &K;
+    fn value(&self) -> &V;
+    fn smaller(&self) -> Option<&Self>;
+    fn larger(&self) -> Option<&Self>;
+    /// None for trees without colored nodes
+    fn color(&self) -> Option<Color>;
 }
-*/
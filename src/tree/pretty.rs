@@ -0,0 +1,228 @@
+use crate::prelude::*;
+use core::fmt::Debug;
+
+/// Options for the structural view of a tree
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrettyOptions {
+    /// show nodes up to this depth, the root has depth 0
+    pub max_depth: Option<usize>,
+    /// show at most this many nodes
+    pub max_nodes: Option<usize>,
+    /// show only the keys
+    pub hide_values: bool,
+    /// draw junctions with ASCII characters only
+    pub ascii: bool,
+    /// Color the nodes where colors show. They are off anyway if NO_COLOR is set,
+    /// stdout is not a terminal or the crate is built without std, and highlights
+    /// are then marked with a '*'.
+    pub color: bool,
+    /// keys of the nodes to highlight, as formatted by Debug
+    pub highlight: Vec<String>,
+}
+
+impl Default for PrettyOptions {
+    fn default() -> Self {
+        PrettyOptions {
+            max_depth: None,
+            max_nodes: None,
+            hide_values: false,
+            ascii: false,
+            color: true,
+            highlight: Vec::new(),
+        }
+    }
+}
+
+/// Displays the structure of a tree as:
+/// (kkk,vvv)
+///   ├─<(kkk,vvv)
+///   │   ├─<(kkk,vvv)
+///   │   └─>nil
+///   └─>(kkk,vvv)
+///       ├─<(kkk,vvv)
+///       └─>(kkk,vvv)
+pub struct Pretty<'a, T> {
+    pub(crate) tree: &'a T,
+    pub(crate) options: PrettyOptions,
+}
+
+impl<'a, T> Pretty<'a, T> {
+    pub(crate) fn new(tree: &'a T) -> Pretty<'a, T> {
+        Pretty {
+            tree,
+            options: PrettyOptions::default(),
+        }
+    }
+
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.options.max_depth = Some(max_depth);
+        self
+    }
+
+    pub fn max_nodes(mut self, max_nodes: usize) -> Self {
+        self.options.max_nodes = Some(max_nodes);
+        self
+    }
+
+    pub fn hide_values(mut self) -> Self {
+        self.options.hide_values = true;
+        self
+    }
+
+    pub fn ascii(mut self) -> Self {
+        self.options.ascii = true;
+        self
+    }
+
+    pub fn color(mut self, color: bool) -> Self {
+        self.options.color = color;
+        self
+    }
+
+    /// highlight the nodes with these keys, uncolored output marks them with a '*'
+    pub fn highlight<K: Debug>(mut self, keys: impl IntoIterator<Item = K>) -> Self {
+        self.options
+            .highlight
+            .extend(keys.into_iter().map(|key| format!("{:?}", key)));
+        self
+    }
+
+    pub fn options(mut self, options: PrettyOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+/// state shared by the nodes while rendering one tree
+pub(crate) struct Layout<'a> {
+    pub options: &'a PrettyOptions,
+    pub j_smaller: &'static str,
+    pub j_larger: &'static str,
+    pub l_smaller: &'static str,
+    pub l_larger: &'static str,
+    // options.color, unless the output can not show colors
+    color: bool,
+    remaining: Option<usize>,
+    truncated: bool,
+}
+
+impl<'a> Layout<'a> {
+    pub fn new(options: &'a PrettyOptions) -> Layout<'a> {
+        let (j_smaller, j_larger, l_smaller, l_larger) = if options.ascii {
+            (" `->", " +-<", "    ", " |  ")
+        } else {
+            (" └─>", " ├─<", "    ", " │  ")
+        };
+        Layout {
+            options,
+            j_smaller,
+            j_larger,
+            l_smaller,
+            l_larger,
+            color: options.color && paint::enabled(),
+            remaining: options.max_nodes,
+            truncated: false,
+        }
+    }
+
+    /// count one more node, false if the node budget is used up
+    pub fn take_node(&mut self) -> bool {
+        match &mut self.remaining {
+            Some(0) => false,
+            Some(remaining) => {
+                *remaining -= 1;
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// true the first time the output is cut short by max_nodes
+    pub fn truncate(&mut self) -> bool {
+        !core::mem::replace(&mut self.truncated, true)
+    }
+
+    pub fn below_max_depth(&self, depth: usize) -> bool {
+        self.options.max_depth.is_none_or(|max| depth <= max)
+    }
+
+    pub fn node_text(&self, key: &dyn Debug, value: &dyn Debug) -> String {
+        if self.options.hide_values {
+            format!("({:?})", key)
+        } else {
+            format!("({:?},{:?})", key, value)
+        }
+    }
+
+    pub fn paint_highlight(&self, key: &dyn Debug, text: String) -> String {
+        if self.options.highlight.is_empty()
+            || !self.options.highlight.contains(&format!("{:?}", key))
+        {
+            text
+        } else if self.color {
+            paint::highlight(text)
+        } else {
+            text + "*"
+        }
+    }
+
+    pub fn paint_red(&self, text: String) -> String {
+        if self.color {
+            paint::red(text)
+        } else {
+            text
+        }
+    }
+
+    pub fn paint_black(&self, text: String) -> String {
+        if self.color {
+            paint::black(text)
+        } else {
+            text
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+mod paint {
+    use colored::*;
+
+    /// false if NO_COLOR is set or stdout is not a terminal, as the colored crate sees it
+    pub fn enabled() -> bool {
+        control::SHOULD_COLORIZE.should_colorize()
+    }
+
+    pub fn highlight(text: String) -> String {
+        text.bold().on_yellow().to_string()
+    }
+
+    pub fn red(text: String) -> String {
+        text.red().to_string()
+    }
+
+    pub fn black(text: String) -> String {
+        text.blue().to_string()
+    }
+}
+
+/// the colored crate needs std, without it the output is always uncolored
+#[cfg(not(feature = "std"))]
+mod paint {
+    use crate::prelude::*;
+
+    pub fn enabled() -> bool {
+        false
+    }
+
+    pub fn highlight(text: String) -> String {
+        text
+    }
+
+    pub fn red(text: String) -> String {
+        text
+    }
+
+    pub fn black(text: String) -> String {
+        text
+    }
+}
@@ -1,19 +1,48 @@
-use crate::tree::rb_tree::rbtree_node::Color;
+use crate::prelude::*;
+use crate::tree::binary_tree::BTree;
+use crate::tree::diagram;
+use crate::tree::dot::{self, DotOptions};
+use crate::tree::frozen::FrozenMap;
+#[cfg(feature = "metrics")]
+use crate::tree::metrics::Stats;
+use crate::tree::metrics::{self, Metrics, ShapeReport};
+use crate::tree::pretty::{Layout, Pretty};
+use crate::tree::shape;
+#[cfg(feature = "std")]
+use crate::tree::snapshot::{self, Codec, SnapshotError};
+use crate::tree::svg::{self, SvgOptions};
 // use rand::Rng;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use core::cmp::Ordering;
+use core::fmt::{Debug, Display, Formatter};
+use core::hash::{Hash, Hasher};
+use core::ops::{Bound, Index, RangeBounds};
+use core::str::FromStr;
 use rbtree_node::RBTreeNode;
-use std::fmt::{Debug, Formatter};
-
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::hash::BuildHasher;
+#[cfg(feature = "std")]
+use std::io::{Read, Write};
+
+mod observer;
 mod rbtree_node;
 
-type SubNode<K, V> = Option<Box<RBTreeNode<K, V>>>;
+pub use observer::{TreeEvent, TreeObserver};
+
+pub use rbtree_node::{Color, RotDir};
 
-#[derive(PartialEq, Debug)]
+type SubNode<K, V> = Option<Arc<RBTreeNode<K, V>>>;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum Branch {
     Smaller,
     Larger,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum InsertState {
     Clean,
     Conflict,
@@ -22,8 +51,12 @@ pub enum InsertState {
     ChgdColor,
 }
 
-pub struct RBTree<K: PartialOrd, V> {
-    root: SubNode<K, V>,
+/// A red black tree, O is an observer that is told about every structural change.
+pub struct RBTree<K: PartialOrd, V, O = ()> {
+    pub(crate) root: SubNode<K, V>,
+    len: usize,
+    observer: O,
+    metrics: Metrics,
 }
 
 impl<K: PartialOrd + Debug, V: Debug> Default for RBTree<K, V> {
@@ -32,61 +65,70 @@ impl<K: PartialOrd + Debug, V: Debug> Default for RBTree<K, V> {
     }
 }
 
-// impl<K: PartialOrd + Debug, V: Debug> RBTree<K, V> {
-
 impl<K: PartialOrd + Debug, V: Debug> RBTree<K, V> {
     pub fn new() -> RBTree<K, V> {
-        RBTree { root: None }
+        RBTree {
+            root: None,
+            len: 0,
+            observer: (),
+            metrics: Metrics::default(),
+        }
     }
+}
 
-    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
-        let (res, insert_state) = if let Some(root) = &mut self.root {
-            root.insert_node_rb(Box::new(RBTreeNode::new(key, value)), true)
+impl<K: PartialOrd + Debug + Clone, V: Debug + Clone> RBTree<K, V> {
+    /// Builds a balanced tree in O(n) if the keys are strictly ascending.
+    /// Otherwise the entries are inserted one by one, later entries replace earlier ones.
+    pub fn from_sorted(entries: Vec<(K, V)>) -> RBTree<K, V> {
+        if entries.windows(2).all(|pair| pair[0].0 < pair[1].0) {
+            let len = entries.len();
+            RBTree::build_sorted(entries.into_iter(), len)
         } else {
-            self.root = Some(Box::new(RBTreeNode::new_black(key, value)));
-            return None;
-        };
-
-        // eprintln!("insert into root returned insert_state {:?}", insert_state);
+            entries.into_iter().collect()
+        }
+    }
 
-        match insert_state {
-            InsertState::Conflict => {
-                panic!("Unexpected conflict in root")
-            }
-            InsertState::Clean => res,
-            InsertState::ChgdColor => panic!("Unexpected insert state in root: {:?}", insert_state),
-            InsertState::LeftRotate => {
-                let root = self.root.take().expect("unexpected empty root node");
+    /// Loads a tree written by write_snapshot, see the snapshot module for the format.
+    /// The records are verified and collected first, the tree is then built in O(n).
+    #[cfg(feature = "std")]
+    pub fn read_snapshot(input: impl Read) -> Result<RBTree<K, V>, SnapshotError>
+    where
+        K: Codec,
+        V: Codec,
+    {
+        let entries = snapshot::read(input)?;
+        let len = entries.len();
+        Ok(RBTree::build_sorted(entries.into_iter(), len))
+    }
 
-                match root.left_rotate() {
-                    Ok(mut new_child) => {
-                        new_child.color = Color::Black;
-                        self.root = Some(new_child);
-                        res
-                    }
-                    Err((_old_child, err)) => {
-                        // eprintln!("Failed to rotate: {}\n{:?}", err, self);
-                        panic!("failed to left-rotate: {}", err);
-                    }
-                }
-            }
-            InsertState::RightRotate => {
-                let root = self.root.take().expect("unexpected empty root node");
-                match root.right_rotate() {
-                    Ok(mut new_child) => {
-                        new_child.color = Color::Black;
-                        self.root = Some(new_child);
-                        res
-                    }
-                    Err((_old_child, err)) => {
-                        // eprintln!("Failed to rotate: {}\n{:?}", err, self);
-                        panic!("failed to right-rotate: {}", err);
-                    }
-                }
-            }
+    /// builds the tree from len entries with strictly ascending keys
+    pub(crate) fn build_sorted(
+        mut entries: impl Iterator<Item = (K, V)>,
+        len: usize,
+    ) -> RBTree<K, V> {
+        RBTree {
+            root: RBTreeNode::build_sorted(&mut entries, len, 0, red_depth(len)),
+            len,
+            observer: (),
+            metrics: Metrics::default(),
         }
     }
+}
 
+/// The depth to color red in a tree of len nodes with all levels but the deepest one
+/// complete. The deepest level is red unless it is complete too, so every path has the
+/// same number of black nodes.
+fn red_depth(len: usize) -> usize {
+    if (len + 1).is_power_of_two() {
+        usize::MAX
+    } else {
+        (usize::BITS - len.leading_zeros()) as usize - 1
+    }
+}
+
+// impl<K: PartialOrd + Debug, V: Debug> RBTree<K, V> {
+
+impl<K: PartialOrd + Debug, V: Debug, O> RBTree<K, V, O> {
     pub fn traverse_asc(&self, func: &mut dyn FnMut(&K, &V)) {
         if let Some(node) = &self.root {
             node.traverse_asc(func);
@@ -94,115 +136,36 @@ impl<K: PartialOrd + Debug, V: Debug> RBTree<K, V> {
     }
 
     pub fn contains(&self, key: &K) -> bool {
-        if let Some(node) = &self.root {
-            let mut curr = node;
-            // loop {
-            loop {
-                if *key < curr.key {
-                    if let Some(subnode) = &curr.smaller {
-                        curr = subnode;
-                    } else {
-                        return false;
-                    }
-                } else if *key > curr.key {
-                    if let Some(subnode) = &curr.larger {
-                        curr = subnode;
-                    } else {
-                        return false;
-                    }
-                } else {
-                    return true;
-                }
-            }
-        } else {
-            false
-        }
+        self.find(key).is_some()
     }
 
     pub fn find(&self, key: &K) -> Option<&V> {
-        if let Some(node) = &self.root {
+        let mut depth = 0;
+        let res = if let Some(node) = &self.root {
             let mut curr = node;
             loop {
+                depth += 1;
                 if *key < curr.key {
                     if let Some(subnode) = &curr.smaller {
                         curr = subnode;
                     } else {
-                        return None;
+                        break None;
                     }
                 } else if *key > curr.key {
                     if let Some(subnode) = &curr.larger {
                         curr = subnode;
                     } else {
-                        return None;
+                        break None;
                     }
                 } else {
-                    return Some(&curr.value);
+                    break Some(&curr.value);
                 }
             }
         } else {
             None
-        }
-    }
-
-    pub fn find_mut(&mut self, key: &K) -> Option<&mut V> {
-        if let Some(node) = &mut self.root {
-            let mut curr = node;
-            loop {
-                if *key < curr.key {
-                    if let Some(subnode) = &mut curr.smaller {
-                        curr = subnode;
-                    } else {
-                        return None;
-                    }
-                } else if *key > curr.key {
-                    if let Some(subnode) = &mut curr.larger {
-                        curr = subnode;
-                    } else {
-                        return None;
-                    }
-                } else {
-                    return Some(&mut curr.value);
-                }
-            }
-        } else {
-            None
-        }
-    }
-
-    // TODO: Find out how to return a value from a deleted entry
-    // TODO: try recursive version first
-
-    pub fn remove(&mut self, key: &K) -> Option<V> {
-        if let Some(true) = self.root.as_ref().map(|root| root.key == *key) {
-            // delete the root
-            let mut root = self.root.take().expect("unexpected empty link");
-            let (res, new_root) = if root.smaller.is_some() {
-                if root.larger.is_some() {
-                    // root has two siblings - swap root with next larger, delete next larger
-                    let (key, value) = root.remove_next_larger();
-                    root.key = key;
-                    let res = std::mem::replace(&mut root.value, value);
-                    (Some(res), Some(root))
-                } else {
-                    // root becomes root.smaller
-                    (Some(root.value), root.smaller.take())
-                }
-            } else if root.larger.is_some() {
-                // root becomes root.larger
-                (Some(root.value), root.larger.take())
-            } else {
-                // the tree is empty
-                (Some(root.value), None)
-            };
-            if new_root.is_some() {
-                self.root = new_root;
-            }
-            res
-        } else if let Some(root) = &mut self.root {
-            root.remove(key)
-        } else {
-            None
-        }
+        };
+        self.metrics.record_search(depth);
+        res
     }
 
     fn smallest_node(&self) -> Option<&RBTreeNode<K, V>> {
@@ -223,10 +186,12 @@ impl<K: PartialOrd + Debug, V: Debug> RBTree<K, V> {
     }
 
     fn smaller_node(&self, key: &K) -> Option<&RBTreeNode<K, V>> {
-        if let Some(root) = &self.root {
+        let mut depth = 0;
+        let res: Option<&RBTreeNode<K, V>> = if let Some(root) = &self.root {
             let mut candidate: Option<&RBTreeNode<K, V>> = None;
             let mut curr = root;
             loop {
+                depth += 1;
                 // eprintln!("smaller_none({:?}), curr {:?}", key, curr.key);
                 if curr.key < *key {
                     // search larger
@@ -235,7 +200,7 @@ impl<K: PartialOrd + Debug, V: Debug> RBTree<K, V> {
                         candidate = Some(curr);
                         curr = larger;
                     } else {
-                        return Some(curr);
+                        break Some(curr);
                     }
                 } else if curr.key >= *key {
                     // search smaller
@@ -243,13 +208,15 @@ impl<K: PartialOrd + Debug, V: Debug> RBTree<K, V> {
                     if let Some(smaller) = &curr.smaller {
                         curr = smaller;
                     } else {
-                        return candidate;
+                        break candidate;
                     }
                 }
             }
         } else {
             None
-        }
+        };
+        self.metrics.record_comparisons(depth);
+        res
     }
 
     pub fn smaller(&self, key: &K) -> Option<(&K, &V)> {
@@ -273,10 +240,12 @@ impl<K: PartialOrd + Debug, V: Debug> RBTree<K, V> {
     }
 
     fn larger_node(&self, key: &K) -> Option<&RBTreeNode<K, V>> {
-        if let Some(root) = &self.root {
+        let mut depth = 0;
+        let res: Option<&RBTreeNode<K, V>> = if let Some(root) = &self.root {
             let mut candidate: Option<&RBTreeNode<K, V>> = None;
             let mut curr = root;
             loop {
+                depth += 1;
                 // eprintln!("smaller_none({:?}), curr {:?}", key, curr.key);
                 if curr.key > *key {
                     // search smaller
@@ -285,7 +254,7 @@ impl<K: PartialOrd + Debug, V: Debug> RBTree<K, V> {
                         candidate = Some(curr);
                         curr = smaller;
                     } else {
-                        return Some(curr);
+                        break Some(curr);
                     }
                 } else if curr.key <= *key {
                     // search larger
@@ -293,20 +262,22 @@ impl<K: PartialOrd + Debug, V: Debug> RBTree<K, V> {
                     if let Some(larger) = &curr.larger {
                         curr = larger;
                     } else {
-                        return candidate;
+                        break candidate;
                     }
                 }
             }
         } else {
             None
-        }
+        };
+        self.metrics.record_comparisons(depth);
+        res
     }
 
     pub fn larger(&self, key: &K) -> Option<(&K, &V)> {
         self.larger_node(key).map(|node| (&node.key, &node.value))
     }
 
-    pub fn check_rules(&self) -> std::result::Result<usize, String> {
+    pub fn check_rules(&self) -> core::result::Result<usize, String> {
         if let Some(root) = &self.root {
             root.check_rules(true, false)
         } else {
@@ -317,12 +288,560 @@ impl<K: PartialOrd + Debug, V: Debug> RBTree<K, V> {
     pub fn is_empty(&self) -> bool {
         self.root.is_none()
     }
+
+    /// the number of entries
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// the tree as a Graphviz digraph, render it with `dot -Tsvg`
+    pub fn to_dot(&self) -> String {
+        self.to_dot_with(&DotOptions::default())
+    }
+
+    pub fn to_dot_with(&self, options: &DotOptions<K>) -> String {
+        dot::to_dot::<K, V, _>("RBTree", self.root.as_deref(), options)
+    }
+
+    /// the tree as a standalone SVG document, laid out without Graphviz
+    pub fn to_svg(&self) -> String {
+        self.to_svg_with(&SvgOptions::default())
+    }
+
+    pub fn to_svg_with(&self, options: &SvgOptions<K>) -> String {
+        svg::to_svg::<K, V, _>(self.root.as_deref(), options)
+    }
 }
 
-impl<K: PartialOrd + Debug, V: Debug> Debug for RBTree<K, V> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        if let Some(root) = &self.root {
-            write!(f, "{}", root.to_string())
+impl<K: PartialOrd + FromStr, V: FromStr + Default> RBTree<K, V> {
+    /// Builds the tree drawn by a pretty() diagram as it is, without rebalancing.
+    /// Nodes without a color marker are black, hidden values are V::default().
+    pub fn from_diagram(diagram: &str) -> Result<RBTree<K, V>, String> {
+        let root = diagram::parse(diagram, |color, key, value, smaller, larger| {
+            Ok(Arc::new(RBTreeNode {
+                key,
+                value,
+                color: color.unwrap_or(Color::Black),
+                smaller,
+                larger,
+            }))
+        })?;
+        let mut tree = RBTree {
+            root,
+            len: 0,
+            observer: (),
+            metrics: Metrics::default(),
+        };
+        tree.len = tree.iter().count();
+        Ok(tree)
+    }
+}
+
+impl<K: PartialOrd + Display, V: Display, O> RBTree<K, V, O> {
+    /// the exact shape and colors of the tree as text, from_shape rebuilds it
+    pub fn to_shape(&self) -> String {
+        shape::encode::<K, V, _>(self.root.as_deref())
+    }
+}
+
+impl<K: PartialOrd + FromStr, V: FromStr> RBTree<K, V> {
+    /// Rebuilds a tree written by to_shape node by node, without rebalancing.
+    /// The result is not checked, use check_rules to see if it is a valid red black tree.
+    pub fn from_shape(shape: &str) -> Result<RBTree<K, V>, String> {
+        let root = shape::decode(shape, |color, key, value, smaller, larger| {
+            Ok(Arc::new(RBTreeNode {
+                key,
+                value,
+                color: color.ok_or("missing color in a red black tree")?,
+                smaller,
+                larger,
+            }))
+        })?;
+        let mut tree = RBTree {
+            root,
+            len: 0,
+            observer: (),
+            metrics: Metrics::default(),
+        };
+        tree.len = tree.iter().count();
+        Ok(tree)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: PartialOrd + Codec, V: Codec, O> RBTree<K, V, O> {
+    /// writes all entries in ascending order, see the snapshot module for the format
+    pub fn write_snapshot(&self, out: &mut impl Write) -> Result<(), SnapshotError> {
+        snapshot::write(self.iter(), self.len, out)
+    }
+}
+
+/// Changes copy the nodes still shared with a clone, which is why insert, find_mut and
+/// remove need K: Clone and V: Clone. Before the trees could be cloned cheaply these
+/// methods had no Clone bounds, callers with keys or values that are not Clone have
+/// to wrap them in an Arc or Rc.
+impl<K: PartialOrd + Debug + Clone, V: Debug + Clone, O: TreeObserver<K>> RBTree<K, V, O> {
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        // the metrics count rotations and recolors alongside the attached observer
+        let observer = &mut (&mut self.observer, &self.metrics);
+        let (res, insert_state) = if let Some(root) = &mut self.root {
+            let mut depth = 0;
+            let inserted = Arc::make_mut(root).insert_node_rb(
+                RBTreeNode::new(key, value),
+                true,
+                observer,
+                &mut depth,
+            );
+            self.metrics.record_search(depth);
+            inserted
+        } else {
+            self.metrics.record_search(0);
+            observer.on_event(TreeEvent::NodeInserted { key: &key });
+            self.root = Some(Arc::new(RBTreeNode::new_black(key, value)));
+            self.len += 1;
+            return None;
+        };
+        if res.is_none() {
+            self.len += 1;
+        }
+
+        // eprintln!("insert into root returned insert_state {:?}", insert_state);
+
+        match insert_state {
+            InsertState::Conflict => {
+                panic!("Unexpected conflict in root")
+            }
+            InsertState::Clean => res,
+            InsertState::ChgdColor => panic!("Unexpected insert state in root: {:?}", insert_state),
+            InsertState::LeftRotate => {
+                let root = self.root.take().expect("unexpected empty root node");
+                observer.on_event(TreeEvent::InsertCase {
+                    key: &root.key,
+                    state: insert_state,
+                });
+
+                match root.left_rotate(observer) {
+                    Ok(mut new_child) => {
+                        Arc::make_mut(&mut new_child).recolor(Color::Black, observer);
+                        self.root = Some(new_child);
+                        res
+                    }
+                    Err((_old_child, err)) => {
+                        // eprintln!("Failed to rotate: {}\n{:?}", err, self);
+                        panic!("failed to left-rotate: {}", err);
+                    }
+                }
+            }
+            InsertState::RightRotate => {
+                let root = self.root.take().expect("unexpected empty root node");
+                observer.on_event(TreeEvent::InsertCase {
+                    key: &root.key,
+                    state: insert_state,
+                });
+
+                match root.right_rotate(observer) {
+                    Ok(mut new_child) => {
+                        Arc::make_mut(&mut new_child).recolor(Color::Black, observer);
+                        self.root = Some(new_child);
+                        res
+                    }
+                    Err((_old_child, err)) => {
+                        // eprintln!("Failed to rotate: {}\n{:?}", err, self);
+                        panic!("failed to right-rotate: {}", err);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn find_mut(&mut self, key: &K) -> Option<&mut V> {
+        // search first, a miss must not copy the path shared with a clone
+        self.find(key)?;
+        let mut curr = Arc::make_mut(self.root.as_mut()?);
+        loop {
+            let link = if *key < curr.key {
+                &mut curr.smaller
+            } else if *key > curr.key {
+                &mut curr.larger
+            } else {
+                return Some(&mut curr.value);
+            };
+            curr = Arc::make_mut(link.as_mut().expect("unexpected missing key"));
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        // search first, a miss must not copy the path shared with a clone
+        self.find(key)?;
+        let observer = &mut (&mut self.observer, &self.metrics);
+        let (res, _) = RBTreeNode::remove_rb(&mut self.root, key, observer);
+        self.len -= 1;
+        if let Some(root) = &mut self.root {
+            if root.color != Color::Black {
+                Arc::make_mut(root).recolor(Color::Black, observer);
+            }
+        }
+        res
+    }
+}
+
+impl<K: PartialOrd, V, O: Clone> Clone for RBTree<K, V, O> {
+    /// the clone shares all nodes with self, nodes are copied when either tree modifies them
+    fn clone(&self) -> Self {
+        RBTree {
+            root: self.root.clone(),
+            len: self.len,
+            observer: self.observer.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+impl<K: PartialOrd, V> RBTree<K, V> {
+    /// attach an observer, it sees all changes made from now on
+    pub fn with_observer<O: TreeObserver<K>>(self, observer: O) -> RBTree<K, V, O> {
+        RBTree {
+            root: self.root,
+            len: self.len,
+            observer,
+            metrics: self.metrics,
+        }
+    }
+}
+
+impl<K: PartialOrd, V, O> RBTree<K, V, O> {
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    /// detach the observer, returns the unobserved tree and the observer
+    pub fn detach(self) -> (RBTree<K, V>, O) {
+        (
+            RBTree {
+                root: self.root,
+                len: self.len,
+                observer: (),
+                metrics: self.metrics,
+            },
+            self.observer,
+        )
+    }
+
+    /// the number of nodes a search for key visits, the search is not counted
+    pub fn search_depth(&self, key: &K) -> usize {
+        metrics::search_depth::<K, V, _>(self.root.as_deref(), key)
+    }
+
+    /// height, black height and nodes per level
+    pub fn shape_report(&self) -> ShapeReport {
+        metrics::shape_report::<K, V, _>(self.root.as_deref())
+    }
+
+    /// counters collected since the tree was created or reset_stats was called
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> Stats {
+        self.metrics.stats()
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn reset_stats(&mut self) {
+        self.metrics.reset()
+    }
+
+    /// structural view of the tree, use {} to print it
+    pub fn pretty(&self) -> Pretty<'_, Self> {
+        Pretty::new(self)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.push_smaller(self.root.as_deref());
+        iter
+    }
+
+    /// ascending iterator over the entries with keys in range
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Range<'_, K, V, R> {
+        let mut iter = Iter { stack: Vec::new() };
+        // push the path to the first key in range, skipping everything below
+        let mut node = self.root.as_deref();
+        let mut depth = 0;
+        while let Some(curr) = node {
+            depth += 1;
+            let above_start = match range.start_bound() {
+                Bound::Included(start) => curr.key >= *start,
+                Bound::Excluded(start) => curr.key > *start,
+                Bound::Unbounded => true,
+            };
+            if above_start {
+                iter.stack.push(curr);
+                node = curr.smaller.as_deref();
+            } else {
+                node = curr.larger.as_deref();
+            }
+        }
+        if matches!(range.start_bound(), Bound::Unbounded) {
+            depth = 0;
+        }
+        self.metrics.record_comparisons(depth);
+        Range {
+            iter,
+            range,
+            metrics: &self.metrics,
+        }
+    }
+}
+
+/// ascending iterator over the entries of an RBTree
+pub struct Iter<'a, K: PartialOrd, V> {
+    // nodes still to be visited, the next one on top
+    stack: Vec<&'a RBTreeNode<K, V>>,
+}
+
+impl<'a, K: PartialOrd, V> Iter<'a, K, V> {
+    fn push_smaller(&mut self, mut node: Option<&'a RBTreeNode<K, V>>) {
+        while let Some(curr) = node {
+            self.stack.push(curr);
+            node = curr.smaller.as_deref();
+        }
+    }
+}
+
+impl<'a, K: PartialOrd, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_smaller(node.larger.as_deref());
+        Some((&node.key, &node.value))
+    }
+}
+
+/// ascending iterator over the entries of an RBTree within a range of keys
+pub struct Range<'a, K: PartialOrd, V, R> {
+    iter: Iter<'a, K, V>,
+    range: R,
+    // counts the comparisons with the end of the range
+    metrics: &'a Metrics,
+}
+
+impl<'a, K: PartialOrd, V, R: RangeBounds<K>> Iterator for Range<'a, K, V, R> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = self.iter.next()?;
+        let below_end = match self.range.end_bound() {
+            Bound::Included(end) => *key <= *end,
+            Bound::Excluded(end) => *key < *end,
+            Bound::Unbounded => true,
+        };
+        if !matches!(self.range.end_bound(), Bound::Unbounded) {
+            self.metrics.record_comparisons(1);
+        }
+        if below_end {
+            Some((key, value))
+        } else {
+            self.iter.stack.clear();
+            None
+        }
+    }
+}
+
+/// moving iterator over the entries of an RBTree in ascending order,
+/// nodes still shared with a clone are copied
+pub struct IntoIter<K: PartialOrd, V> {
+    stack: Vec<RBTreeNode<K, V>>,
+}
+
+impl<K: PartialOrd + Clone, V: Clone> IntoIter<K, V> {
+    fn push_smaller(&mut self, mut link: SubNode<K, V>) {
+        while let Some(node) = link {
+            let mut node = Arc::unwrap_or_clone(node);
+            link = node.smaller.take();
+            self.stack.push(node);
+        }
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut node = self.stack.pop()?;
+        self.push_smaller(node.larger.take());
+        Some((node.key, node.value))
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone, O> RBTree<K, V, O> {
+    /// Moves the entries into a read-only map in Eytzinger layout, for indexes that
+    /// are built once and queried often. FrozenMap::thaw turns it back into a tree.
+    pub fn freeze(self) -> FrozenMap<K, V> {
+        FrozenMap::from_sorted(self.into_iter().collect())
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone, O> IntoIterator for RBTree<K, V, O> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(mut self) -> IntoIter<K, V> {
+        let mut iter = IntoIter { stack: Vec::new() };
+        iter.push_smaller(self.root.take());
+        iter
+    }
+}
+
+impl<'a, K: PartialOrd, V, O> IntoIterator for &'a RBTree<K, V, O> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+// equality, ordering and hashing compare the entries in ascending order, not the shape
+
+impl<K: PartialOrd, V: PartialEq, O> PartialEq for RBTree<K, V, O> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl<K: PartialOrd + Eq, V: Eq, O> Eq for RBTree<K, V, O> {}
+
+impl<K: PartialOrd, V: PartialOrd, O> PartialOrd for RBTree<K, V, O> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+
+impl<K: Ord, V: Ord, O> Ord for RBTree<K, V, O> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl<K: PartialOrd + Hash, V: Hash, O> Hash for RBTree<K, V, O> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut len = 0;
+        for entry in self.iter() {
+            entry.hash(state);
+            len += 1;
+        }
+        state.write_usize(len);
+    }
+}
+
+impl<K: PartialOrd + Debug, V: Debug, O> Index<&K> for RBTree<K, V, O> {
+    type Output = V;
+
+    fn index(&self, key: &K) -> &V {
+        self.find(key).expect("key not found in RBTree")
+    }
+}
+
+impl<K: PartialOrd + Debug + Clone, V: Debug + Clone, O: TreeObserver<K>> Extend<(K, V)>
+    for RBTree<K, V, O>
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: PartialOrd + Debug + Clone, V: Debug + Clone> FromIterator<(K, V)> for RBTree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tree = RBTree::new();
+        tree.extend(iter);
+        tree
+    }
+}
+
+impl<K: PartialOrd + Debug + Clone, V: Debug + Clone, const N: usize> From<[(K, V); N]>
+    for RBTree<K, V>
+{
+    fn from(entries: [(K, V); N]) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+impl<K: PartialOrd + Debug + Clone, V: Debug + Clone> From<Vec<(K, V)>> for RBTree<K, V> {
+    fn from(entries: Vec<(K, V)>) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+impl<K: PartialOrd + Debug + Clone, V: Debug + Clone> From<BTreeMap<K, V>> for RBTree<K, V> {
+    fn from(map: BTreeMap<K, V>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+impl<K: PartialOrd + Debug + Clone, V: Debug + Clone> From<BTree<K, V>> for RBTree<K, V> {
+    /// Rebalances the BTree with Day-Stout-Warren and copies it over in the same shape,
+    /// colored by depth, in O(n) without inserting the entries one by one. Keys and
+    /// values are moved, only nodes still shared with a clone of the BTree are cloned.
+    ///
+    /// The nodes themselves are not moved over, every entry gets a new RBTreeNode and
+    /// its BTreeNode is freed. An RBTreeNode is a different type with a color, reusing
+    /// the allocation would take either one node type for BTree and RBTree or an unsafe
+    /// cast between the two, and a one-off conversion is not worth either.
+    fn from(mut tree: BTree<K, V>) -> Self {
+        let len = tree.rebalance_len();
+        let mut res = RBTree::new();
+        res.root = RBTreeNode::from_btree(tree.root.take(), 0, red_depth(len));
+        res.len = len;
+        res
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: PartialOrd + Debug + Clone, V: Debug + Clone, S> From<HashMap<K, V, S>> for RBTree<K, V> {
+    fn from(map: HashMap<K, V, S>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone, O> From<RBTree<K, V, O>> for Vec<(K, V)> {
+    fn from(tree: RBTree<K, V, O>) -> Self {
+        tree.into_iter().collect()
+    }
+}
+
+impl<K: Ord + Clone, V: Clone, O> From<RBTree<K, V, O>> for BTreeMap<K, V> {
+    fn from(tree: RBTree<K, V, O>) -> Self {
+        tree.into_iter().collect()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: PartialOrd + Eq + Hash + Clone, V: Clone, O, S: BuildHasher + Default> From<RBTree<K, V, O>>
+    for HashMap<K, V, S>
+{
+    fn from(tree: RBTree<K, V, O>) -> Self {
+        tree.into_iter().collect()
+    }
+}
+
+impl<K: PartialOrd + Debug, V: Debug, O> Debug for RBTree<K, V, O> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: PartialOrd + Debug, V: Debug, O> Display for Pretty<'_, RBTree<K, V, O>> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        if let Some(root) = &self.tree.root {
+            let mut buffer = String::new();
+            let mut layout = Layout::new(&self.options);
+            root.to_str_buffer(&mut buffer, "", true, false, 0, &mut layout);
+            write!(f, "{}", buffer)
         } else {
             write!(f, "nil")
         }
@@ -332,6 +851,7 @@ impl<K: PartialOrd + Debug, V: Debug> Debug for RBTree<K, V> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::tree::test_map::{self, test_map_impl};
     use rand::Rng;
 
     #[test]
@@ -383,14 +903,14 @@ mod test {
         for value in values {
             assert_eq!(tree.insert(value, value.to_string()), None);
         }
-        eprintln!("{:?}\n", &tree);
+        eprintln!("{}\n", tree.pretty());
 
         for value in values {
             assert_eq!(tree.remove(&value), Some(value.to_string()));
             assert!(!tree.contains(&value));
 
             if let Err(msg) = tree.check_rules() {
-                eprintln!("tree rule violation: {} in \n{:?}", msg, tree);
+                eprintln!("tree rule violation: {} in \n{}", msg, tree.pretty());
                 panic!("tree rule violation: {}", msg);
             }
 
@@ -419,6 +939,12 @@ mod test {
                 }
             }
         }
+        assert_eq!(
+            tree.insert(list[0], String::new()),
+            Some(list[0].to_string())
+        );
+        tree.insert(list[0], list[0].to_string());
+        assert_eq!(tree.len(), MAX as usize);
 
         while !list.is_empty() {
             let index = rng.gen_range(0..list.len());
@@ -426,6 +952,7 @@ mod test {
             assert_eq!(tree.remove(&key), Some(key.to_string()));
             assert!(!tree.contains(&key));
             assert_eq!(tree.remove(&key), None);
+            assert_eq!(tree.len(), list.len());
 
             if let Err(msg) = tree.check_rules() {
                 panic!("tree rule violation: {}", msg);
@@ -444,6 +971,38 @@ mod test {
         assert!(tree.is_empty());
     }
 
+    #[test]
+    fn rb_test_remove_rebalance() {
+        // removing the root over and over runs through the two sibling case
+        let mut tree = RBTree::new();
+        for key in 0..200u32 {
+            tree.insert(key, key);
+        }
+        while let Some(root) = tree.root.as_ref().map(|root| root.key) {
+            assert_eq!(tree.remove(&root), Some(root));
+            assert_eq!(tree.remove(&root), None);
+            tree.check_rules()
+                .unwrap_or_else(|msg| panic!("{} after remove of {}\n{:?}", msg, root, tree));
+        }
+
+        // mixed inserts and removes hit the red sibling and the nephew cases
+        let mut rng = rand::thread_rng();
+        let mut tree = RBTree::new();
+        let mut present = [false; 300];
+        for _ in 0..20000 {
+            let key = rng.gen_range(0..300u32);
+            if rng.gen_bool(0.5) {
+                assert_eq!(tree.insert(key, key).is_some(), present[key as usize]);
+                present[key as usize] = true;
+            } else {
+                assert_eq!(tree.remove(&key).is_some(), present[key as usize]);
+                present[key as usize] = false;
+            }
+            tree.check_rules()
+                .unwrap_or_else(|msg| panic!("{} after update of {}\n{:?}", msg, key, tree));
+        }
+    }
+
     #[test]
     fn rb_test_find_mut() {
         let values = [10u32, 20, 5, 15, 25, 3, 8];
@@ -503,7 +1062,7 @@ mod test {
         let mut iter = values.iter();
         let mut key = iter.next().expect("No test cases found");
         for val in iter {
-            if let Some((lkey, lval)) = tree.smaller(&key) {
+            if let Some((lkey, lval)) = tree.smaller(key) {
                 eprintln!("looking for smaller than {:?}, got {:?}", key, lkey);
                 assert_eq!(val, lkey);
                 assert_eq!(val.to_string(), *lval);
@@ -514,7 +1073,7 @@ mod test {
             }
         }
 
-        assert_eq!(tree.smaller(&key), None);
+        assert_eq!(tree.smaller(key), None);
 
         for val in values {
             if let Some((lkey, lval)) = tree.smaller(&(val + 1)) {
@@ -541,7 +1100,7 @@ mod test {
         let mut iter = values.iter();
         let mut key = iter.next().expect("empty test array");
         for val in iter {
-            if let Some((lkey, lval)) = tree.larger(&key) {
+            if let Some((lkey, lval)) = tree.larger(key) {
                 eprintln!("looking for smaller than {:?}, got {:?}", key, lkey);
                 assert_eq!(val, lkey);
                 assert_eq!(val.to_string(), *lval);
@@ -552,7 +1111,7 @@ mod test {
             }
         }
 
-        assert_eq!(tree.larger(&key), None);
+        assert_eq!(tree.larger(key), None);
 
         for val in values {
             if let Some((lkey, lval)) = tree.larger(&(val - 1)) {
@@ -576,9 +1135,34 @@ mod test {
             assert_eq!(tree.insert(value, value.to_string()), None);
         }
         for val in values {
-            assert_eq!(tree.contains(&val), true);
+            assert!(tree.contains(&val));
+        }
+        assert!(!tree.contains(&100));
+    }
+
+    fn check(tree: &RBTree<u32, u32>) {
+        if let Err(msg) = tree.check_rules() {
+            panic!("tree rule violation: {}", msg);
+        }
+    }
+
+    test_map_impl!(RBTree<u32, u32>, check);
+
+    fn node_ptrs<K: PartialOrd, V>(link: &SubNode<K, V>, ptrs: &mut Vec<usize>) {
+        if let Some(node) = link {
+            ptrs.push(Arc::as_ptr(node) as usize);
+            node_ptrs(&node.smaller, ptrs);
+            node_ptrs(&node.larger, ptrs);
         }
-        assert_eq!(tree.contains(&100), false);
+    }
+
+    #[test]
+    fn rb_test_clone() {
+        test_map::check_clone(|tree: &RBTree<u32, u32>| {
+            let mut ptrs = Vec::new();
+            node_ptrs(&tree.root, &mut ptrs);
+            ptrs
+        });
     }
 
     #[test]
@@ -601,15 +1185,519 @@ mod test {
         });
     }
 
+    #[test]
+    fn rb_test_iter() {
+        let mut values = [10u32, 20, 5, 15, 25, 3, 8];
+
+        let mut tree: RBTree<u32, String> = RBTree::new();
+        assert_eq!(tree.iter().next(), None);
+        for value in values {
+            assert_eq!(tree.insert(value, value.to_string()), None);
+        }
+
+        values.sort();
+        let entries: Vec<(u32, String)> = tree
+            .iter()
+            .map(|(key, value)| (*key, value.clone()))
+            .collect();
+        let expected: Vec<(u32, String)> = values.iter().map(|v| (*v, v.to_string())).collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn rb_test_range() {
+        let mut tree: RBTree<u32, String> = RBTree::new();
+        for value in (10..=100).step_by(10) {
+            assert_eq!(tree.insert(value, value.to_string()), None);
+        }
+
+        fn keys<R: RangeBounds<u32>>(iter: Range<u32, String, R>) -> Vec<u32> {
+            iter.map(|(key, _)| *key).collect()
+        }
+        assert_eq!(keys(tree.range(30..60)), vec![30, 40, 50]);
+        assert_eq!(keys(tree.range(25..=60)), vec![30, 40, 50, 60]);
+        assert_eq!(keys(tree.range(..20)), vec![10]);
+        assert_eq!(keys(tree.range(95..)), vec![100]);
+        assert_eq!(keys(tree.range(41..49)), Vec::<u32>::new());
+        assert_eq!(
+            keys(tree.range((Bound::Excluded(10), Bound::Excluded(40)))),
+            vec![20, 30]
+        );
+        assert_eq!(tree.range(..).count(), 10);
+    }
+
+    #[test]
+    fn rb_test_std_traits() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let tree = RBTree::from([(3u32, "c"), (1, "a"), (2, "b")]);
+        // same content, different insertion order and shape
+        let mut other: RBTree<u32, &str> = (1..=10u32).rev().map(|key| (key, "x")).collect();
+        for key in 4..=10 {
+            assert_eq!(other.remove(&key), Some("x"));
+        }
+        other.extend([(1, "a"), (2, "b"), (3, "c")]);
+        assert_eq!(tree, other);
+
+        let hash = |tree: &RBTree<u32, &str>| {
+            let mut hasher = DefaultHasher::new();
+            tree.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&tree), hash(&other));
+
+        other.insert(4, "d");
+        assert_ne!(tree, other);
+        assert!(tree < other);
+        assert_eq!(tree.cmp(&other), Ordering::Less);
+        assert_eq!(tree.partial_cmp(&tree.clone()), Some(Ordering::Equal));
+        assert!(RBTree::from([(1u32, "z")]) > other);
+
+        assert_eq!(tree[&2], "b");
+
+        let vec: Vec<(u32, &str)> = tree.clone().into();
+        assert_eq!(vec, vec![(1, "a"), (2, "b"), (3, "c")]);
+        assert_eq!(RBTree::from(vec.clone()), tree);
+
+        let map: BTreeMap<u32, &str> = tree.clone().into();
+        assert_eq!(map.into_iter().collect::<Vec<_>>(), vec);
+        #[cfg(feature = "std")]
+        {
+            let map: std::collections::HashMap<u32, &str> = tree.clone().into();
+            assert_eq!(map.len(), 3);
+            assert_eq!(RBTree::from(map), tree);
+        }
+        assert_eq!(
+            RBTree::from(BTreeMap::from([(2, "b"), (1, "a"), (3, "c")])),
+            tree
+        );
+
+        let mut keys = Vec::new();
+        for (key, _) in &tree {
+            keys.push(*key);
+        }
+        assert_eq!(keys, vec![1, 2, 3]);
+
+        // moving out of a tree that shares nodes with a clone leaves the clone intact
+        let clone = other.clone();
+        assert_eq!(other.into_iter().count(), 4);
+        assert_eq!(clone.iter().count(), 4);
+        if let Err(msg) = clone.check_rules() {
+            panic!("tree rule violation: {}", msg);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "key not found")]
+    fn rb_test_index_missing() {
+        let tree = RBTree::from([(1u32, 1u32)]);
+        let _ = tree[&2];
+    }
+
+    #[test]
+    fn rb_test_debug() {
+        let tree: RBTree<u32, String> = (1..=3).map(|key| (key, key.to_string())).collect();
+        assert_eq!(format!("{:?}", tree), r#"{1: "1", 2: "2", 3: "3"}"#);
+        assert_eq!(format!("{:?}", RBTree::<u32, u32>::new()), "{}");
+    }
+
+    #[test]
+    fn rb_test_pretty() {
+        let tree: RBTree<u32, String> = (1..=7).map(|key| (key, key.to_string())).collect();
+        assert_eq!(
+            tree.pretty().color(false).to_string(),
+            r#"B(2,"2")
+ ├─<R(4,"4")
+ │   ├─<B(6,"6")
+ │   │   ├─<R(7,"7")
+ │   │   └─>R(5,"5")
+ │   └─>B(3,"3")
+ └─>B(1,"1")
+"#
+        );
+        assert_eq!(
+            tree.pretty().color(false).ascii().hide_values().to_string(),
+            r#"B(2)
+ +-<R(4)
+ |   +-<B(6)
+ |   |   +-<R(7)
+ |   |   `->R(5)
+ |   `->B(3)
+ `->B(1)
+"#
+        );
+        assert_eq!(
+            tree.pretty().color(false).max_depth(1).to_string(),
+            r#"B(2,"2")
+ ├─<R(4,"4")
+ │   └─>...
+ └─>B(1,"1")
+"#
+        );
+        assert_eq!(
+            tree.pretty().color(false).max_nodes(3).to_string(),
+            r#"B(2,"2")
+ ├─<R(4,"4")
+ │   ├─<B(6,"6")
+ │   │   ├─<...
+"#
+        );
+        assert_eq!(RBTree::<u32, u32>::new().pretty().to_string(), "nil");
+    }
+
+    #[test]
+    fn rb_test_dot() {
+        let tree: RBTree<u32, String> = (1..=3).map(|key| (key, format!("\"{}", key))).collect();
+        assert_eq!(
+            tree.to_dot(),
+            r#"digraph RBTree {
+    node [style=filled, fontname="monospace"];
+    n0 [label="2\n\"\\\"2\"", fillcolor=black, fontcolor=white];
+    n0 -> n1 [label="<"];
+    n0 -> n2 [label=">"];
+    n2 [label="3\n\"\\\"3\"", fillcolor=red, fontcolor=white];
+    n1 [label="1\n\"\\\"1\"", fillcolor=red, fontcolor=white];
+}
+"#
+        );
+
+        let options = DotOptions::new()
+            .hide_values()
+            .nil_leaves()
+            .highlight([1])
+            .search_path(4);
+        assert_eq!(
+            tree.to_dot_with(&options),
+            r#"digraph RBTree {
+    node [style=filled, fontname="monospace"];
+    n0 [label="2", fillcolor=black, fontcolor=white, color=gold, penwidth=3];
+    n0 -> n1 [label="<"];
+    n0 -> n2 [label=">", color=gold, penwidth=3];
+    n2 [label="3", fillcolor=red, fontcolor=white, color=gold, penwidth=3];
+    n2 -> n3 [label="<"];
+    n3 [label="nil", shape=box, fillcolor=black, fontcolor=white, fontsize=8];
+    n2 -> n4 [label=">", color=gold, penwidth=3];
+    n4 [label="nil", shape=box, fillcolor=black, fontcolor=white, fontsize=8, color=gold, penwidth=3];
+    n1 [label="1", fillcolor=red, fontcolor=white, color=gold, penwidth=3];
+    n1 -> n5 [label="<"];
+    n5 [label="nil", shape=box, fillcolor=black, fontcolor=white, fontsize=8];
+    n1 -> n6 [label=">"];
+    n6 [label="nil", shape=box, fillcolor=black, fontcolor=white, fontsize=8];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn rb_test_observer() {
+        fn events(tree: &mut RBTree<u32, (), Vec<TreeEvent<u32>>>) -> Vec<TreeEvent<u32>> {
+            core::mem::take(tree.observer_mut())
+        }
+
+        let mut tree = RBTree::new().with_observer(Vec::new());
+        assert_eq!(tree.insert(10, ()), None);
+        assert_eq!(events(&mut tree), vec![TreeEvent::NodeInserted { key: 10 }]);
+        tree.insert(20, ());
+        assert_eq!(events(&mut tree), vec![TreeEvent::NodeInserted { key: 20 }]);
+        tree.insert(30, ());
+        assert_eq!(
+            events(&mut tree),
+            vec![
+                TreeEvent::NodeInserted { key: 30 },
+                TreeEvent::InsertCase {
+                    key: 10,
+                    state: InsertState::Conflict
+                },
+                TreeEvent::InsertCase {
+                    key: 10,
+                    state: InsertState::LeftRotate
+                },
+                TreeEvent::Recolored {
+                    key: 10,
+                    color: Color::Red
+                },
+                TreeEvent::Rotated {
+                    dir: RotDir::Left,
+                    pivot: 20
+                },
+                TreeEvent::Recolored {
+                    key: 20,
+                    color: Color::Black
+                },
+            ]
+        );
+        assert_eq!(tree.insert(30, ()), Some(()));
+        assert_eq!(events(&mut tree), Vec::new());
+        // a red leaf goes without rebalancing
+        assert_eq!(tree.remove(&10), Some(()));
+        assert_eq!(events(&mut tree), vec![TreeEvent::NodeRemoved { key: 10 }]);
+        assert_eq!(tree.remove(&10), None);
+        assert_eq!(events(&mut tree), Vec::new());
+
+        let (tree, observer) = tree.detach();
+        assert!(observer.is_empty());
+        assert_eq!(tree, RBTree::from([(20, ()), (30, ())]));
+    }
+
+    #[derive(Default, Clone)]
+    struct Counter {
+        rotations: usize,
+        recolors: usize,
+        nodes: isize,
+    }
+
+    impl<K> TreeObserver<K> for Counter {
+        fn on_event(&mut self, event: TreeEvent<&K>) {
+            match event {
+                TreeEvent::Rotated { .. } => self.rotations += 1,
+                TreeEvent::Recolored { .. } => self.recolors += 1,
+                TreeEvent::NodeInserted { .. } => self.nodes += 1,
+                TreeEvent::NodeRemoved { .. } => self.nodes -= 1,
+                TreeEvent::InsertCase { .. } => (),
+            }
+        }
+    }
+
+    #[test]
+    fn rb_test_observer_counter() {
+        // observing does not change the tree
+        let mut rng = rand::thread_rng();
+        let mut observed = RBTree::new().with_observer(Counter::default());
+        let mut plain = RBTree::new();
+        for _ in 0..2000 {
+            let key = rng.gen_range(0..300u32);
+            if rng.gen_bool(0.6) {
+                assert_eq!(observed.insert(key, key), plain.insert(key, key));
+            } else {
+                assert_eq!(observed.remove(&key), plain.remove(&key));
+            }
+            assert_eq!(
+                observed.pretty().color(false).to_string(),
+                plain.pretty().color(false).to_string()
+            );
+        }
+        assert_eq!(observed.observer().nodes as usize, plain.iter().count());
+        assert!(observed.observer().rotations > 0);
+        assert!(observed.observer().recolors > 0);
+    }
+
+    #[test]
+    fn rb_test_highlight() {
+        let tree: RBTree<u32, ()> = (1..=3).map(|key| (key, ())).collect();
+        assert_eq!(
+            tree.pretty()
+                .color(false)
+                .hide_values()
+                .highlight([1, 2])
+                .to_string(),
+            "B(2)*\n ├─<R(3)\n └─>R(1)*\n"
+        );
+        // where colors do not show, as with NO_COLOR, the marker stands in
+        #[cfg(feature = "std")]
+        colored::control::set_override(false);
+        assert_eq!(
+            tree.pretty().hide_values().highlight([3]).to_string(),
+            "B(2)\n ├─<R(3)*\n └─>R(1)\n"
+        );
+    }
+
+    #[test]
+    fn rb_test_from_sorted() {
+        for len in 0..=130u32 {
+            let entries: Vec<(u32, u32)> = (0..len).map(|key| (key, key * 10)).collect();
+            let tree = RBTree::from_sorted(entries.clone());
+            if let Err(msg) = tree.check_rules() {
+                panic!(
+                    "RB violation after from_sorted of {} keys: {}\n{}",
+                    len,
+                    msg,
+                    tree.pretty()
+                );
+            }
+            assert_eq!(Vec::from(tree.clone()), entries);
+            // the tree is as low as possible
+            let report = tree.shape_report();
+            assert_eq!(report.height, (u32::BITS - len.leading_zeros()) as usize);
+        }
+
+        // unsorted input is inserted, later values replace earlier ones
+        let tree = RBTree::from_sorted(vec![(3, 'a'), (1, 'b'), (3, 'c'), (2, 'd')]);
+        assert!(tree.check_rules().is_ok());
+        assert_eq!(Vec::from(tree), vec![(1, 'b'), (2, 'd'), (3, 'c')]);
+    }
+
+    #[test]
+    fn rb_test_diagram() {
+        let mut rng = rand::thread_rng();
+        let mut tree = RBTree::new();
+        for _ in 0..500 {
+            let key = rng.gen_range(0..200u32);
+            tree.insert(key, format!("v, \"{}\"", key));
+        }
+        for pretty in [
+            tree.pretty().color(false),
+            tree.pretty().color(false).ascii(),
+        ] {
+            let copy = RBTree::<u32, String>::from_diagram(&pretty.to_string())
+                .expect("failed to parse diagram");
+            assert_eq!(copy.to_shape(), tree.to_shape());
+        }
+        let keys =
+            RBTree::<u32, u8>::from_diagram(&tree.pretty().color(false).hide_values().to_string())
+                .expect("failed to parse diagram");
+        assert!(keys.iter().all(|(_, value)| *value == 0));
+        assert_eq!(
+            keys.pretty().hide_values().color(false).to_string(),
+            tree.pretty().hide_values().color(false).to_string()
+        );
+
+        // indented, highlighted and with ANSI colors, a missing child may be left out
+        let tree = RBTree::<u32, u32>::from_diagram(
+            "
+    \u{1b}[34mB(2,20)\u{1b}[0m*
+     └─>R(1,10)
+",
+        )
+        .expect("failed to parse diagram");
+        assert_eq!(tree.to_shape(), "B2=20 R1=10 . . .");
+        assert!(RBTree::<u32, u32>::from_diagram("nil")
+            .expect("failed to parse diagram")
+            .is_empty());
+
+        for diagram in [
+            "",
+            "B(1)\n ├─<...",
+            "B(1)\nB(2)",
+            "B(1)\n ├─<R(2)\n ├─<R(3)",
+            "B(1)\n     ├─<R(2)",
+            "B(1)\n  ├─<R(2)",
+            "B(x)",
+            "B 1",
+        ] {
+            assert!(
+                RBTree::<u32, u32>::from_diagram(diagram).is_err(),
+                "{:?}",
+                diagram
+            );
+        }
+    }
+
+    #[test]
+    fn rb_test_shape() {
+        let tree: RBTree<u32, String> = (1..=3).map(|key| (key, key.to_string())).collect();
+        assert_eq!(tree.to_shape(), "B2=2 R1=1 . . R3=3 . .");
+        assert_eq!(RBTree::<u32, u32>::new().to_shape(), ".");
+
+        let mut rng = rand::thread_rng();
+        let mut tree = RBTree::new();
+        for _ in 0..2000 {
+            let key = rng.gen_range(0..500u32);
+            if rng.gen_bool(0.7) {
+                tree.insert(key, format!("v {}", key));
+            } else {
+                tree.remove(&key);
+            }
+        }
+        let copy = RBTree::<u32, String>::from_shape(&tree.to_shape()).expect("failed to decode");
+        assert_eq!(
+            copy.pretty().color(false).to_string(),
+            tree.pretty().color(false).to_string()
+        );
+
+        // broken trees are rebuilt as they are
+        let tree = RBTree::<u32, u32>::from_shape("R2=0 R1=0 . . .").expect("failed to decode");
+        assert!(tree.check_rules().is_err());
+        assert_eq!(tree.to_shape(), "R2=0 R1=0 . . .");
+
+        for shape in ["", "B1=1 .", "B1=1 . . .", "N1=1 . .", "B1 . .", "Bx=1 . ."] {
+            assert!(RBTree::<u32, u32>::from_shape(shape).is_err(), "{}", shape);
+        }
+    }
+
+    #[test]
+    fn rb_test_shape_report() {
+        assert_eq!(
+            RBTree::<u32, ()>::new().shape_report(),
+            ShapeReport::default()
+        );
+
+        let tree: RBTree<u32, ()> = (1..=3).map(|key| (key, ())).collect();
+        assert_eq!(
+            tree.shape_report(),
+            ShapeReport {
+                height: 2,
+                black_height: Some(1),
+                nodes: 3,
+                per_depth: vec![1, 2],
+            }
+        );
+
+        // ascending inserts keep the tree within twice the black height
+        let tree: RBTree<u32, ()> = (0..1000).map(|key| (key, ())).collect();
+        let report = tree.shape_report();
+        assert_eq!(report.nodes, 1000);
+        assert_eq!(report.per_depth.iter().sum::<usize>(), 1000);
+        assert_eq!(report.per_depth.len(), report.height);
+        let black_height = report.black_height.expect("no black height");
+        assert!(report.height <= 2 * black_height);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn rb_test_stats() {
+        let mut tree = RBTree::new().with_observer(Counter::default());
+        for key in 1..=3u32 {
+            tree.insert(key, ());
+        }
+        let stats = tree.stats();
+        assert_eq!(stats.searches, 3);
+        // inserting 2 compares with 1, inserting 3 with 1 and 2
+        assert_eq!(stats.comparisons, 3);
+        assert_eq!(stats.max_search_depth, 2);
+        assert_eq!(stats.left_rotations, 1);
+        assert_eq!(stats.right_rotations, 0);
+        // the metrics see the same changes as the attached observer
+        assert_eq!(
+            stats.left_rotations + stats.right_rotations,
+            tree.observer().rotations as u64
+        );
+        assert_eq!(stats.recolors, tree.observer().recolors as u64);
+
+        // a hit at the root is one comparison
+        assert!(tree.contains(&2));
+        let stats = tree.stats();
+        assert_eq!(stats.comparisons, 4);
+        assert_eq!(stats.avg_search_depth, 1.0);
+
+        // smaller walks 2 and 3, it is no search
+        assert_eq!(tree.smaller(&3), Some((&2, &())));
+        let stats = tree.stats();
+        assert_eq!(stats.comparisons, 6);
+        assert_eq!(stats.searches, 4);
+
+        // range compares 2 and 1 with the start, then 1, 2 and 3 with the end
+        assert_eq!(tree.range(1..3).count(), 2);
+        assert_eq!(tree.stats().comparisons, 11);
+
+        // a miss compares the whole path
+        assert_eq!(tree.remove(&4), None);
+        assert_eq!(tree.stats().comparisons, 13);
+
+        tree.reset_stats();
+        assert_eq!(tree.stats(), Stats::default());
+    }
+
     #[test]
     fn rb_test_insert() {
         let mut tree = RBTree::new();
         for val in 1..=100 {
             assert_eq!(tree.insert(val, val.to_string()), None);
-            if let std::result::Result::Err(msg) = tree.check_rules() {
+            if let core::result::Result::Err(msg) = tree.check_rules() {
                 eprintln!(
-                    "RB violation after insert of {}, msg: {}\n{:?}",
-                    val, msg, tree
+                    "RB violation after insert of {}, msg: {}\n{}",
+                    val,
+                    msg,
+                    tree.pretty()
                 );
                 panic!("{}", msg)
             }
@@ -627,10 +1715,12 @@ mod test {
         let mut tree = RBTree::new();
         for val in (1..=100).rev() {
             assert_eq!(tree.insert(val, val.to_string()), None);
-            if let std::result::Result::Err(msg) = tree.check_rules() {
+            if let core::result::Result::Err(msg) = tree.check_rules() {
                 eprintln!(
-                    "RB violation after insert of {}, msg: {}\n{:?}",
-                    val, msg, tree
+                    "RB violation after insert of {}, msg: {}\n{}",
+                    val,
+                    msg,
+                    tree.pretty()
                 );
                 panic!("{}", msg)
             }
@@ -660,10 +1750,12 @@ mod test {
                 if !tree.contains(&val) {
                     assert_eq!(tree.insert(val, val.to_string()), None);
                     entries.push(val);
-                    if let std::result::Result::Err(msg) = tree.check_rules() {
+                    if let core::result::Result::Err(msg) = tree.check_rules() {
                         eprintln!(
-                            "RB violation after insert of {}, msg: {}\n{:?}",
-                            val, msg, tree
+                            "RB violation after insert of {}, msg: {}\n{}",
+                            val,
+                            msg,
+                            tree.pretty()
                         );
                         panic!("{}", msg)
                     }
@@ -689,4 +1781,21 @@ mod test {
             assert_eq!(tree.find(&val), Some(&val.to_string()));
         }
     }
+
+    #[test]
+    fn rb_test_from_btree() {
+        for len in 0..70u32 {
+            let chain: BTree<u32, String> = (0..len).map(|key| (key, key.to_string())).collect();
+            let copy = chain.clone();
+            let tree = RBTree::from(chain);
+            tree.check_rules()
+                .unwrap_or_else(|msg| panic!("{} nodes: {}\n{}", len, msg, tree.pretty()));
+            assert!(tree.iter().eq(copy.iter()));
+            // the clone kept its nodes
+            assert_eq!(copy.shape_report().height, len as usize);
+        }
+        // the red nodes are exactly the incomplete deepest level
+        let tree = RBTree::from(BTree::from([(1, 1), (2, 2), (3, 3), (4, 4)]));
+        assert_eq!(tree.to_shape(), "B3=3 B2=2 R1=1 . . . B4=4 . .");
+    }
 }
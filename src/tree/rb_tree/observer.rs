@@ -0,0 +1,87 @@
+use super::rbtree_node::{Color, RotDir};
+use super::InsertState;
+use crate::prelude::*;
+
+/// A structural change inside an RBTree.
+/// Observers receive the keys borrowed as `TreeEvent<&K>`, `TreeEvent<K>` is the owned form.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum TreeEvent<K> {
+    /// a new node was linked into the tree
+    NodeInserted {
+        key: K,
+    },
+    /// the node with this key was removed
+    NodeRemoved {
+        key: K,
+    },
+    /// the node with key handled the state reported by its child during insert
+    InsertCase {
+        key: K,
+        state: InsertState,
+    },
+    Recolored {
+        key: K,
+        color: Color,
+    },
+    /// a single rotation moved pivot up one level, double rotations report two events
+    Rotated {
+        dir: RotDir,
+        pivot: K,
+    },
+}
+
+impl<K: Clone> TreeEvent<&K> {
+    pub fn cloned(&self) -> TreeEvent<K> {
+        match *self {
+            TreeEvent::NodeInserted { key } => TreeEvent::NodeInserted { key: key.clone() },
+            TreeEvent::NodeRemoved { key } => TreeEvent::NodeRemoved { key: key.clone() },
+            TreeEvent::InsertCase { key, state } => TreeEvent::InsertCase {
+                key: key.clone(),
+                state,
+            },
+            TreeEvent::Recolored { key, color } => TreeEvent::Recolored {
+                key: key.clone(),
+                color,
+            },
+            TreeEvent::Rotated { dir, pivot } => TreeEvent::Rotated {
+                dir,
+                pivot: pivot.clone(),
+            },
+        }
+    }
+}
+
+/// Receives the events of an RBTree it is attached to, in the order they happen.
+/// The observer is a type parameter of the tree, the default () ignores all events
+/// and compiles down to nothing.
+pub trait TreeObserver<K> {
+    fn on_event(&mut self, event: TreeEvent<&K>);
+}
+
+impl<K> TreeObserver<K> for () {
+    #[inline(always)]
+    fn on_event(&mut self, _event: TreeEvent<&K>) {}
+}
+
+/// records owned copies of all events
+impl<K: Clone> TreeObserver<K> for Vec<TreeEvent<K>> {
+    fn on_event(&mut self, event: TreeEvent<&K>) {
+        self.push(event.cloned())
+    }
+}
+
+impl<K, O: TreeObserver<K>> TreeObserver<K> for &mut O {
+    #[inline(always)]
+    fn on_event(&mut self, event: TreeEvent<&K>) {
+        (**self).on_event(event)
+    }
+}
+
+/// both observers see every event, first A then B
+impl<K, A: TreeObserver<K>, B: TreeObserver<K>> TreeObserver<K> for (A, B) {
+    #[inline(always)]
+    fn on_event(&mut self, event: TreeEvent<&K>) {
+        self.0.on_event(event);
+        self.1.on_event(event);
+    }
+}
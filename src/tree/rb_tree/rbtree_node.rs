@@ -1,21 +1,26 @@
 use super::SubNode;
+use crate::prelude::*;
+use crate::tree::binary_tree::BTreeNode;
+use crate::tree::pretty::{Layout, PrettyOptions};
 use crate::tree::rb_tree::rbtree_node::Color::Red;
-use crate::tree::rb_tree::{Branch, InsertState};
-use colored::*;
-use std::fmt::Debug;
+use crate::tree::rb_tree::{Branch, InsertState, TreeEvent, TreeObserver};
+use crate::tree::TreeNode;
+use alloc::sync::Arc;
+use core::fmt::{Debug, Display, Formatter};
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum Color {
     Red,
     Black,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum RotDir {
     Right,
     Left,
 }
 
+#[derive(Clone)]
 pub struct RBTreeNode<K: PartialOrd, V> {
     pub key: K,
     pub value: V,
@@ -45,19 +50,92 @@ impl<K: PartialOrd + Debug, V> RBTreeNode<K, V> {
         }
     }
 
-    pub fn insert_node_rb(
+    pub fn traverse_asc(&self, func: &mut dyn FnMut(&K, &V)) {
+        if let Some(smaller) = &self.smaller {
+            smaller.traverse_asc(func);
+        }
+        func(&self.key, &self.value);
+        if let Some(larger) = &self.larger {
+            larger.traverse_asc(func);
+        }
+    }
+
+    /// Builds a balanced subtree from the next len entries, which must be in ascending order.
+    /// The smaller half goes left of the midpoint, nodes at red_depth are red, all others black.
+    /// Recursion is bounded by the height of the balanced tree.
+    pub(crate) fn build_sorted(
+        entries: &mut impl Iterator<Item = (K, V)>,
+        len: usize,
+        depth: usize,
+        red_depth: usize,
+    ) -> SubNode<K, V> {
+        if len == 0 {
+            return None;
+        }
+        let smaller_len = (len - 1) / 2;
+        let smaller = Self::build_sorted(entries, smaller_len, depth + 1, red_depth);
+        let (key, value) = entries.next().expect("fewer entries than announced");
+        let larger = Self::build_sorted(entries, len - 1 - smaller_len, depth + 1, red_depth);
+        Some(Arc::new(RBTreeNode {
+            key,
+            value,
+            color: if depth == red_depth {
+                Color::Red
+            } else {
+                Color::Black
+            },
+            smaller,
+            larger,
+        }))
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> RBTreeNode<K, V> {
+    /// Rebuilds a BTree subtree node by node in a new allocation each, keeping its shape,
+    /// see `From<BTree>` for RBTree for why the BTree nodes are not reused.
+    /// The subtree must have all levels complete down to red_depth, nodes at red_depth
+    /// are red, all others black. Keys and values are moved out of the BTree nodes,
+    /// they are cloned only for nodes shared with a clone of the BTree.
+    pub(crate) fn from_btree(
+        link: Option<Arc<BTreeNode<K, V>>>,
+        depth: usize,
+        red_depth: usize,
+    ) -> SubNode<K, V> {
+        let node = Arc::unwrap_or_clone(link?);
+        Some(Arc::new(RBTreeNode {
+            key: node.key,
+            value: node.value,
+            color: if depth == red_depth {
+                Color::Red
+            } else {
+                Color::Black
+            },
+            smaller: Self::from_btree(node.smaller, depth + 1, red_depth),
+            larger: Self::from_btree(node.larger, depth + 1, red_depth),
+        }))
+    }
+}
+
+/// all modifications go through Arc::make_mut, nodes shared with a cloned tree
+/// are copied before they are changed
+impl<K: PartialOrd + Debug + Clone, V: Clone> RBTreeNode<K, V> {
+    /// visited counts the nodes node.key is compared with
+    pub(crate) fn insert_node_rb<O: TreeObserver<K>>(
         &mut self,
-        node: Box<RBTreeNode<K, V>>,
+        node: RBTreeNode<K, V>,
         is_root: bool,
+        observer: &mut O,
+        visited: &mut u64,
     ) -> (Option<V>, InsertState) {
         /*eprintln!(
             "({:?}).insert_node_rb(key: {:?}) root: {}",
             self.key, node.key, is_root
         );*/
+        *visited += 1;
         if node.key == self.key {
             //eprintln!("insert_node_rb() update");
             return (
-                Some(std::mem::replace(&mut self.value, node.value)),
+                Some(core::mem::replace(&mut self.value, node.value)),
                 InsertState::Clean,
             );
         }
@@ -69,12 +147,19 @@ impl<K: PartialOrd + Debug, V> RBTreeNode<K, V> {
         };
 
         if let Some(child_node) = child_link {
-            let (res, ins_state) = child_node.insert_node_rb(node, false);
+            let (res, ins_state) =
+                Arc::make_mut(child_node).insert_node_rb(node, false, observer, visited);
             /* eprintln!(
                 "({:?}).insert_node_rb() root: {} insert into subnode returned insert_state {:?}",
                 self.key, is_root, ins_state
             );
             */
+            if ins_state != InsertState::Clean {
+                observer.on_event(TreeEvent::InsertCase {
+                    key: &self.key,
+                    state: ins_state,
+                });
+            }
             match ins_state {
                 InsertState::Clean => (res, ins_state),
                 InsertState::ChgdColor => {
@@ -92,16 +177,20 @@ impl<K: PartialOrd + Debug, V> RBTreeNode<K, V> {
                 }
                 InsertState::Conflict => {
                     // child reports that it and its child are red
-                    if let Some(true) = uncle_link.as_mut().map(|node| node.color == Color::Red) {
+                    if let Some(true) = uncle_link.as_ref().map(|node| node.color == Color::Red) {
                         // uncle is red
-                        if !is_root {
+                        if !is_root && self.color != Color::Red {
                             self.color = Color::Red;
+                            observer.on_event(TreeEvent::Recolored {
+                                key: &self.key,
+                                color: Color::Red,
+                            });
                         }
                         if let Some(uncle) = uncle_link.as_mut() {
-                            uncle.color = Color::Black
+                            Arc::make_mut(uncle).recolor(Color::Black, observer)
                         }
                         if let Some(child) = child_link.as_mut() {
-                            child.color = Color::Black
+                            Arc::make_mut(child).recolor(Color::Black, observer)
                         }
 
                         (
@@ -119,18 +208,23 @@ impl<K: PartialOrd + Debug, V> RBTreeNode<K, V> {
                         }
                     }
                 }
-                InsertState::LeftRotate => match self.rotate_child(RotDir::Left, branch) {
-                    Ok(_) => (res, InsertState::Clean),
-                    Err(err) => panic!("{}", err),
-                },
-                InsertState::RightRotate => match self.rotate_child(RotDir::Right, branch) {
-                    Ok(_) => (res, InsertState::Clean),
-                    Err(err) => panic!("{}", err),
-                },
+                InsertState::LeftRotate => {
+                    match self.rotate_child(RotDir::Left, branch, observer) {
+                        Ok(_) => (res, InsertState::Clean),
+                        Err(err) => panic!("{}", err),
+                    }
+                }
+                InsertState::RightRotate => {
+                    match self.rotate_child(RotDir::Right, branch, observer) {
+                        Ok(_) => (res, InsertState::Clean),
+                        Err(err) => panic!("{}", err),
+                    }
+                }
             }
         } else {
             assert_eq!(node.color, Color::Red);
-            *child_link = Some(node);
+            observer.on_event(TreeEvent::NodeInserted { key: &node.key });
+            *child_link = Some(Arc::new(node));
             (
                 None,
                 if self.color == Color::Black {
@@ -142,107 +236,252 @@ impl<K: PartialOrd + Debug, V> RBTreeNode<K, V> {
         }
     }
 
-    pub fn insert_node_rec(&mut self, node: Box<RBTreeNode<K, V>>) -> Option<V> {
+    pub fn insert_node_rec(&mut self, node: RBTreeNode<K, V>) -> Option<V> {
         if node.key < self.key {
             if let Some(smaller) = &mut self.smaller {
-                smaller.insert_node_rec(node)
+                Arc::make_mut(smaller).insert_node_rec(node)
             } else {
-                self.smaller = Some(node);
+                self.smaller = Some(Arc::new(node));
                 None
             }
         } else if node.key > self.key {
             if let Some(larger) = &mut self.larger {
-                larger.insert_node_rec(node)
+                Arc::make_mut(larger).insert_node_rec(node)
             } else {
-                self.larger = Some(node);
+                self.larger = Some(Arc::new(node));
                 None
             }
         } else {
             // don't even really need to support this case
-            Some(std::mem::replace(&mut self.value, node.value))
+            Some(core::mem::replace(&mut self.value, node.value))
         }
     }
 
-    pub fn traverse_asc(&self, func: &mut dyn FnMut(&K, &V)) {
-        if let Some(smaller) = &self.smaller {
-            smaller.traverse_asc(func);
-        }
-        func(&self.key, &self.value);
-        if let Some(larger) = &self.larger {
-            larger.traverse_asc(func);
+    /// remove the node with key from the subtree hanging off link
+    /// returns the removed value and true if the subtree has lost one black node in height,
+    /// in which case the parent has to rebalance. The nodes on the path are copied if
+    /// shared, so look for key first.
+    pub(crate) fn remove_rb<O: TreeObserver<K>>(
+        link: &mut SubNode<K, V>,
+        key: &K,
+        observer: &mut O,
+    ) -> (Option<V>, bool) {
+        let node = match link {
+            Some(node) => Arc::make_mut(node),
+            None => return (None, false),
+        };
+
+        if *key < node.key {
+            let (res, shorter) = Self::remove_rb(&mut node.smaller, key, observer);
+            (
+                res,
+                shorter && Self::rebalance_link(link, Branch::Smaller, observer),
+            )
+        } else if *key > node.key {
+            let (res, shorter) = Self::remove_rb(&mut node.larger, key, observer);
+            (
+                res,
+                shorter && Self::rebalance_link(link, Branch::Larger, observer),
+            )
+        } else if node.smaller.is_some() && node.larger.is_some() {
+            // node has two siblings - replace node with next larger, delete next larger
+            observer.on_event(TreeEvent::NodeRemoved { key: &node.key });
+            let (key, value, shorter) = Self::remove_smallest(&mut node.larger, observer);
+            node.key = key;
+            let res = core::mem::replace(&mut node.value, value);
+            (
+                Some(res),
+                shorter && Self::rebalance_link(link, Branch::Larger, observer),
+            )
+        } else {
+            observer.on_event(TreeEvent::NodeRemoved { key: &node.key });
+            let (_key, value, shorter) = Self::unlink(link, observer);
+            (Some(value), shorter)
         }
     }
 
-    pub fn remove(&mut self, key: &K) -> Option<V> {
-        // remove only subnodes - this node has beech checked upstream
-        #[cfg(test)]
-        if self.key == *key {
-            panic!("BTreeNode.remove() cannot semove self");
+    /// remove the smallest node from the subtree hanging off link
+    fn remove_smallest<O: TreeObserver<K>>(
+        link: &mut SubNode<K, V>,
+        observer: &mut O,
+    ) -> (K, V, bool) {
+        let node = Arc::make_mut(link.as_mut().expect("unexpected empty link"));
+        if node.smaller.is_some() {
+            let (key, value, shorter) = Self::remove_smallest(&mut node.smaller, observer);
+            (
+                key,
+                value,
+                shorter && Self::rebalance_link(link, Branch::Smaller, observer),
+            )
+        } else {
+            Self::unlink(link, observer)
         }
+    }
 
-        let child_link = if *key < self.key {
-            &mut self.smaller
-        } else {
-            &mut self.larger
+    /// unlink a node that has at most one sibling, the sibling takes its place
+    fn unlink<O: TreeObserver<K>>(link: &mut SubNode<K, V>, observer: &mut O) -> (K, V, bool) {
+        let mut node = Arc::unwrap_or_clone(link.take().expect("unexpected empty link"));
+        let shorter = match node.smaller.take().or_else(|| node.larger.take()) {
+            Some(mut child) => {
+                // a single sibling is always red - painting it black restores the black count
+                Arc::make_mut(&mut child).recolor(Color::Black, observer);
+                *link = Some(child);
+                false
+            }
+            None => node.color == Color::Black,
         };
+        (node.key, node.value, shorter)
+    }
 
-        if let Some(true) = child_link.as_ref().map(|root| root.key == *key) {
-            // delete the root
-            let mut child = child_link.take().expect("unexpected empty link");
-            let (res, new_child) = if child.smaller.is_some() {
-                if child.larger.is_some() {
-                    // root has two siblings - swap root with next larger, delete next larger
-                    let (key, value) = child.remove_next_larger();
-                    child.key = key;
-                    let res = std::mem::replace(&mut child.value, value);
-                    (Some(res), Some(child))
-                } else {
-                    // root becomes root.smaller
-                    (Some(child.value), child.smaller.take())
-                }
-            } else if child.larger.is_some() {
-                // root becomes root.larger
-                (Some(child.value), child.larger.take())
+    fn rebalance_link<O: TreeObserver<K>>(
+        link: &mut SubNode<K, V>,
+        branch: Branch,
+        observer: &mut O,
+    ) -> bool {
+        let node = link.take().expect("unexpected empty link");
+        let (node, shorter) = node.rebalance_removed(branch, observer);
+        *link = Some(node);
+        shorter
+    }
+
+    /// restore the rules after the subtree on branch has lost one black node in height
+    /// returns the new subtree root and true if the whole subtree is still one black node short
+    fn rebalance_removed<O: TreeObserver<K>>(
+        mut self: Arc<Self>,
+        branch: Branch,
+        observer: &mut O,
+    ) -> (Arc<Self>, bool) {
+        let (sibling_branch, direction, counter_direction) = match branch {
+            Branch::Smaller => (Branch::Larger, RotDir::Left, RotDir::Right),
+            Branch::Larger => (Branch::Smaller, RotDir::Right, RotDir::Left),
+        };
+
+        if Self::is_red(self.child(sibling_branch)) {
+            // red sibling - rotate it up, the short side then has a black sibling and a red parent
+            let mut top = self.rotate(direction, observer);
+            let top_node = Arc::make_mut(&mut top);
+            top_node.recolor(Color::Black, observer);
+            let mut parent = top_node
+                .child_mut(branch)
+                .take()
+                .expect("unexpected empty link");
+            Arc::make_mut(&mut parent).recolor(Color::Red, observer);
+            let (parent, _) = parent.rebalance_removed(branch, observer);
+            *top_node.child_mut(branch) = Some(parent);
+            return (top, false);
+        }
+
+        let node = Arc::make_mut(&mut self);
+        let sibling = Arc::make_mut(
+            node.child_mut(sibling_branch)
+                .as_mut()
+                .expect("RB violation: short subtree without sibling"),
+        );
+        let far_red = Self::is_red(sibling.child(sibling_branch));
+        let near_red = Self::is_red(sibling.child(branch));
+
+        if !far_red && !near_red {
+            // black sibling with black siblings - paint it red and push the problem up
+            sibling.recolor(Color::Red, observer);
+            if node.color == Color::Red {
+                node.recolor(Color::Black, observer);
+                (self, false)
             } else {
-                // the tree is empty
-                (Some(child.value), None)
-            };
-            if new_child.is_some() {
-                *child_link = new_child;
+                (self, true)
             }
-            res
-        } else if let Some(child) = child_link {
-            child.remove(key)
         } else {
-            None
+            if !far_red {
+                // move the red near nephew to the far side
+                let sibling = node
+                    .child_mut(sibling_branch)
+                    .take()
+                    .expect("unexpected empty link");
+                let mut nephew = sibling.rotate(counter_direction, observer);
+                let nephew_node = Arc::make_mut(&mut nephew);
+                nephew_node.recolor(Color::Black, observer);
+                if let Some(sibling) = nephew_node.child_mut(sibling_branch) {
+                    Arc::make_mut(sibling).recolor(Color::Red, observer);
+                }
+                *node.child_mut(sibling_branch) = Some(nephew);
+            }
+            let color = node.color;
+            let mut top = self.rotate(direction, observer);
+            let top_node = Arc::make_mut(&mut top);
+            top_node.recolor(color, observer);
+            if let Some(node) = &mut top_node.smaller {
+                Arc::make_mut(node).recolor(Color::Black, observer);
+            }
+            if let Some(node) = &mut top_node.larger {
+                Arc::make_mut(node).recolor(Color::Black, observer);
+            }
+            (top, false)
         }
     }
 
-    pub fn remove_next_larger(&mut self) -> (K, V) {
-        match self.larger.as_ref().map(|node| node.smaller.is_some()) {
-            Some(true) => {
-                // larger has smaller siblings - find the smallest one
-                let mut curr = self.larger.as_mut().expect("unexpected empty link");
-                while let Some(true) = curr.smaller.as_ref().map(|node| node.smaller.is_some()) {
-                    curr = curr.smaller.as_mut().expect("unexpected empty link");
-                }
-                // current is the parent of the smallest node
-                let smallest = curr.smaller.take().expect("unexpected empty node");
-                if smallest.larger.is_some() {
-                    curr.smaller = smallest.larger;
-                }
-                (smallest.key, smallest.value)
+    /// change the color and report it if it differs
+    pub(crate) fn recolor<O: TreeObserver<K>>(&mut self, color: Color, observer: &mut O) {
+        if self.color != color {
+            self.color = color;
+            observer.on_event(TreeEvent::Recolored {
+                key: &self.key,
+                color,
+            });
+        }
+    }
+
+    fn is_red(link: &SubNode<K, V>) -> bool {
+        matches!(link, Some(node) if node.color == Color::Red)
+    }
+
+    fn child(&self, branch: Branch) -> &SubNode<K, V> {
+        match branch {
+            Branch::Smaller => &self.smaller,
+            Branch::Larger => &self.larger,
+        }
+    }
+
+    fn child_mut(&mut self, branch: Branch) -> &mut SubNode<K, V> {
+        match branch {
+            Branch::Smaller => &mut self.smaller,
+            Branch::Larger => &mut self.larger,
+        }
+    }
+
+    /// plain rotation without any recoloring, used to rebalance after remove
+    /// left moves the larger side up, right moves the smaller side up
+    fn rotate<O: TreeObserver<K>>(
+        mut self: Arc<Self>,
+        direction: RotDir,
+        observer: &mut O,
+    ) -> Arc<Self> {
+        let node = Arc::make_mut(&mut self);
+        let top = match direction {
+            RotDir::Left => {
+                let mut larger = node
+                    .larger
+                    .take()
+                    .expect("cannot rotate - larger subnode is nil");
+                let larger_node = Arc::make_mut(&mut larger);
+                node.larger = larger_node.smaller.take();
+                larger_node.smaller = Some(self);
+                larger
             }
-            Some(false) => {
-                // self.larger has no smaller siblings - larger is the one
-                let larger = self.larger.take().expect("unexpected empty link");
-                if larger.larger.is_some() {
-                    self.larger = larger.larger;
-                }
-                (larger.key, larger.value)
+            RotDir::Right => {
+                let mut smaller = node
+                    .smaller
+                    .take()
+                    .expect("cannot rotate - smaller subnode is nil");
+                let smaller_node = Arc::make_mut(&mut smaller);
+                node.smaller = smaller_node.larger.take();
+                smaller_node.larger = Some(self);
+                smaller
             }
-            None => panic!("remove_next_larger - no larger subnode exists"),
-        }
+        };
+        observer.on_event(TreeEvent::Rotated {
+            dir: direction,
+            pivot: &top.key,
+        });
+        top
     }
 
     /// rotate left - larger side moves up
@@ -256,12 +495,12 @@ impl<K: PartialOrd + Debug, V> RBTreeNode<K, V> {
     ///         └─>(s)                └─>(s)
     ///             ├─<(s,l)              ├─<(s,l)
     ///             └─>(s,s)              └─>(s,s)
-
-    pub fn rotate_child(
+    pub(crate) fn rotate_child<O: TreeObserver<K>>(
         &mut self,
         direction: RotDir,
         branch: Branch,
-    ) -> std::result::Result<(), &'static str> {
+        observer: &mut O,
+    ) -> core::result::Result<(), &'static str> {
         let child_link = match branch {
             Branch::Smaller => &mut self.smaller,
             Branch::Larger => &mut self.larger,
@@ -270,8 +509,8 @@ impl<K: PartialOrd + Debug, V> RBTreeNode<K, V> {
         if child_link.is_some() {
             let node = child_link.take().unwrap();
             let res = match direction {
-                RotDir::Left => node.left_rotate(),
-                RotDir::Right => node.right_rotate(),
+                RotDir::Left => node.left_rotate(observer),
+                RotDir::Right => node.right_rotate(observer),
             };
             match res {
                 Ok(new_child) => {
@@ -297,28 +536,43 @@ impl<K: PartialOrd + Debug, V> RBTreeNode<K, V> {
     ///     └─>(s)                └─>(s)
     ///         ├─<(s,l)              ├─<(s,l)
     ///         └─>(s,s)              └─>(s,s)
-
-    pub fn left_rotate(
-        mut self: Box<Self>,
-    ) -> std::result::Result<Box<Self>, (Box<Self>, &'static str)> {
+    pub(crate) fn left_rotate<O: TreeObserver<K>>(
+        mut self: Arc<Self>,
+        observer: &mut O,
+    ) -> core::result::Result<Arc<Self>, (Arc<Self>, &'static str)> {
         if self.larger.is_some() {
-            let mut larger = self.larger.take().expect("unexpected empty link");
-            self.color = Color::Red;
-            let mut new_root =
-                if let Some(true) = larger.smaller.as_ref().map(|node| node.color == Color::Red) {
-                    //  do a modified right rotate on larger.larger, larger, larger.smaller
-                    let mut smaller_gc = larger.smaller.take().expect("unexpected empty link");
-                    larger.smaller = smaller_gc.larger.take();
-                    smaller_gc.larger = Some(larger);
-                    smaller_gc
-                } else {
-                    larger
-                };
+            let node = Arc::make_mut(&mut self);
+            let mut larger = node.larger.take().expect("unexpected empty link");
+            node.recolor(Color::Red, observer);
+            let larger_node = Arc::make_mut(&mut larger);
+            let mut new_root = if let Some(true) = larger_node
+                .smaller
+                .as_ref()
+                .map(|node| node.color == Color::Red)
+            {
+                //  do a modified right rotate on larger.larger, larger, larger.smaller
+                let mut smaller_gc = larger_node.smaller.take().expect("unexpected empty link");
+                let smaller_gc_node = Arc::make_mut(&mut smaller_gc);
+                larger_node.smaller = smaller_gc_node.larger.take();
+                smaller_gc_node.larger = Some(larger);
+                observer.on_event(TreeEvent::Rotated {
+                    dir: RotDir::Right,
+                    pivot: &smaller_gc.key,
+                });
+                smaller_gc
+            } else {
+                larger
+            };
             // now left rotate larger smaller_gc self
 
-            self.larger = new_root.smaller.take();
-            new_root.smaller = Some(self);
-            new_root.color = Color::Black;
+            let new_root_node = Arc::make_mut(&mut new_root);
+            node.larger = new_root_node.smaller.take();
+            new_root_node.smaller = Some(self);
+            observer.on_event(TreeEvent::Rotated {
+                dir: RotDir::Left,
+                pivot: &new_root_node.key,
+            });
+            new_root_node.recolor(Color::Black, observer);
             Ok(new_root)
         } else {
             Err((self, "cannot left rotate - larger subnode is nil"))
@@ -334,28 +588,43 @@ impl<K: PartialOrd + Debug, V> RBTreeNode<K, V> {
     ///     └─>(s)           │   │   └─>(l,s)     
     ///         ├─<(s,l)     │   └─>(s,l)     
     ///         └─>(s,s)     └─>(s,s)          
-
-    pub fn right_rotate(
-        mut self: Box<Self>,
-    ) -> std::result::Result<Box<Self>, (Box<Self>, &'static str)> {
+    pub(crate) fn right_rotate<O: TreeObserver<K>>(
+        mut self: Arc<Self>,
+        observer: &mut O,
+    ) -> core::result::Result<Arc<Self>, (Arc<Self>, &'static str)> {
         if self.smaller.is_some() {
-            let mut smaller = self.smaller.take().expect("unexpected empty link");
-            self.color = Color::Red;
-            let mut new_root =
-                if let Some(true) = smaller.larger.as_ref().map(|node| node.color == Color::Red) {
-                    //  do a modified left rotate on smaller.larger, larger, larger.smaller
-                    let mut larger_gc = smaller.larger.take().expect("unexpected empty link");
-                    smaller.larger = larger_gc.smaller.take();
-                    larger_gc.smaller = Some(smaller);
-                    larger_gc
-                } else {
-                    smaller
-                };
+            let node = Arc::make_mut(&mut self);
+            let mut smaller = node.smaller.take().expect("unexpected empty link");
+            node.recolor(Color::Red, observer);
+            let smaller_node = Arc::make_mut(&mut smaller);
+            let mut new_root = if let Some(true) = smaller_node
+                .larger
+                .as_ref()
+                .map(|node| node.color == Color::Red)
+            {
+                //  do a modified left rotate on smaller.larger, larger, larger.smaller
+                let mut larger_gc = smaller_node.larger.take().expect("unexpected empty link");
+                let larger_gc_node = Arc::make_mut(&mut larger_gc);
+                smaller_node.larger = larger_gc_node.smaller.take();
+                larger_gc_node.smaller = Some(smaller);
+                observer.on_event(TreeEvent::Rotated {
+                    dir: RotDir::Left,
+                    pivot: &larger_gc.key,
+                });
+                larger_gc
+            } else {
+                smaller
+            };
             // now left rotate larger smaller_gc self
 
-            self.smaller = new_root.larger.take();
-            new_root.larger = Some(self);
-            new_root.color = Color::Black;
+            let new_root_node = Arc::make_mut(&mut new_root);
+            node.smaller = new_root_node.larger.take();
+            new_root_node.larger = Some(self);
+            observer.on_event(TreeEvent::Rotated {
+                dir: RotDir::Right,
+                pivot: &new_root_node.key,
+            });
+            new_root_node.recolor(Color::Black, observer);
             Ok(new_root)
         } else {
             Err((self, "cannot right rotate - smaller subnode is nil"))
@@ -372,54 +641,68 @@ impl<K: PartialOrd + Debug, V: Debug> RBTreeNode<K, V> {
     ///   └─>(kkk,vvv)
     ///       ├─<(kkk,vvv)
     ///       └─>(kkk,vvv)
+    pub(crate) fn to_str_buffer(
+        &self,
+        buffer: &mut String,
+        lead: &str,
+        root: bool,
+        smaller: bool,
+        depth: usize,
+        layout: &mut Layout,
+    ) {
+        let junction = if root {
+            ""
+        } else if smaller {
+            layout.j_smaller
+        } else {
+            layout.j_larger
+        };
 
-    fn to_str_buffer(&self, buffer: &mut String, lead: &str, root: bool, smaller: bool) {
-        const J_SMALLER: &str = " └─>";
-        const J_LARGER: &str = " ├─<";
-        const L_SMALLER: &str = "    ";
-        const L_LARGER: &str = " │  ";
-
-        // buffer.push_str(&format!("lead: '{}'\n", lead));
+        if !layout.take_node() {
+            if layout.truncate() {
+                buffer.push_str(&format!("{}{}...\n", lead, junction));
+            }
+            return;
+        }
 
         let node_str = format!(
-            "{}({:?},{:?})",
+            "{}{}",
             if self.color == Color::Red { "R" } else { "B" },
-            self.key,
-            self.value
+            layout.node_text(&self.key, &self.value)
         );
         let node_str = match self.color {
-            Color::Red => node_str.red().to_string(),
-            Color::Black => node_str.blue().to_string(),
+            Color::Red => layout.paint_red(node_str),
+            Color::Black => layout.paint_black(node_str),
         };
-        if root {
-            buffer.push_str(&format!("{}\n", node_str));
-        } else {
-            let junction = if smaller { J_SMALLER } else { J_LARGER };
-            let out = format!("{}{}{}\n", lead, junction, node_str);
-            buffer.push_str(&out);
-        }
+        let node_str = layout.paint_highlight(&self.key, node_str);
+        buffer.push_str(&format!("{}{}{}\n", lead, junction, node_str));
 
         if self.smaller.is_some() || self.larger.is_some() {
             let sub_lead = if root {
                 lead.to_string()
             } else if smaller {
-                lead.to_string() + L_SMALLER
+                lead.to_string() + layout.l_smaller
             } else {
-                lead.to_string() + L_LARGER
+                lead.to_string() + layout.l_larger
             };
 
-            let nil = "nil".blue().to_string();
+            if !layout.below_max_depth(depth + 1) {
+                buffer.push_str(&format!("{}{}...\n", sub_lead, layout.j_smaller));
+                return;
+            }
+
+            let nil = layout.paint_black("nil".to_string());
 
             if let Some(subnode) = &self.larger {
-                subnode.to_str_buffer(buffer, &sub_lead, false, false);
+                subnode.to_str_buffer(buffer, &sub_lead, false, false, depth + 1, layout);
             } else {
-                buffer.push_str(&format!("{}{}{}\n", sub_lead, J_LARGER, nil));
+                buffer.push_str(&format!("{}{}{}\n", sub_lead, layout.j_larger, nil));
             }
 
             if let Some(subnode) = &self.smaller {
-                subnode.to_str_buffer(buffer, &sub_lead, false, true);
+                subnode.to_str_buffer(buffer, &sub_lead, false, true, depth + 1, layout);
             } else {
-                buffer.push_str(&format!("{}{}{}\n", sub_lead, J_SMALLER, nil));
+                buffer.push_str(&format!("{}{}{}\n", sub_lead, layout.j_smaller, nil));
             }
         }
     }
@@ -428,7 +711,7 @@ impl<K: PartialOrd + Debug, V: Debug> RBTreeNode<K, V> {
         &self,
         is_root: bool,
         parent_red: bool,
-    ) -> std::result::Result<usize, String> {
+    ) -> core::result::Result<usize, String> {
         if is_root {
             // this is root
             if let Color::Red = self.color {
@@ -466,17 +749,43 @@ impl<K: PartialOrd + Debug, V: Debug> RBTreeNode<K, V> {
     }
 }
 
-impl<K: PartialOrd + Debug, V: Debug> ToString for RBTreeNode<K, V> {
-    fn to_string(&self) -> String {
+impl<K: PartialOrd, V> TreeNode<K, V> for RBTreeNode<K, V> {
+    fn key(&self) -> &K {
+        &self.key
+    }
+
+    fn value(&self) -> &V {
+        &self.value
+    }
+
+    fn smaller(&self) -> Option<&Self> {
+        self.smaller.as_deref()
+    }
+
+    fn larger(&self) -> Option<&Self> {
+        self.larger.as_deref()
+    }
+
+    fn color(&self) -> Option<Color> {
+        Some(self.color)
+    }
+}
+
+impl<K: PartialOrd + Debug, V: Debug> Display for RBTreeNode<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let options = PrettyOptions::default();
         let mut buffer = String::new();
-        self.to_str_buffer(&mut buffer, "", true, false);
-        buffer
+        self.to_str_buffer(&mut buffer, "", true, false, 0, &mut Layout::new(&options));
+        write!(f, "{}", buffer)
     }
 }
 
 #[cfg(test)]
 mod test {
     use crate::tree::rb_tree::rbtree_node::{Branch, Color, RBTreeNode, RotDir};
+    use crate::tree::rb_tree::RBTree;
+    use crate::tree::shape;
+    use alloc::sync::Arc;
 
     #[test]
     fn test_rotate() {
@@ -484,22 +793,22 @@ mod test {
             key: 10,
             value: 10.to_string(),
             color: Color::Black,
-            smaller: Some(Box::new(RBTreeNode {
+            smaller: Some(Arc::new(RBTreeNode {
                 key: 5,
                 value: 5.to_string(),
                 color: Color::Black,
-                smaller: Some(Box::new(RBTreeNode {
+                smaller: Some(Arc::new(RBTreeNode {
                     key: 3,
                     value: 3.to_string(),
                     color: Color::Black,
-                    smaller: Some(Box::new(RBTreeNode {
+                    smaller: Some(Arc::new(RBTreeNode {
                         key: 2,
                         value: 1.to_string(),
                         color: Color::Black,
                         smaller: None,
                         larger: None,
                     })),
-                    larger: Some(Box::new(RBTreeNode {
+                    larger: Some(Arc::new(RBTreeNode {
                         key: 4,
                         value: 4.to_string(),
                         color: Color::Black,
@@ -507,18 +816,18 @@ mod test {
                         larger: None,
                     })),
                 })),
-                larger: Some(Box::new(RBTreeNode {
+                larger: Some(Arc::new(RBTreeNode {
                     key: 8,
                     value: 8.to_string(),
                     color: Color::Black,
-                    smaller: Some(Box::new(RBTreeNode {
+                    smaller: Some(Arc::new(RBTreeNode {
                         key: 7,
                         value: 7.to_string(),
                         color: Color::Black,
                         smaller: None,
                         larger: None,
                     })),
-                    larger: Some(Box::new(RBTreeNode {
+                    larger: Some(Arc::new(RBTreeNode {
                         key: 9,
                         value: 9.to_string(),
                         color: Color::Black,
@@ -527,22 +836,22 @@ mod test {
                     })),
                 })),
             })),
-            larger: Some(Box::new(RBTreeNode {
+            larger: Some(Arc::new(RBTreeNode {
                 key: 20,
                 value: 20.to_string(),
                 color: Color::Black,
-                smaller: Some(Box::new(RBTreeNode {
+                smaller: Some(Arc::new(RBTreeNode {
                     key: 15,
                     value: 15.to_string(),
                     color: Color::Black,
-                    smaller: Some(Box::new(RBTreeNode {
+                    smaller: Some(Arc::new(RBTreeNode {
                         key: 12,
                         value: 12.to_string(),
                         color: Color::Black,
                         smaller: None,
                         larger: None,
                     })),
-                    larger: Some(Box::new(RBTreeNode {
+                    larger: Some(Arc::new(RBTreeNode {
                         key: 17,
                         value: 17.to_string(),
                         color: Color::Black,
@@ -550,18 +859,18 @@ mod test {
                         larger: None,
                     })),
                 })),
-                larger: Some(Box::new(RBTreeNode {
+                larger: Some(Arc::new(RBTreeNode {
                     key: 25,
                     value: 25.to_string(),
                     color: Color::Black,
-                    smaller: Some(Box::new(RBTreeNode {
+                    smaller: Some(Arc::new(RBTreeNode {
                         key: 22,
                         value: 22.to_string(),
                         color: Color::Black,
                         smaller: None,
                         larger: None,
                     })),
-                    larger: Some(Box::new(RBTreeNode {
+                    larger: Some(Arc::new(RBTreeNode {
                         key: 27,
                         value: 27.to_string(),
                         color: Color::Black,
@@ -572,14 +881,82 @@ mod test {
             })),
         };
 
-        eprintln!("{}", tree.to_string());
+        eprintln!("{}", tree);
 
-        assert_eq!(tree.rotate_child(RotDir::Left, Branch::Smaller), Ok(()));
+        assert_eq!(
+            tree.rotate_child(RotDir::Left, Branch::Smaller, &mut ()),
+            Ok(())
+        );
+
+        eprintln!("{}", tree);
+
+        assert_eq!(
+            tree.rotate_child(RotDir::Right, Branch::Smaller, &mut ()),
+            Ok(())
+        );
 
-        eprintln!("{}", tree.to_string());
+        eprintln!("{}", tree);
+    }
 
-        assert_eq!(tree.rotate_child(RotDir::Right, Branch::Smaller), Ok(()));
+    /// rotates the root of the before diagram and compares with the after diagram
+    fn check_rotate(direction: RotDir, before: &str, after: &str) {
+        let tree = RBTree::<u32, u32>::from_diagram(before).expect("invalid before diagram");
+        let expected = RBTree::<u32, u32>::from_diagram(after).expect("invalid after diagram");
+        let root = tree.root.expect("empty before diagram");
+        let rotated = match direction {
+            RotDir::Left => root.left_rotate(&mut ()),
+            RotDir::Right => root.right_rotate(&mut ()),
+        }
+        .unwrap_or_else(|(_, err)| panic!("failed to rotate: {}", err));
+        assert_eq!(
+            shape::encode::<u32, u32, _>(Some(&*rotated)),
+            expected.to_shape()
+        );
+    }
 
-        eprintln!("{}", tree.to_string());
+    #[test]
+    fn test_rotate_diagram() {
+        check_rotate(
+            RotDir::Left,
+            "B(2)
+ ├─<R(4)
+ │   ├─<B(5)
+ │   └─>B(3)
+ └─>B(1)",
+            "B(4)
+ ├─<B(5)
+ └─>R(2)
+     ├─<B(3)
+     └─>B(1)",
+        );
+        // a red inner grandchild is rotated up first
+        check_rotate(
+            RotDir::Left,
+            "B(2)
+ ├─<R(4)
+ │   ├─<B(5)
+ │   └─>R(3)
+ └─>B(1)",
+            "B(3)
+ ├─<R(4)
+ │   ├─<B(5)
+ │   └─>nil
+ └─>R(2)
+     ├─<nil
+     └─>B(1)",
+        );
+        check_rotate(
+            RotDir::Right,
+            "B(4)
+ ├─<B(5)
+ └─>R(2)
+     ├─<B(3)
+     └─>B(1)",
+            "B(2)
+ ├─<R(4)
+ │   ├─<B(5)
+ │   └─>B(3)
+ └─>B(1)",
+        );
     }
 }
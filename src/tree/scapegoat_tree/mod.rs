@@ -0,0 +1,591 @@
+use crate::prelude::*;
+use crate::tree::binary_tree::BTreeNode;
+use crate::tree::dot::{self, DotOptions};
+#[cfg(feature = "metrics")]
+use crate::tree::metrics::Stats;
+use crate::tree::metrics::{self, Metrics, ShapeReport};
+use crate::tree::pretty::{Layout, Pretty};
+use crate::tree::shape;
+use alloc::sync::Arc;
+use core::cmp::Ordering;
+use core::fmt::{Debug, Display, Formatter};
+use core::ops::Index;
+
+type SubNode<K, V> = Option<Arc<BTreeNode<K, V>>>;
+
+/// the default balance factor, see with_alpha
+const ALPHA: f64 = 0.7;
+
+/// A scapegoat tree, balanced without any data in the nodes, they are the plain
+/// BTreeNodes of key, value and two links.
+///
+/// An insert that ends deeper than log(n) / log(1 / alpha) walks back up to the first
+/// ancestor with a child holding more than alpha of its nodes, the scapegoat, and
+/// rebuilds its subtree perfectly balanced. A remove that shrinks the tree below alpha
+/// of its size since the last full rebuild rebuilds the whole tree. The height stays
+/// within log(n) / log(1 / alpha) + 1, inserts and removes take O(log n) amortized.
+pub struct ScapegoatTree<K: PartialOrd, V> {
+    pub(crate) root: SubNode<K, V>,
+    len: usize,
+    /// the largest len since the last rebuild of the whole tree
+    max_len: usize,
+    alpha: f64,
+    metrics: Metrics,
+}
+
+impl<K: PartialOrd, V> Default for ScapegoatTree<K, V> {
+    fn default() -> Self {
+        ScapegoatTree::new()
+    }
+}
+
+impl<K: PartialOrd, V> ScapegoatTree<K, V> {
+    pub fn new() -> ScapegoatTree<K, V> {
+        ScapegoatTree {
+            root: None,
+            len: 0,
+            max_len: 0,
+            alpha: ALPHA,
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// A tree with the balance factor alpha, which must be in 0.5..1.0. A lower alpha
+    /// keeps the tree flatter and rebuilds more often, the default is 0.7.
+    pub fn with_alpha(alpha: f64) -> Result<ScapegoatTree<K, V>, String> {
+        if !(0.5..1.0).contains(&alpha) {
+            return Err(format!("alpha {} is not in 0.5..1.0", alpha));
+        }
+        Ok(ScapegoatTree {
+            alpha,
+            ..ScapegoatTree::new()
+        })
+    }
+
+    /// the deepest level, counted from 0 at the root, a new node may be inserted at
+    fn max_depth(&self, len: usize) -> usize {
+        // floor(log(len) / log(1 / alpha)), counted up as core has no ln
+        let mut depth = 0;
+        let mut size = 1.0 / self.alpha;
+        while size <= len as f64 {
+            size /= self.alpha;
+            depth += 1;
+        }
+        depth
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.find(key).is_some()
+    }
+
+    pub fn find(&self, key: &K) -> Option<&V> {
+        let mut depth = 0;
+        let mut node = self.root.as_deref();
+        while let Some(curr) = node {
+            depth += 1;
+            if *key < curr.key {
+                node = curr.smaller.as_deref();
+            } else if *key > curr.key {
+                node = curr.larger.as_deref();
+            } else {
+                break;
+            }
+        }
+        self.metrics.record_search(depth);
+        node.map(|curr| &curr.value)
+    }
+
+    pub fn smallest(&self) -> Option<(&K, &V)> {
+        let mut curr = self.root.as_deref()?;
+        while let Some(subnode) = &curr.smaller {
+            curr = subnode;
+        }
+        Some((&curr.key, &curr.value))
+    }
+
+    /// the entry with the largest key below key
+    pub fn smaller(&self, key: &K) -> Option<(&K, &V)> {
+        let mut candidate = None;
+        let mut depth = 0;
+        let mut node = self.root.as_deref();
+        while let Some(curr) = node {
+            depth += 1;
+            if curr.key < *key {
+                candidate = Some(curr);
+                node = curr.larger.as_deref();
+            } else {
+                node = curr.smaller.as_deref();
+            }
+        }
+        self.metrics.record_comparisons(depth);
+        candidate.map(|node| (&node.key, &node.value))
+    }
+
+    pub fn largest(&self) -> Option<(&K, &V)> {
+        let mut curr = self.root.as_deref()?;
+        while let Some(subnode) = &curr.larger {
+            curr = subnode;
+        }
+        Some((&curr.key, &curr.value))
+    }
+
+    /// the entry with the smallest key above key
+    pub fn larger(&self, key: &K) -> Option<(&K, &V)> {
+        let mut candidate = None;
+        let mut depth = 0;
+        let mut node = self.root.as_deref();
+        while let Some(curr) = node {
+            depth += 1;
+            if curr.key > *key {
+                candidate = Some(curr);
+                node = curr.smaller.as_deref();
+            } else {
+                node = curr.larger.as_deref();
+            }
+        }
+        self.metrics.record_comparisons(depth);
+        candidate.map(|node| (&node.key, &node.value))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// height, node count and nodes per level, the black height is always None
+    pub fn shape_report(&self) -> ShapeReport {
+        metrics::shape_report::<K, V, _>(self.root.as_deref())
+    }
+
+    /// counters collected since the tree was created or reset_stats was called,
+    /// rebuilds are not counted
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> Stats {
+        self.metrics.stats()
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn reset_stats(&mut self) {
+        self.metrics.reset()
+    }
+
+    /// structural view of the tree, use {} to print it
+    pub fn pretty(&self) -> Pretty<'_, Self> {
+        Pretty::new(self)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.push_smaller(self.root.as_deref());
+        iter
+    }
+}
+
+impl<K: PartialOrd + Debug, V> ScapegoatTree<K, V> {
+    /// checks the order of the keys and the height bound, returns the height
+    pub fn check_rules(&self) -> core::result::Result<usize, String> {
+        let mut height = 0;
+        let mut stack = vec![(self.root.as_deref(), None, None, 1)];
+        while let Some((node, lower, upper, depth)) = stack.pop() {
+            let Some(node) = node else {
+                continue;
+            };
+            let below =
+                |smaller: &K, larger: &K| smaller.partial_cmp(larger) == Some(Ordering::Less);
+            if lower.is_some_and(|lower| !below(lower, &node.key))
+                || upper.is_some_and(|upper| !below(&node.key, upper))
+            {
+                return Err(format!(
+                    "scapegoat violation: key {:?} out of order between {:?} and {:?}",
+                    node.key, lower, upper
+                ));
+            }
+            height = height.max(depth);
+            stack.push((node.smaller.as_deref(), lower, Some(&node.key), depth + 1));
+            stack.push((node.larger.as_deref(), Some(&node.key), upper, depth + 1));
+        }
+        let counted = self.iter().count();
+        if counted != self.len {
+            return Err(format!(
+                "scapegoat violation: {} nodes, len is {}",
+                counted, self.len
+            ));
+        }
+        if height > self.max_depth(self.max_len) + 1 {
+            return Err(format!(
+                "scapegoat violation: height {} above the bound for {} nodes",
+                height, self.max_len
+            ));
+        }
+        Ok(height)
+    }
+}
+
+impl<K: PartialOrd + Debug, V: Debug> ScapegoatTree<K, V> {
+    /// the tree as a Graphviz digraph, render it with `dot -Tsvg`
+    pub fn to_dot(&self) -> String {
+        self.to_dot_with(&DotOptions::default())
+    }
+
+    pub fn to_dot_with(&self, options: &DotOptions<K>) -> String {
+        dot::to_dot::<K, V, _>("ScapegoatTree", self.root.as_deref(), options)
+    }
+}
+
+impl<K: PartialOrd + Display, V: Display> ScapegoatTree<K, V> {
+    /// the exact shape in the notation of the shape module
+    pub fn to_shape(&self) -> String {
+        shape::encode::<K, V, _>(self.root.as_deref())
+    }
+}
+
+/// what an insert below a link reports to its parent
+enum Insert<V> {
+    Replaced(V),
+    Done,
+    /// the new node is too deep and no scapegoat was found yet,
+    /// carries the number of nodes below the link
+    TooDeep(usize),
+}
+
+impl<K: PartialOrd + Clone, V: Clone> ScapegoatTree<K, V> {
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let max_depth = self.max_depth(self.len + 1);
+        let mut visited = 0;
+        let res = insert(
+            &mut self.root,
+            key,
+            value,
+            0,
+            max_depth,
+            self.alpha,
+            &mut visited,
+        );
+        self.metrics.record_search(visited);
+        if let Insert::Replaced(value) = res {
+            return Some(value);
+        }
+        if let Insert::TooDeep(_) = res {
+            // the root always qualifies, rounding aside
+            rebuild(&mut self.root);
+        }
+        self.len += 1;
+        self.max_len = self.max_len.max(self.len);
+        None
+    }
+
+    pub fn find_mut(&mut self, key: &K) -> Option<&mut V> {
+        // search first, a miss must not copy the path shared with a clone
+        self.find(key)?;
+        let mut curr = Arc::make_mut(self.root.as_mut()?);
+        loop {
+            let link = if *key < curr.key {
+                &mut curr.smaller
+            } else if *key > curr.key {
+                &mut curr.larger
+            } else {
+                return Some(&mut curr.value);
+            };
+            curr = Arc::make_mut(link.as_mut().expect("unexpected missing key"));
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        // search first, a miss must not copy the path shared with a clone
+        self.find(key)?;
+        let res = remove(&mut self.root, key)?;
+        self.len -= 1;
+        if (self.len as f64) < self.alpha * self.max_len as f64 {
+            rebuild(&mut self.root);
+            self.max_len = self.len;
+        }
+        Some(res)
+    }
+}
+
+/// the number of nodes below link
+fn size<K: PartialOrd, V>(link: &SubNode<K, V>) -> usize {
+    match link {
+        Some(node) => size(&node.smaller) + 1 + size(&node.larger),
+        None => 0,
+    }
+}
+
+/// moves the entries below link into out in ascending order, shared nodes are copied
+fn flatten<K: PartialOrd + Clone, V: Clone>(link: SubNode<K, V>, out: &mut Vec<(K, V)>) {
+    if let Some(node) = link {
+        let node = Arc::unwrap_or_clone(node);
+        flatten(node.smaller, out);
+        out.push((node.key, node.value));
+        flatten(node.larger, out);
+    }
+}
+
+/// replaces the subtree below link with a perfectly balanced one
+fn rebuild<K: PartialOrd + Clone, V: Clone>(link: &mut SubNode<K, V>) {
+    let mut entries = Vec::new();
+    flatten(link.take(), &mut entries);
+    let len = entries.len();
+    *link = BTreeNode::build_sorted(&mut entries.into_iter(), len);
+}
+
+/// Inserts below link at depth. On the way back up from a node deeper than max_depth
+/// the subtree sizes are counted until a child holds more than alpha of its parent's
+/// nodes, the parent is the scapegoat. visited counts the nodes key is compared with.
+fn insert<K: PartialOrd + Clone, V: Clone>(
+    link: &mut SubNode<K, V>,
+    key: K,
+    value: V,
+    depth: usize,
+    max_depth: usize,
+    alpha: f64,
+    visited: &mut u64,
+) -> Insert<V> {
+    let node = match link {
+        Some(node) => Arc::make_mut(node),
+        None => {
+            *link = Some(Arc::new(BTreeNode::new(key, value)));
+            return if depth > max_depth {
+                Insert::TooDeep(1)
+            } else {
+                Insert::Done
+            };
+        }
+    };
+    *visited += 1;
+    let (child_size, sibling) = if key < node.key {
+        match insert(
+            &mut node.smaller,
+            key,
+            value,
+            depth + 1,
+            max_depth,
+            alpha,
+            visited,
+        ) {
+            Insert::TooDeep(size) => (size, &node.larger),
+            res => return res,
+        }
+    } else if key > node.key {
+        match insert(
+            &mut node.larger,
+            key,
+            value,
+            depth + 1,
+            max_depth,
+            alpha,
+            visited,
+        ) {
+            Insert::TooDeep(size) => (size, &node.smaller),
+            res => return res,
+        }
+    } else {
+        return Insert::Replaced(core::mem::replace(&mut node.value, value));
+    };
+    let total = child_size + size(sibling) + 1;
+    if child_size as f64 > alpha * total as f64 {
+        rebuild(link);
+        Insert::Done
+    } else {
+        Insert::TooDeep(total)
+    }
+}
+
+/// Removes key below link, a node with two children takes over the next larger entry.
+/// The nodes on the path are copied if shared, so look for key first.
+fn remove<K: PartialOrd + Clone, V: Clone>(link: &mut SubNode<K, V>, key: &K) -> Option<V> {
+    let node = Arc::make_mut(link.as_mut()?);
+    if *key < node.key {
+        remove(&mut node.smaller, key)
+    } else if *key > node.key {
+        remove(&mut node.larger, key)
+    } else if node.smaller.is_some() && node.larger.is_some() {
+        let (key, value) = node.remove_next_larger();
+        node.key = key;
+        Some(core::mem::replace(&mut node.value, value))
+    } else {
+        let mut node = Arc::unwrap_or_clone(link.take().expect("unexpected empty link"));
+        *link = node.smaller.take().or(node.larger.take());
+        Some(node.value)
+    }
+}
+
+impl<K: PartialOrd, V> Clone for ScapegoatTree<K, V> {
+    /// the clone shares all nodes with self, nodes are copied when either tree modifies them
+    fn clone(&self) -> Self {
+        ScapegoatTree {
+            root: self.root.clone(),
+            len: self.len,
+            max_len: self.max_len,
+            alpha: self.alpha,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+/// ascending iterator over the entries of a ScapegoatTree
+pub struct Iter<'a, K: PartialOrd, V> {
+    // nodes still to be visited, the next one on top
+    stack: Vec<&'a BTreeNode<K, V>>,
+}
+
+impl<'a, K: PartialOrd, V> Iter<'a, K, V> {
+    fn push_smaller(&mut self, mut node: Option<&'a BTreeNode<K, V>>) {
+        while let Some(curr) = node {
+            self.stack.push(curr);
+            node = curr.smaller.as_deref();
+        }
+    }
+}
+
+impl<'a, K: PartialOrd, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_smaller(node.larger.as_deref());
+        Some((&node.key, &node.value))
+    }
+}
+
+impl<'a, K: PartialOrd, V> IntoIterator for &'a ScapegoatTree<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: PartialOrd, V: PartialEq> PartialEq for ScapegoatTree<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl<K: PartialOrd, V> Index<&K> for ScapegoatTree<K, V> {
+    type Output = V;
+
+    fn index(&self, key: &K) -> &V {
+        self.find(key).expect("key not found in ScapegoatTree")
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> Extend<(K, V)> for ScapegoatTree<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> FromIterator<(K, V)> for ScapegoatTree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tree = ScapegoatTree::new();
+        tree.extend(iter);
+        tree
+    }
+}
+
+impl<K: PartialOrd + Debug, V: Debug> Debug for ScapegoatTree<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: PartialOrd + Debug, V: Debug> Display for Pretty<'_, ScapegoatTree<K, V>> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        if let Some(root) = &self.tree.root {
+            let mut buffer = String::new();
+            let mut layout = Layout::new(&self.options);
+            root.to_str_buffer(&mut buffer, "", true, false, 0, &mut layout);
+            write!(f, "{}", buffer)
+        } else {
+            write!(f, "nil")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tree::test_map::{self, test_map_impl};
+    use rand::seq::SliceRandom;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn check(tree: &ScapegoatTree<u32, u32>) -> usize {
+        tree.check_rules()
+            .unwrap_or_else(|err| panic!("{}\n{}", err, tree.pretty()))
+    }
+
+    #[test]
+    fn sg_test_insert_remove() {
+        // ascending keys degenerate a BTree, here they trigger rebuilds
+        let mut tree = ScapegoatTree::new();
+        for key in 0..2000 {
+            assert_eq!(tree.insert(key, key), None);
+            check(&tree);
+        }
+        assert_eq!(tree.len(), 2000);
+        // log(2000) / log(1 / 0.7) + 1
+        assert!(check(&tree) <= 22);
+        assert_eq!(tree.insert(5, 50), Some(5));
+        *tree.find_mut(&5).expect("key not found") = 5;
+
+        let mut keys: Vec<u32> = (0..2000).collect();
+        keys.shuffle(&mut StdRng::seed_from_u64(17));
+        let snapshot = tree.clone();
+        for key in &keys {
+            assert_eq!(tree.remove(key), Some(*key));
+            assert_eq!(tree.remove(key), None);
+            check(&tree);
+        }
+        assert!(tree.is_empty());
+        assert_eq!(tree.len(), 0);
+        // the clone is untouched
+        check(&snapshot);
+        assert!(snapshot
+            .iter()
+            .map(|(key, value)| (*key, *value))
+            .eq((0..2000).map(|key| (key, key))));
+    }
+
+    #[test]
+    fn sg_test_alpha() {
+        assert!(ScapegoatTree::<u32, u32>::with_alpha(0.4).is_err());
+        assert!(ScapegoatTree::<u32, u32>::with_alpha(1.0).is_err());
+        let mut tree = ScapegoatTree::with_alpha(0.5).expect("valid alpha");
+        tree.extend((0..1023).map(|key| (key, key)));
+        // alpha 0.5 allows no slack above a perfect tree
+        assert_eq!(check(&tree), 10);
+    }
+
+    test_map_impl!(ScapegoatTree<u32, u32>, check);
+
+    fn node_ptrs<K: PartialOrd, V>(link: &SubNode<K, V>, ptrs: &mut Vec<usize>) {
+        if let Some(node) = link {
+            ptrs.push(Arc::as_ptr(node) as usize);
+            node_ptrs(&node.smaller, ptrs);
+            node_ptrs(&node.larger, ptrs);
+        }
+    }
+
+    #[test]
+    fn sg_test_clone() {
+        test_map::check_clone(|tree: &ScapegoatTree<u32, u32>| {
+            let mut ptrs = Vec::new();
+            node_ptrs(&tree.root, &mut ptrs);
+            ptrs
+        });
+    }
+
+    #[test]
+    fn sg_test_against_rbtree() {
+        let (tree, rb_tree, _) =
+            test_map::check_against_rbtree::<ScapegoatTree<u32, u32>>(19, 5000, 400, 0.55);
+        assert_eq!(tree.len(), rb_tree.iter().count());
+    }
+}
@@ -0,0 +1,113 @@
+//! Serde support, the trees serialize as maps in ascending key order.
+//! Deserializing collects the entries and builds the tree with from_sorted,
+//! input written by serialize is sorted and takes the O(n) path.
+
+use crate::prelude::*;
+use crate::tree::binary_tree::BTree;
+use crate::tree::rb_tree::RBTree;
+use core::fmt::{Debug, Formatter};
+use core::marker::PhantomData;
+use serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+impl<K: PartialOrd + Serialize, V: Serialize, O> Serialize for RBTree<K, V, O> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+impl<K: PartialOrd + Serialize, V: Serialize> Serialize for BTree<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+impl<'de, K, V> Deserialize<'de> for RBTree<K, V>
+where
+    K: PartialOrd + Debug + Clone + Deserialize<'de>,
+    V: Debug + Clone + Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entries = deserializer.deserialize_map(EntryVisitor(PhantomData))?;
+        Ok(RBTree::from_sorted(entries))
+    }
+}
+
+impl<'de, K, V> Deserialize<'de> for BTree<K, V>
+where
+    K: PartialOrd + Clone + Deserialize<'de>,
+    V: Clone + Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entries = deserializer.deserialize_map(EntryVisitor(PhantomData))?;
+        Ok(BTree::from_sorted(entries))
+    }
+}
+
+/// collects the entries of a map in input order
+struct EntryVisitor<K, V>(PhantomData<(K, V)>);
+
+impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Visitor<'de> for EntryVisitor<K, V> {
+    type Value = Vec<(K, V)>;
+
+    fn expecting(&self, formatter: &mut Formatter) -> core::fmt::Result {
+        formatter.write_str("a map")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        // the hint comes from the input, cap it so a bogus length cannot exhaust memory
+        let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(0).min(4096));
+        while let Some(entry) = map.next_entry()? {
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn serde_test_rb_json() {
+        let tree: RBTree<u32, String> = (0..100).map(|key| (key, key.to_string())).collect();
+        let json = serde_json::to_string(&tree).expect("failed to serialize");
+        assert!(json.starts_with(r#"{"0":"0","1":"1","2":"2","#));
+        let copy: RBTree<u32, String> = serde_json::from_str(&json).expect("failed to deserialize");
+        assert_eq!(copy, tree);
+        assert_eq!(copy.check_rules(), tree.check_rules());
+
+        let empty: RBTree<String, u32> = serde_json::from_str("{}").expect("failed to deserialize");
+        assert!(empty.is_empty());
+        assert_eq!(
+            serde_json::to_string(&empty).expect("failed to serialize"),
+            "{}"
+        );
+
+        // unsorted input and duplicate keys take the insert path, the last value wins
+        let tree: RBTree<String, u32> =
+            serde_json::from_str(r#"{"b":1,"a":2,"c":3,"a":4}"#).expect("failed to deserialize");
+        assert!(tree.check_rules().is_ok());
+        assert_eq!(
+            tree.iter()
+                .map(|(key, value)| (key.as_str(), *value))
+                .collect::<Vec<_>>(),
+            vec![("a", 4), ("b", 1), ("c", 3)]
+        );
+
+        assert!(serde_json::from_str::<RBTree<u32, u32>>("[1, 2]").is_err());
+        assert!(serde_json::from_str::<RBTree<u32, u32>>(r#"{"1":"x"}"#).is_err());
+    }
+
+    #[test]
+    fn serde_test_bt_json() {
+        let tree = BTree::from([(3u32, vec![1, 2]), (1, vec![]), (2, vec![3])]);
+        let json = serde_json::to_string(&tree).expect("failed to serialize");
+        assert_eq!(json, r#"{"1":[],"2":[3],"3":[1,2]}"#);
+        let copy: BTree<u32, Vec<u32>> =
+            serde_json::from_str(&json).expect("failed to deserialize");
+        assert_eq!(copy, tree);
+        // the sorted input is built balanced
+        assert_eq!(copy.shape_report().per_depth, vec![1, 2]);
+    }
+}
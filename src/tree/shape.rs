@@ -0,0 +1,194 @@
+//! A text encoding that keeps the exact shape of a tree, meant to paste failing
+//! trees into regression tests.
+//!
+//! The nodes are listed in pre-order separated by spaces, every empty subtree is a `.`.
+//! A node is its color `R` or `B` (`N` for uncolored trees), the key, `=` and the value,
+//! both written with Display and read back with FromStr. `%`, `=` and whitespace inside
+//! keys and values are percent encoded.
+//!
+//! `B2=b R1=a . . R3=c . .` is a black root 2 with the red children 1 and 3.
+
+use crate::prelude::*;
+use crate::tree::rb_tree::Color;
+use crate::tree::TreeNode;
+use core::fmt::Display;
+use core::str::FromStr;
+
+const NIL: &str = ".";
+
+/// Writes the tree below root in pre-order, iterative so degenerated trees do not overflow.
+pub(crate) fn encode<K, V, N>(root: Option<&N>) -> String
+where
+    K: Display,
+    V: Display,
+    N: TreeNode<K, V>,
+{
+    let mut tokens: Vec<String> = Vec::new();
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        match node {
+            Some(node) => {
+                let color = match node.color() {
+                    Some(Color::Red) => 'R',
+                    Some(Color::Black) => 'B',
+                    None => 'N',
+                };
+                tokens.push(format!(
+                    "{}{}={}",
+                    color,
+                    escape(&node.key().to_string()),
+                    escape(&node.value().to_string())
+                ));
+                // the smaller subtree is listed first
+                stack.push(node.larger());
+                stack.push(node.smaller());
+            }
+            None => tokens.push(NIL.to_string()),
+        }
+    }
+    tokens.join(" ")
+}
+
+/// a node whose larger subtree is not complete yet
+struct Partial<K, V, T> {
+    color: Option<Color>,
+    key: K,
+    value: V,
+    smaller: Option<Option<T>>,
+}
+
+/// Rebuilds the tree from an encoded shape, make creates a node from color, key, value
+/// and the smaller and larger subtrees. No rules are checked, the shape is taken as is.
+pub(crate) fn decode<K, V, T>(
+    shape: &str,
+    mut make: impl FnMut(Option<Color>, K, V, Option<T>, Option<T>) -> Result<T, String>,
+) -> Result<Option<T>, String>
+where
+    K: FromStr,
+    V: FromStr,
+{
+    let mut stack: Vec<Partial<K, V, T>> = Vec::new();
+    let mut root = None;
+    for (idx, token) in shape.split_whitespace().enumerate() {
+        if root.is_some() {
+            return Err(format!(
+                "unexpected token {} after the tree: {}",
+                idx, token
+            ));
+        }
+        if token != NIL {
+            stack.push(parse_node(token).map_err(|msg| format!("token {}: {}", idx, msg))?);
+            continue;
+        }
+        // a complete subtree becomes the child of the innermost partial node,
+        // completing that one too if it was its larger subtree
+        let mut subtree = None;
+        loop {
+            match stack.last_mut() {
+                Some(parent) if parent.smaller.is_none() => {
+                    parent.smaller = Some(subtree);
+                    break;
+                }
+                Some(_) => {
+                    let parent = stack.pop().expect("unexpected empty stack");
+                    let smaller = parent.smaller.expect("unexpected missing smaller subtree");
+                    subtree = Some(make(
+                        parent.color,
+                        parent.key,
+                        parent.value,
+                        smaller,
+                        subtree,
+                    )?);
+                }
+                None => {
+                    root = Some(subtree);
+                    break;
+                }
+            }
+        }
+    }
+    root.ok_or_else(|| "unexpected end of shape".to_string())
+}
+
+fn parse_node<K: FromStr, V: FromStr, T>(token: &str) -> Result<Partial<K, V, T>, String> {
+    let mut chars = token.chars();
+    let color = match chars.next() {
+        Some('R') => Some(Color::Red),
+        Some('B') => Some(Color::Black),
+        Some('N') => None,
+        _ => return Err(format!("invalid node {}", token)),
+    };
+    let (key, value) = chars
+        .as_str()
+        .split_once('=')
+        .ok_or_else(|| format!("missing '=' in {}", token))?;
+    Ok(Partial {
+        color,
+        key: unescape(key)?
+            .parse()
+            .map_err(|_| format!("invalid key in {}", token))?,
+        value: unescape(value)?
+            .parse()
+            .map_err(|_| format!("invalid value in {}", token))?,
+        smaller: None,
+    })
+}
+
+fn escape(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for ch in text.chars() {
+        if ch == '%' || ch == '=' || ch.is_whitespace() {
+            let mut buf = [0u8; 4];
+            for byte in ch.encode_utf8(&mut buf).bytes() {
+                res.push_str(&format!("%{:02X}", byte));
+            }
+        } else {
+            res.push(ch);
+        }
+    }
+    res
+}
+
+fn unescape(text: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail
+                .get(..2)
+                .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                .and_then(|hex| core::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| format!("invalid escape in {}", text))?;
+            bytes.push(hex);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| format!("invalid escape in {}", text))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shape_test_escape() {
+        for text in [
+            "",
+            "plain",
+            "a b=c%d",
+            "tab\tnew\nline",
+            "wide\u{3000}space",
+        ] {
+            let escaped = escape(text);
+            assert!(!escaped.contains(|ch: char| ch == '=' || ch.is_whitespace()));
+            assert_eq!(unescape(&escaped).expect("failed to unescape"), text);
+        }
+        assert!(unescape("%4").is_err());
+        assert!(unescape("%zz").is_err());
+        assert!(unescape("%+1").is_err());
+    }
+}
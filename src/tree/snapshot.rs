@@ -0,0 +1,524 @@
+//! Binary snapshots of a tree, written by RBTree::write_snapshot and read by
+//! RBTree::read_snapshot.
+//!
+//! All integers are little endian:
+//!
+//! | field    | size       | content                                       |
+//! |----------|------------|-----------------------------------------------|
+//! | magic    | 8          | `RBTSNAP\0`                                   |
+//! | version  | 4          | format version, currently 1                   |
+//! | count    | 8          | number of records                             |
+//! | records  | count x .. | key length (4), key, value length (4), value  |
+//! | crc      | 4          | CRC-32 (IEEE) of all preceding bytes          |
+//!
+//! Records are in strictly ascending key order, keys and values are encoded by their Codec.
+//! Nothing follows the checksum.
+
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
+
+pub const MAGIC: [u8; 8] = *b"RBTSNAP\0";
+pub const VERSION: u32 = 1;
+
+/// Converts keys and values to and from the bytes of a snapshot record
+pub trait Codec: Sized {
+    fn encode(&self, buf: &mut Vec<u8>);
+    /// bytes holds exactly what encode wrote
+    fn decode(bytes: &[u8]) -> Result<Self, String>;
+}
+
+macro_rules! int_codec {
+    ($($int:ty),*) => {
+        $(
+            impl Codec for $int {
+                fn encode(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn decode(bytes: &[u8]) -> Result<Self, String> {
+                    Ok(<$int>::from_le_bytes(bytes.try_into().map_err(|_| {
+                        format!("{} bytes for {}", bytes.len(), stringify!($int))
+                    })?))
+                }
+            }
+        )*
+    };
+}
+
+int_codec!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl Codec for String {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        String::from_utf8(bytes.to_vec()).map_err(|err| err.to_string())
+    }
+}
+
+impl Codec for Vec<u8> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl Codec for bool {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(*self as u8);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        match bytes {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(format!("invalid bool {:?}", bytes)),
+        }
+    }
+}
+
+impl Codec for () {
+    fn encode(&self, _buf: &mut Vec<u8>) {}
+
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("{} bytes for ()", bytes.len()))
+        }
+    }
+}
+
+/// Why a snapshot could not be written or read
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(std::io::Error),
+    /// the input does not start with MAGIC
+    BadMagic,
+    UnsupportedVersion(u32),
+    /// the input ended before the checksum
+    Truncated,
+    ChecksumMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    /// the codec rejected the key or value of a record
+    Decode {
+        record: u64,
+        msg: String,
+    },
+    /// the key of a record is not larger than the one before
+    Unsorted {
+        record: u64,
+    },
+    /// a key or value is larger than a record can hold
+    TooLarge {
+        record: u64,
+        len: usize,
+    },
+    /// bytes follow the checksum
+    TrailingData,
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "snapshot i/o error: {}", err),
+            SnapshotError::BadMagic => write!(f, "not a snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "snapshot checksum mismatch, expected {:08x}, got {:08x}",
+                expected, actual
+            ),
+            SnapshotError::Decode { record, msg } => {
+                write!(f, "failed to decode record {}: {}", record, msg)
+            }
+            SnapshotError::Unsorted { record } => {
+                write!(f, "record {} is out of order", record)
+            }
+            SnapshotError::TooLarge { record, len } => {
+                write!(f, "record {} has a field of {} bytes", record, len)
+            }
+            SnapshotError::TrailingData => write!(f, "snapshot has data after the checksum"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SnapshotError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(err: std::io::Error) -> Self {
+        if err.kind() == std::io::ErrorKind::UnexpectedEof {
+            SnapshotError::Truncated
+        } else {
+            SnapshotError::Io(err)
+        }
+    }
+}
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut idx = 0;
+    while idx < 256 {
+        let mut crc = idx as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[idx] = crc;
+        idx += 1;
+    }
+    table
+}
+
+static CRC_TABLE: [u32; 256] = crc_table();
+
+/// CRC-32 (IEEE), the checksum of zip and png
+#[derive(Clone, Copy)]
+pub(crate) struct Crc32(u32);
+
+impl Crc32 {
+    pub(crate) fn new() -> Crc32 {
+        Crc32(!0)
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = CRC_TABLE[((self.0 ^ *byte as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub(crate) fn value(&self) -> u32 {
+        !self.0
+    }
+}
+
+/// checksums everything written through it
+struct CrcWriter<W> {
+    inner: W,
+    crc: Crc32,
+}
+
+impl<W: Write> CrcWriter<W> {
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), SnapshotError> {
+        self.crc.update(bytes);
+        Ok(self.inner.write_all(bytes)?)
+    }
+
+    fn write_field(&mut self, record: u64, bytes: &[u8]) -> Result<(), SnapshotError> {
+        let len = u32::try_from(bytes.len()).map_err(|_| SnapshotError::TooLarge {
+            record,
+            len: bytes.len(),
+        })?;
+        self.write_all(&len.to_le_bytes())?;
+        self.write_all(bytes)
+    }
+}
+
+/// the unread part of a snapshot, running out of bytes is an error
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.0.len() < len {
+            return Err(SnapshotError::Truncated);
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], SnapshotError> {
+        Ok(self.take(N)?.try_into().expect("unexpected slice length"))
+    }
+
+    /// a length prefixed field
+    fn field(&mut self) -> Result<&'a [u8], SnapshotError> {
+        let len = u32::from_le_bytes(self.array()?) as usize;
+        self.take(len)
+    }
+}
+
+/// Writes a snapshot record by record, the count is part of the header
+pub(crate) struct Writer<W: Write> {
+    out: CrcWriter<W>,
+    count: u64,
+    written: u64,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Writer<W> {
+    pub(crate) fn new(out: W, count: u64) -> Result<Writer<W>, SnapshotError> {
+        let mut out = CrcWriter {
+            inner: out,
+            crc: Crc32::new(),
+        };
+        out.write_all(&MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&count.to_le_bytes())?;
+        Ok(Writer {
+            out,
+            count,
+            written: 0,
+            buf: Vec::new(),
+        })
+    }
+
+    /// appends a record, the keys must be pushed in ascending order
+    pub(crate) fn push<K: Codec, V: Codec>(
+        &mut self,
+        key: &K,
+        value: &V,
+    ) -> Result<(), SnapshotError> {
+        self.buf.clear();
+        key.encode(&mut self.buf);
+        self.out.write_field(self.written, &self.buf)?;
+        self.buf.clear();
+        value.encode(&mut self.buf);
+        self.out.write_field(self.written, &self.buf)?;
+        self.written += 1;
+        Ok(())
+    }
+
+    /// writes the checksum and returns the output
+    pub(crate) fn finish(mut self) -> Result<W, SnapshotError> {
+        assert_eq!(
+            self.written, self.count,
+            "record count differs from the header"
+        );
+        let crc = self.out.crc.value();
+        self.out.inner.write_all(&crc.to_le_bytes())?;
+        self.out.inner.flush()?;
+        Ok(self.out.inner)
+    }
+}
+
+/// Writes count entries in ascending key order as a snapshot
+pub(crate) fn write<'a, K, V, W>(
+    entries: impl Iterator<Item = (&'a K, &'a V)>,
+    count: usize,
+    out: W,
+) -> Result<(), SnapshotError>
+where
+    K: Codec + 'a,
+    V: Codec + 'a,
+    W: Write,
+{
+    let mut writer = Writer::new(out, count as u64)?;
+    for (key, value) in entries {
+        writer.push(key, value)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Reads the records of a snapshot and passes the key bytes, the value bytes and the
+/// offset of the value bytes in the input to visit. The checksum is verified over the
+/// raw bytes before the first record is visited, the input is read into memory for that.
+/// Returns the record count.
+pub(crate) fn read_records<R: Read>(
+    mut input: R,
+    mut visit: impl FnMut(u64, &[u8], &[u8], u64) -> Result<(), SnapshotError>,
+) -> Result<u64, SnapshotError> {
+    // the count is not trusted before the checksum matched, only what the input
+    // delivers is allocated
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    let mut cursor = Cursor(&data);
+    if cursor.array::<8>()? != MAGIC {
+        return Err(SnapshotError::BadMagic);
+    }
+    let version = u32::from_le_bytes(cursor.array()?);
+    if version != VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    let count = u64::from_le_bytes(cursor.array()?);
+    let header_len = data.len() - cursor.0.len();
+
+    // skip over the records by their lengths to find the checksum
+    for _ in 0..count {
+        cursor.field()?;
+        cursor.field()?;
+    }
+    let records_end = data.len() - cursor.0.len();
+    let expected = u32::from_le_bytes(cursor.array()?);
+    if !cursor.0.is_empty() {
+        return Err(SnapshotError::TrailingData);
+    }
+    let mut crc = Crc32::new();
+    crc.update(&data[..records_end]);
+    let actual = crc.value();
+    if expected != actual {
+        return Err(SnapshotError::ChecksumMismatch { expected, actual });
+    }
+
+    let mut cursor = Cursor(&data[header_len..records_end]);
+    for record in 0..count {
+        let key = cursor.field()?;
+        let value_offset = (records_end - cursor.0.len() + 4) as u64;
+        let value = cursor.field()?;
+        visit(record, key, value, value_offset)?;
+    }
+    Ok(count)
+}
+
+/// Reads the entries of a snapshot, they are verified to be in strictly ascending key order.
+/// Nothing is decoded before the checksum matched.
+pub(crate) fn read<K, V, R>(input: R) -> Result<Vec<(K, V)>, SnapshotError>
+where
+    K: Codec + PartialOrd,
+    V: Codec,
+    R: Read,
+{
+    // the count is not trusted before the checksum matched, do not preallocate for it
+    let mut entries: Vec<(K, V)> = Vec::new();
+    read_records(input, |record, key, value, _| {
+        let key = K::decode(key).map_err(|msg| SnapshotError::Decode { record, msg })?;
+        let value = V::decode(value).map_err(|msg| SnapshotError::Decode { record, msg })?;
+        if let Some((last, _)) = entries.last() {
+            if last.partial_cmp(&key) != Some(Ordering::Less) {
+                return Err(SnapshotError::Unsorted { record });
+            }
+        }
+        entries.push((key, value));
+        Ok(())
+    })?;
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tree::rb_tree::RBTree;
+
+    fn snapshot<K: PartialOrd + Codec, V: Codec>(entries: &[(K, V)]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write(
+            entries.iter().map(|(key, value)| (key, value)),
+            entries.len(),
+            &mut buf,
+        )
+        .expect("failed to write snapshot");
+        buf
+    }
+
+    #[test]
+    fn snapshot_test_crc() {
+        let mut crc = Crc32::new();
+        crc.update(b"123456789");
+        assert_eq!(crc.value(), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn snapshot_test_round_trip() {
+        let tree: RBTree<u64, String> = RBTree::from_sorted(
+            (0..100_000u64)
+                .map(|key| (key * 3, format!("value {}", key)))
+                .collect(),
+        );
+        let mut buf = Vec::new();
+        tree.write_snapshot(&mut buf)
+            .expect("failed to write snapshot");
+        assert_eq!(buf[..8], MAGIC);
+        assert_eq!(buf[12..20], 100_000u64.to_le_bytes());
+
+        let copy =
+            RBTree::<u64, String>::read_snapshot(buf.as_slice()).expect("failed to read snapshot");
+        assert!(copy.check_rules().is_ok());
+        assert_eq!(copy, tree);
+        assert_eq!(copy.len(), 100_000);
+
+        let empty: RBTree<String, ()> = RBTree::new();
+        let mut buf = Vec::new();
+        empty
+            .write_snapshot(&mut buf)
+            .expect("failed to write snapshot");
+        assert_eq!(buf.len(), 8 + 4 + 8 + 4);
+        let copy =
+            RBTree::<String, ()>::read_snapshot(buf.as_slice()).expect("failed to read snapshot");
+        assert!(copy.is_empty());
+    }
+
+    #[test]
+    fn snapshot_test_corrupted() {
+        let buf = snapshot(&[(1u32, "one".to_string()), (2, "two".to_string())]);
+        let read = |bytes: &[u8]| RBTree::<u32, String>::read_snapshot(bytes).map(|_| ());
+
+        for len in 0..buf.len() {
+            assert!(
+                matches!(read(&buf[..len]), Err(SnapshotError::Truncated)),
+                "prefix of {} bytes",
+                len
+            );
+        }
+
+        let mut bad = buf.clone();
+        bad.push(0);
+        assert!(matches!(read(&bad), Err(SnapshotError::TrailingData)));
+        let mut bad = buf.clone();
+        bad.extend_from_slice(&buf);
+        assert!(matches!(read(&bad), Err(SnapshotError::TrailingData)));
+
+        let mut bad = buf.clone();
+        bad[0] = b'X';
+        assert!(matches!(read(&bad), Err(SnapshotError::BadMagic)));
+
+        let mut bad = buf.clone();
+        bad[8] = 2;
+        assert!(matches!(
+            read(&bad),
+            Err(SnapshotError::UnsupportedVersion(2))
+        ));
+
+        // flip a bit in the last value
+        let mut bad = buf.clone();
+        let idx = buf.len() - 5;
+        bad[idx] ^= 0x10;
+        assert!(matches!(
+            read(&bad),
+            Err(SnapshotError::ChecksumMismatch { .. })
+        ));
+
+        // a value the codec rejects is caught by the checksum before it is decoded
+        let mut bad = buf.clone();
+        let idx = buf.len() - 5;
+        bad[idx] = 0xff;
+        assert!(matches!(
+            read(&bad),
+            Err(SnapshotError::ChecksumMismatch { .. })
+        ));
+
+        // checksummed but not decodable or not sorted
+        let buf = snapshot(&[(vec![0xffu8], 1u8)]);
+        assert!(matches!(
+            RBTree::<String, u8>::read_snapshot(buf.as_slice()),
+            Err(SnapshotError::Decode { record: 0, .. })
+        ));
+        let buf = snapshot(&[(2u8, ()), (1, ())]);
+        assert!(matches!(
+            RBTree::<u8, ()>::read_snapshot(buf.as_slice()),
+            Err(SnapshotError::Unsorted { record: 1 })
+        ));
+    }
+}
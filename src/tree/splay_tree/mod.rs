@@ -0,0 +1,378 @@
+use crate::prelude::*;
+#[cfg(feature = "metrics")]
+use crate::tree::metrics::Stats;
+use crate::tree::metrics::{self, Metrics, ShapeReport};
+use core::cmp::Ordering;
+use core::fmt::{Debug, Formatter};
+use splay_node::SplayNode;
+
+type SubNode<K, V> = Option<Box<SplayNode<K, V>>>;
+
+mod splay_node;
+
+/// A self adjusting binary search tree, every find, insert and remove splays the key
+/// to the root. Keys that are accessed often stay near the top, a sequence of m
+/// operations takes O(m log n) in total, while a single one may walk a long path.
+///
+/// Lookups change the shape, so they take &mut self. peek looks up without splaying.
+/// The nodes are not shared, a degenerated tree would overflow the stack with
+/// recursion, so all walks and the drop are iterative.
+pub struct SplayTree<K: PartialOrd, V> {
+    root: SubNode<K, V>,
+    metrics: Metrics,
+}
+
+impl<K: PartialOrd, V> Default for SplayTree<K, V> {
+    fn default() -> Self {
+        SplayTree::new()
+    }
+}
+
+impl<K: PartialOrd, V> SplayTree<K, V> {
+    pub fn new() -> SplayTree<K, V> {
+        SplayTree {
+            root: None,
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// splays key or its last neighbor on the way to the root, true if key was found
+    fn splay(&mut self, key: &K) -> bool {
+        match self.root.take() {
+            Some(root) => {
+                let (root, depth) = splay_node::splay(root, key);
+                self.metrics.record_search(depth);
+                let found = root.key == *key;
+                self.root = Some(root);
+                found
+            }
+            None => {
+                self.metrics.record_search(0);
+                false
+            }
+        }
+    }
+
+    pub fn contains(&mut self, key: &K) -> bool {
+        self.splay(key)
+    }
+
+    pub fn find(&mut self, key: &K) -> Option<&V> {
+        if self.splay(key) {
+            self.root.as_ref().map(|root| &root.value)
+        } else {
+            None
+        }
+    }
+
+    pub fn find_mut(&mut self, key: &K) -> Option<&mut V> {
+        if self.splay(key) {
+            self.root.as_mut().map(|root| &mut root.value)
+        } else {
+            None
+        }
+    }
+
+    /// looks up key without changing the tree
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        let mut node = self.root.as_deref();
+        while let Some(curr) = node {
+            if *key < curr.key {
+                node = curr.smaller.as_deref();
+            } else if *key > curr.key {
+                node = curr.larger.as_deref();
+            } else {
+                return Some(&curr.value);
+            }
+        }
+        None
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let Some(mut root) = self.root.take() else {
+            self.metrics.record_search(0);
+            self.root = Some(Box::new(SplayNode::new(key, value)));
+            return None;
+        };
+        let depth;
+        (root, depth) = splay_node::splay(root, &key);
+        self.metrics.record_search(depth);
+        // the new node takes the place of its neighbor at the root
+        let mut node = Box::new(SplayNode::new(key, value));
+        if node.key < root.key {
+            node.smaller = root.smaller.take();
+            node.larger = Some(root);
+        } else if node.key > root.key {
+            node.larger = root.larger.take();
+            node.smaller = Some(root);
+        } else {
+            let res = core::mem::replace(&mut root.value, node.value);
+            self.root = Some(root);
+            return Some(res);
+        }
+        self.root = Some(node);
+        None
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        if !self.splay(key) {
+            return None;
+        }
+        let mut root = self.root.take().expect("unexpected empty root");
+        self.root = match root.smaller.take() {
+            // the largest smaller key comes up without a larger child, which the
+            // larger subtree then becomes
+            Some(smaller) => {
+                let (mut smaller, depth) = splay_node::splay(smaller, key);
+                self.metrics.record_comparisons(depth);
+                smaller.larger = root.larger.take();
+                Some(smaller)
+            }
+            None => root.larger.take(),
+        };
+        Some(root.value)
+    }
+
+    pub fn smallest(&self) -> Option<(&K, &V)> {
+        let mut curr = self.root.as_deref()?;
+        while let Some(subnode) = &curr.smaller {
+            curr = subnode;
+        }
+        Some((&curr.key, &curr.value))
+    }
+
+    pub fn largest(&self) -> Option<(&K, &V)> {
+        let mut curr = self.root.as_deref()?;
+        while let Some(subnode) = &curr.larger {
+            curr = subnode;
+        }
+        Some((&curr.key, &curr.value))
+    }
+
+    /// the entry with the largest key below key, splays key's neighborhood to the root
+    pub fn smaller(&mut self, key: &K) -> Option<(&K, &V)> {
+        self.splay(key);
+        let root = self.root.as_deref()?;
+        if root.key < *key {
+            return Some((&root.key, &root.value));
+        }
+        let mut curr = root.smaller.as_deref()?;
+        while let Some(subnode) = &curr.larger {
+            curr = subnode;
+        }
+        Some((&curr.key, &curr.value))
+    }
+
+    /// the entry with the smallest key above key, splays key's neighborhood to the root
+    pub fn larger(&mut self, key: &K) -> Option<(&K, &V)> {
+        self.splay(key);
+        let root = self.root.as_deref()?;
+        if root.key > *key {
+            return Some((&root.key, &root.value));
+        }
+        let mut curr = root.larger.as_deref()?;
+        while let Some(subnode) = &curr.smaller {
+            curr = subnode;
+        }
+        Some((&curr.key, &curr.value))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// the number of nodes a search for key visits without splaying or counting it
+    pub fn search_depth(&self, key: &K) -> usize {
+        metrics::search_depth::<K, V, _>(self.root.as_deref(), key)
+    }
+
+    /// height, node count and nodes per level, the black height is always None
+    pub fn shape_report(&self) -> ShapeReport {
+        metrics::shape_report::<K, V, _>(self.root.as_deref())
+    }
+
+    /// counters collected since the tree was created or reset_stats was called, every
+    /// splaying call, insert included, counts as a search, rotations are not counted
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> Stats {
+        self.metrics.stats()
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn reset_stats(&mut self) {
+        self.metrics.reset()
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.push_smaller(self.root.as_deref());
+        iter
+    }
+}
+
+impl<K: PartialOrd + Debug, V> SplayTree<K, V> {
+    /// checks the order of the keys, returns the height
+    pub fn check_rules(&self) -> core::result::Result<usize, String> {
+        let mut height = 0;
+        let mut stack = vec![(self.root.as_deref(), None, None, 1)];
+        while let Some((node, lower, upper, depth)) = stack.pop() {
+            let Some(node) = node else {
+                continue;
+            };
+            let below =
+                |smaller: &K, larger: &K| smaller.partial_cmp(larger) == Some(Ordering::Less);
+            if lower.is_some_and(|lower| !below(lower, &node.key))
+                || upper.is_some_and(|upper| !below(&node.key, upper))
+            {
+                return Err(format!(
+                    "splay violation: key {:?} out of order between {:?} and {:?}",
+                    node.key, lower, upper
+                ));
+            }
+            height = height.max(depth);
+            stack.push((node.smaller.as_deref(), lower, Some(&node.key), depth + 1));
+            stack.push((node.larger.as_deref(), Some(&node.key), upper, depth + 1));
+        }
+        Ok(height)
+    }
+}
+
+impl<K: PartialOrd, V> Drop for SplayTree<K, V> {
+    /// unlinks the nodes one by one, dropping a long chain recursively would overflow
+    fn drop(&mut self) {
+        let mut stack: Vec<Box<SplayNode<K, V>>> = self.root.take().into_iter().collect();
+        while let Some(mut node) = stack.pop() {
+            stack.extend(node.smaller.take());
+            stack.extend(node.larger.take());
+        }
+    }
+}
+
+/// ascending iterator over the entries of a SplayTree, it does not splay
+pub struct Iter<'a, K: PartialOrd, V> {
+    // nodes still to be visited, the next one on top
+    stack: Vec<&'a SplayNode<K, V>>,
+}
+
+impl<'a, K: PartialOrd, V> Iter<'a, K, V> {
+    fn push_smaller(&mut self, mut node: Option<&'a SplayNode<K, V>>) {
+        while let Some(curr) = node {
+            self.stack.push(curr);
+            node = curr.smaller.as_deref();
+        }
+    }
+}
+
+impl<'a, K: PartialOrd, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_smaller(node.larger.as_deref());
+        Some((&node.key, &node.value))
+    }
+}
+
+impl<'a, K: PartialOrd, V> IntoIterator for &'a SplayTree<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: PartialOrd, V> Extend<(K, V)> for SplayTree<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: PartialOrd, V> FromIterator<(K, V)> for SplayTree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tree = SplayTree::new();
+        tree.extend(iter);
+        tree
+    }
+}
+
+impl<K: PartialOrd + Debug, V: Debug> Debug for SplayTree<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tree::binary_tree::BTree;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn check(tree: &SplayTree<u32, u32>) -> usize {
+        tree.check_rules()
+            .unwrap_or_else(|err| panic!("{}\n{:?}", err, tree))
+    }
+
+    #[test]
+    fn splay_test_against_btree() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut tree: SplayTree<u32, u32> = SplayTree::new();
+        let mut reference: BTree<u32, u32> = BTree::new();
+        for step in 0..5000 {
+            let key = rng.gen_range(0..300);
+            match rng.gen_range(0..3) {
+                0 => assert_eq!(tree.insert(key, step), reference.insert(key, step)),
+                1 => assert_eq!(tree.remove(&key), reference.remove(&key)),
+                _ => assert_eq!(tree.find(&key), reference.find(&key)),
+            }
+            if step % 100 == 0 {
+                check(&tree);
+            }
+            assert_eq!(tree.peek(&key), reference.find(&key));
+            assert_eq!(tree.smaller(&key), reference.smaller(&key));
+            assert_eq!(tree.larger(&key), reference.larger(&key));
+        }
+        check(&tree);
+        assert!(tree.iter().eq(reference.iter()));
+        assert_eq!(tree.smallest(), reference.smallest());
+        assert_eq!(tree.largest(), reference.largest());
+    }
+
+    #[test]
+    fn splay_test_hot_keys() {
+        // ascending inserts leave a chain, deep enough to overflow recursive code
+        let mut tree: SplayTree<u32, u32> = (0..200_000).map(|key| (key, key)).collect();
+        assert_eq!(check(&tree), 200_000);
+        assert_eq!(tree.find(&0), Some(&0));
+        // the walk down the chain folded it to about half its height
+        assert!(check(&tree) < 100_010);
+        *tree.find_mut(&7).expect("key not found") = 70;
+        assert_eq!(tree.root.as_ref().map(|root| root.key), Some(7));
+        assert_eq!(tree.peek(&7), Some(&70));
+        // peek leaves the shape alone
+        assert_eq!(tree.peek(&199_999), Some(&199_999));
+        assert_eq!(tree.root.as_ref().map(|root| root.key), Some(7));
+        assert_eq!(tree.remove(&7), Some(70));
+        assert!(!tree.contains(&7));
+        assert_eq!(tree.iter().count(), 199_999);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn splay_test_stats() {
+        let mut tree = SplayTree::new();
+        for key in 1..=3u32 {
+            tree.insert(key, ());
+        }
+        // every insert compares with the previous key at the root
+        assert_eq!(tree.stats().searches, 3);
+        assert_eq!(tree.stats().comparisons, 2);
+        // 1 is at the bottom of the chain 3 2 1
+        assert_eq!(tree.search_depth(&1), 3);
+        assert!(tree.contains(&1));
+        assert_eq!(tree.stats().max_search_depth, 3);
+        assert_eq!(tree.search_depth(&1), 1);
+    }
+}
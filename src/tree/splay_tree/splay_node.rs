@@ -0,0 +1,150 @@
+use super::SubNode;
+use crate::prelude::*;
+use crate::tree::rb_tree::Color;
+use crate::tree::TreeNode;
+
+pub struct SplayNode<K: PartialOrd, V> {
+    pub key: K,
+    pub value: V,
+    pub smaller: SubNode<K, V>,
+    pub larger: SubNode<K, V>,
+}
+
+impl<K: PartialOrd, V> SplayNode<K, V> {
+    pub fn new(key: K, value: V) -> SplayNode<K, V> {
+        SplayNode {
+            key,
+            value,
+            smaller: None,
+            larger: None,
+        }
+    }
+}
+
+/// Top-down splaying of the tree below root towards key, after Sleator and Tarjan.
+/// Walks down to key and brings it, or the last node on the way to it, up to the
+/// root. Pairs of steps in the same direction are rotated first, which roughly halves
+/// the depth of the path. The nodes passed on the way down are hung into a smaller and
+/// a larger tree at once, through the link at the tail of each, so the path is walked
+/// once. Returns the new root and the number of nodes visited.
+pub(crate) fn splay<K: PartialOrd, V>(
+    mut root: Box<SplayNode<K, V>>,
+    key: &K,
+) -> (Box<SplayNode<K, V>>, u64) {
+    // the nodes below and above key, each new one hangs off the tail of its side
+    let mut smaller_side: SubNode<K, V> = None;
+    let mut larger_side: SubNode<K, V> = None;
+    let mut smaller_tail = &mut smaller_side;
+    let mut larger_tail = &mut larger_side;
+    let mut depth = 1;
+    loop {
+        if *key < root.key {
+            let Some(mut smaller) = root.smaller.take() else {
+                break;
+            };
+            depth += 1;
+            if *key < smaller.key {
+                // zig-zig, rotate right before linking
+                root.smaller = smaller.larger.take();
+                smaller.larger = Some(root);
+                root = smaller;
+                let Some(next) = root.smaller.take() else {
+                    break;
+                };
+                depth += 1;
+                smaller = next;
+            }
+            *larger_tail = Some(root);
+            larger_tail = &mut larger_tail.as_mut().expect("unexpected empty link").smaller;
+            root = smaller;
+        } else if *key > root.key {
+            let Some(mut larger) = root.larger.take() else {
+                break;
+            };
+            depth += 1;
+            if *key > larger.key {
+                // zig-zig, rotate left before linking
+                root.larger = larger.smaller.take();
+                larger.smaller = Some(root);
+                root = larger;
+                let Some(next) = root.larger.take() else {
+                    break;
+                };
+                depth += 1;
+                larger = next;
+            }
+            *smaller_tail = Some(root);
+            smaller_tail = &mut smaller_tail.as_mut().expect("unexpected empty link").larger;
+            root = larger;
+        } else {
+            break;
+        }
+    }
+
+    // the subtrees of the new root go below the deepest nodes of the sides
+    *smaller_tail = root.smaller.take();
+    *larger_tail = root.larger.take();
+    root.smaller = smaller_side;
+    root.larger = larger_side;
+    (root, depth)
+}
+
+impl<K: PartialOrd, V> TreeNode<K, V> for SplayNode<K, V> {
+    fn key(&self) -> &K {
+        &self.key
+    }
+
+    fn value(&self) -> &V {
+        &self.value
+    }
+
+    fn smaller(&self) -> Option<&Self> {
+        self.smaller.as_deref()
+    }
+
+    fn larger(&self) -> Option<&Self> {
+        self.larger.as_deref()
+    }
+
+    fn color(&self) -> Option<Color> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tree::shape;
+
+    fn chain(keys: impl Iterator<Item = u32>) -> Box<SplayNode<u32, u32>> {
+        // every key is above the ones before, they hang off its smaller side
+        let mut root: SubNode<u32, u32> = None;
+        for key in keys {
+            let mut node = Box::new(SplayNode::new(key, key));
+            node.smaller = root;
+            root = Some(node);
+        }
+        root.expect("empty chain")
+    }
+
+    #[test]
+    fn splay_test_splay() {
+        let root = chain(1..=7);
+        assert_eq!(
+            shape::encode::<u32, u32, _>(Some(root.as_ref())),
+            "N7=7 N6=6 N5=5 N4=4 N3=3 N2=2 N1=1 . . . . . . . ."
+        );
+        let (root, depth) = splay(root, &1);
+        assert_eq!(depth, 7);
+        // the deepest key is on top and the path folded to about half its length
+        assert_eq!(
+            shape::encode::<u32, u32, _>(Some(root.as_ref())),
+            "N1=1 . N6=6 N4=4 N2=2 . N3=3 . . N5=5 . . N7=7 . ."
+        );
+        // a missing key brings up a neighbor
+        let (root, depth) = splay(root, &8);
+        assert_eq!(depth, 3);
+        assert_eq!(root.key, 7);
+        assert!(root.larger.is_none());
+    }
+}
@@ -0,0 +1,626 @@
+//! A red black tree in a fixed array, for targets without an allocator.
+//!
+//! The nodes live inline in the tree and link to each other by index, NIL marks a
+//! missing node. Slots given back by remove form a free list, slots that were never
+//! used are handed out from the end of the used part. Nothing here allocates, the
+//! tree can be built in a const or static.
+
+use crate::tree::rb_tree::Color::{Black, Red};
+use core::fmt::{Debug, Display, Formatter};
+use core::ops::Index;
+use static_node::{Slot, StaticNode, NIL};
+
+mod static_node;
+
+/// The error of StaticRBTree::insert when all N slots are in use, it hands the entry back
+#[derive(Debug, PartialEq)]
+pub struct CapacityExceeded<K, V> {
+    pub key: K,
+    pub value: V,
+}
+
+impl<K, V> Display for CapacityExceeded<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "capacity of the StaticRBTree exceeded")
+    }
+}
+
+impl<K: Debug, V: Debug> core::error::Error for CapacityExceeded<K, V> {}
+
+/// A red black tree with room for N entries, see the module docs for the layout.
+/// Insert and remove follow Cormen et al. with parent links, so neither recursion
+/// nor a stack is needed.
+pub struct StaticRBTree<K: PartialOrd, V, const N: usize> {
+    slots: [Slot<K, V>; N],
+    root: usize,
+    len: usize,
+    // the slots from here on were never used
+    unused: usize,
+    // the first slot of the free list
+    free: usize,
+}
+
+impl<K: PartialOrd, V, const N: usize> Default for StaticRBTree<K, V, N> {
+    fn default() -> Self {
+        StaticRBTree::new()
+    }
+}
+
+impl<K: PartialOrd, V, const N: usize> StaticRBTree<K, V, N> {
+    pub const fn new() -> StaticRBTree<K, V, N> {
+        StaticRBTree {
+            slots: [const { Slot::Free(NIL) }; N],
+            root: NIL,
+            len: 0,
+            unused: 0,
+            free: NIL,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// the number of entries the tree can hold, N
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    /// removes all entries
+    pub fn clear(&mut self) {
+        *self = StaticRBTree::new();
+    }
+
+    fn node(&self, idx: usize) -> &StaticNode<K, V> {
+        match &self.slots[idx] {
+            Slot::Used(node) => node,
+            Slot::Free(_) => panic!("unexpected free slot {}", idx),
+        }
+    }
+
+    fn node_mut(&mut self, idx: usize) -> &mut StaticNode<K, V> {
+        match &mut self.slots[idx] {
+            Slot::Used(node) => node,
+            Slot::Free(_) => panic!("unexpected free slot {}", idx),
+        }
+    }
+
+    /// the nil leaves are black
+    fn is_red(&self, idx: usize) -> bool {
+        idx != NIL && self.node(idx).color == Red
+    }
+
+    fn parent(&self, idx: usize) -> usize {
+        self.node(idx).parent
+    }
+
+    /// true if the node at idx is the larger child of its parent
+    fn is_larger(&self, idx: usize) -> bool {
+        self.node(self.parent(idx)).larger == idx
+    }
+
+    fn find_idx(&self, key: &K) -> Option<usize> {
+        let mut idx = self.root;
+        while idx != NIL {
+            let curr = self.node(idx);
+            if *key < curr.key {
+                idx = curr.smaller;
+            } else if *key > curr.key {
+                idx = curr.larger;
+            } else {
+                return Some(idx);
+            }
+        }
+        None
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.find_idx(key).is_some()
+    }
+
+    pub fn find(&self, key: &K) -> Option<&V> {
+        self.find_idx(key).map(|idx| &self.node(idx).value)
+    }
+
+    pub fn find_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.find_idx(key).map(|idx| &mut self.node_mut(idx).value)
+    }
+
+    fn entry(&self, idx: usize) -> (&K, &V) {
+        let node = self.node(idx);
+        (&node.key, &node.value)
+    }
+
+    /// the last node going down towards the larger side if larger is true
+    fn outermost(&self, mut idx: usize, larger: bool) -> usize {
+        while self.node(idx).child(larger) != NIL {
+            idx = self.node(idx).child(larger);
+        }
+        idx
+    }
+
+    pub fn smallest(&self) -> Option<(&K, &V)> {
+        (self.root != NIL).then(|| self.entry(self.outermost(self.root, false)))
+    }
+
+    pub fn largest(&self) -> Option<(&K, &V)> {
+        (self.root != NIL).then(|| self.entry(self.outermost(self.root, true)))
+    }
+
+    /// the entry with the largest key below key
+    pub fn smaller(&self, key: &K) -> Option<(&K, &V)> {
+        let mut candidate = NIL;
+        let mut idx = self.root;
+        while idx != NIL {
+            let curr = self.node(idx);
+            if curr.key < *key {
+                candidate = idx;
+                idx = curr.larger;
+            } else {
+                idx = curr.smaller;
+            }
+        }
+        (candidate != NIL).then(|| self.entry(candidate))
+    }
+
+    /// the entry with the smallest key above key
+    pub fn larger(&self, key: &K) -> Option<(&K, &V)> {
+        let mut candidate = NIL;
+        let mut idx = self.root;
+        while idx != NIL {
+            let curr = self.node(idx);
+            if curr.key > *key {
+                candidate = idx;
+                idx = curr.smaller;
+            } else {
+                idx = curr.larger;
+            }
+        }
+        (candidate != NIL).then(|| self.entry(candidate))
+    }
+
+    /// the node after idx in key order, NIL after the last one
+    fn successor(&self, idx: usize) -> usize {
+        let larger = self.node(idx).larger;
+        if larger != NIL {
+            return self.outermost(larger, false);
+        }
+        // up to the first ancestor reached from its smaller child
+        let mut idx = idx;
+        while self.node(idx).parent != NIL && self.is_larger(idx) {
+            idx = self.node(idx).parent;
+        }
+        self.node(idx).parent
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V, N> {
+        Iter {
+            tree: self,
+            next: if self.root == NIL {
+                NIL
+            } else {
+                self.outermost(self.root, false)
+            },
+        }
+    }
+
+    /// a slot for a new node, from the free list first
+    fn take_slot(&mut self) -> Option<usize> {
+        if self.free != NIL {
+            let idx = self.free;
+            self.free = match self.slots[idx] {
+                Slot::Free(next) => next,
+                Slot::Used(_) => panic!("unexpected used slot {} in the free list", idx),
+            };
+            Some(idx)
+        } else if self.unused < N {
+            self.unused += 1;
+            Some(self.unused - 1)
+        } else {
+            None
+        }
+    }
+
+    /// puts the slot at idx on the free list and returns its node
+    fn free_slot(&mut self, idx: usize) -> StaticNode<K, V> {
+        let slot = core::mem::replace(&mut self.slots[idx], Slot::Free(self.free));
+        self.free = idx;
+        match slot {
+            Slot::Used(node) => node,
+            Slot::Free(_) => panic!("unexpected free slot {}", idx),
+        }
+    }
+
+    /// links new in place of the child old of parent, a NIL parent stands for the root
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        if parent == NIL {
+            self.root = new;
+        } else if self.node(parent).smaller == old {
+            self.node_mut(parent).smaller = new;
+        } else {
+            self.node_mut(parent).larger = new;
+        }
+    }
+
+    /// moves the subtree at new into the place of the node at old
+    fn transplant(&mut self, old: usize, new: usize) {
+        let parent = self.parent(old);
+        self.replace_child(parent, old, new);
+        if new != NIL {
+            self.node_mut(new).parent = parent;
+        }
+    }
+
+    /// rotates the node at idx above its parent, a left rotation if it is the larger child
+    fn rotate_up(&mut self, idx: usize) {
+        let parent = self.parent(idx);
+        let larger = self.is_larger(idx);
+        // the inner subtree changes sides
+        let inner = self.node(idx).child(!larger);
+        *self.node_mut(parent).child_mut(larger) = inner;
+        if inner != NIL {
+            self.node_mut(inner).parent = parent;
+        }
+        self.transplant(parent, idx);
+        *self.node_mut(idx).child_mut(!larger) = parent;
+        self.node_mut(parent).parent = idx;
+    }
+
+    /// Inserts or replaces the value of key. Replacing works on a full tree too, a new
+    /// key that does not fit is returned in the error.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, CapacityExceeded<K, V>> {
+        let mut parent = NIL;
+        let mut larger = false;
+        let mut idx = self.root;
+        while idx != NIL {
+            let curr = self.node_mut(idx);
+            if key < curr.key {
+                larger = false;
+            } else if key > curr.key {
+                larger = true;
+            } else {
+                return Ok(Some(core::mem::replace(&mut curr.value, value)));
+            }
+            parent = idx;
+            idx = curr.child(larger);
+        }
+        let Some(idx) = self.take_slot() else {
+            return Err(CapacityExceeded { key, value });
+        };
+        self.slots[idx] = Slot::Used(StaticNode::new(key, value, parent));
+        if parent == NIL {
+            self.root = idx;
+        } else {
+            *self.node_mut(parent).child_mut(larger) = idx;
+        }
+        self.len += 1;
+        self.fix_insert(idx);
+        Ok(None)
+    }
+
+    /// restores the rules after the red node at idx was inserted
+    fn fix_insert(&mut self, mut idx: usize) {
+        while self.is_red(self.parent(idx)) {
+            // a red parent is not the root, the grandparent exists
+            let parent = self.parent(idx);
+            let grandparent = self.parent(parent);
+            let uncle = self.node(grandparent).child(!self.is_larger(parent));
+            if self.is_red(uncle) {
+                // pass the red up
+                self.node_mut(parent).color = Black;
+                self.node_mut(uncle).color = Black;
+                self.node_mut(grandparent).color = Red;
+                idx = grandparent;
+                continue;
+            }
+            let mut parent = parent;
+            if self.is_larger(idx) != self.is_larger(parent) {
+                // an inner grandchild is rotated to the outside first
+                self.rotate_up(idx);
+                parent = idx;
+            }
+            self.rotate_up(parent);
+            self.node_mut(parent).color = Black;
+            self.node_mut(grandparent).color = Red;
+            break;
+        }
+        let root = self.root;
+        self.node_mut(root).color = Black;
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let idx = self.find_idx(key)?;
+        let StaticNode {
+            smaller,
+            larger,
+            color,
+            ..
+        } = *self.node(idx);
+        // the node that takes the place of the one removed from the tree,
+        // and its parent as it may be NIL
+        let (replacement, parent, removed_color) = if smaller == NIL || larger == NIL {
+            let child = if smaller == NIL { larger } else { smaller };
+            let parent = self.parent(idx);
+            self.transplant(idx, child);
+            (child, parent, color)
+        } else {
+            // the successor moves into the place of the node
+            let next = self.outermost(larger, false);
+            let next_color = self.node(next).color;
+            let child = self.node(next).larger;
+            let parent = if self.parent(next) == idx {
+                next
+            } else {
+                let parent = self.parent(next);
+                self.transplant(next, child);
+                self.node_mut(next).larger = larger;
+                self.node_mut(larger).parent = next;
+                parent
+            };
+            self.transplant(idx, next);
+            self.node_mut(next).smaller = smaller;
+            self.node_mut(smaller).parent = next;
+            self.node_mut(next).color = color;
+            (child, parent, next_color)
+        };
+        if removed_color == Black {
+            self.fix_remove(replacement, parent);
+        }
+        self.len -= 1;
+        Some(self.free_slot(idx).value)
+    }
+
+    /// Restores the rules after a black node was removed above idx, whose path now
+    /// lacks a black node. idx may be NIL, so its parent is passed along.
+    fn fix_remove(&mut self, mut idx: usize, mut parent: usize) {
+        while idx != self.root && !self.is_red(idx) {
+            let larger = self.node(parent).larger == idx;
+            // the sibling exists, its side of the parent has the larger black height
+            let mut sibling = self.node(parent).child(!larger);
+            if self.is_red(sibling) {
+                self.node_mut(sibling).color = Black;
+                self.node_mut(parent).color = Red;
+                self.rotate_up(sibling);
+                sibling = self.node(parent).child(!larger);
+            }
+            let near = self.node(sibling).child(larger);
+            let far = self.node(sibling).child(!larger);
+            if !self.is_red(near) && !self.is_red(far) {
+                // the sibling gives up a black node, the parent lacks one now
+                self.node_mut(sibling).color = Red;
+                idx = parent;
+                parent = self.node(idx).parent;
+                continue;
+            }
+            if !self.is_red(far) {
+                self.node_mut(near).color = Black;
+                self.node_mut(sibling).color = Red;
+                self.rotate_up(near);
+                sibling = near;
+            }
+            self.node_mut(sibling).color = self.node(parent).color;
+            self.node_mut(parent).color = Black;
+            let far = self.node(sibling).child(!larger);
+            self.node_mut(far).color = Black;
+            self.rotate_up(sibling);
+            idx = self.root;
+        }
+        if idx != NIL {
+            self.node_mut(idx).color = Black;
+        }
+    }
+
+    /// Checks the order of the keys, the links, the red black rules and the number of
+    /// entries, returns the black height. The messages are static, nothing allocates.
+    pub fn check_rules(&self) -> Result<usize, &'static str> {
+        if self.root == NIL {
+            return if self.len == 0 {
+                Ok(0)
+            } else {
+                Err("StaticRBTree violation: len of an empty tree is not 0")
+            };
+        }
+        if self.is_red(self.root) || self.parent(self.root) != NIL {
+            return Err("RB violation: root is red or has a parent");
+        }
+        let mut black_height = None;
+        let mut count = 0;
+        let mut prev: Option<usize> = None;
+        let mut idx = self.outermost(self.root, false);
+        while idx != NIL {
+            let node = self.node(idx);
+            count += 1;
+            if count > self.len {
+                return Err("StaticRBTree violation: more nodes than len");
+            }
+            if prev.is_some_and(|prev| self.node(prev).key >= node.key) {
+                return Err("RB violation: keys out of order");
+            }
+            for child in [node.smaller, node.larger] {
+                if child != NIL && self.parent(child) != idx {
+                    return Err("StaticRBTree violation: parent link does not match");
+                }
+            }
+            if node.color == Red && (self.is_red(node.smaller) || self.is_red(node.larger)) {
+                return Err("RB violation: two successive red nodes");
+            }
+            if node.smaller == NIL || node.larger == NIL {
+                // a path ends in a nil leaf here
+                let mut blacks = 0;
+                let mut curr = idx;
+                while curr != NIL {
+                    if !self.is_red(curr) {
+                        blacks += 1;
+                    }
+                    curr = self.parent(curr);
+                }
+                if *black_height.get_or_insert(blacks) != blacks {
+                    return Err("RB violation: mismatching black counts");
+                }
+            }
+            prev = Some(idx);
+            idx = self.successor(idx);
+        }
+        if count != self.len {
+            return Err("StaticRBTree violation: fewer nodes than len");
+        }
+        Ok(black_height.unwrap_or(0))
+    }
+}
+
+/// ascending iterator over the entries of a StaticRBTree
+pub struct Iter<'a, K: PartialOrd, V, const N: usize> {
+    tree: &'a StaticRBTree<K, V, N>,
+    // the index of the next node, NIL when done
+    next: usize,
+}
+
+impl<'a, K: PartialOrd, V, const N: usize> Iterator for Iter<'a, K, V, N> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == NIL {
+            return None;
+        }
+        let idx = self.next;
+        self.next = self.tree.successor(idx);
+        Some(self.tree.entry(idx))
+    }
+}
+
+impl<'a, K: PartialOrd, V, const N: usize> IntoIterator for &'a StaticRBTree<K, V, N> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V, N>;
+
+    fn into_iter(self) -> Iter<'a, K, V, N> {
+        self.iter()
+    }
+}
+
+impl<K: PartialOrd, V, const N: usize> Index<&K> for StaticRBTree<K, V, N> {
+    type Output = V;
+
+    fn index(&self, key: &K) -> &V {
+        self.find(key).expect("key not found in StaticRBTree")
+    }
+}
+
+impl<K: PartialOrd + Debug, V: Debug, const N: usize> Debug for StaticRBTree<K, V, N> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tree::test_map::{self, TestMap};
+
+    fn check<const N: usize>(tree: &StaticRBTree<u32, u32, N>) -> usize {
+        tree.check_rules()
+            .unwrap_or_else(|err| panic!("{}\n{:?}", err, tree))
+    }
+
+    #[test]
+    fn srb_test_capacity() {
+        static EMPTY: StaticRBTree<u32, u32, 4> = StaticRBTree::new();
+        assert!(EMPTY.is_empty());
+        let mut tree: StaticRBTree<u32, u32, 4> = StaticRBTree::new();
+        for key in 0..4 {
+            assert_eq!(tree.insert(key, key), Ok(None));
+        }
+        assert_eq!(
+            tree.insert(4, 40),
+            Err(CapacityExceeded { key: 4, value: 40 })
+        );
+        // replacing needs no slot
+        assert_eq!(tree.insert(2, 20), Ok(Some(2)));
+        assert_eq!(tree[&2], 20);
+        assert_eq!(tree.len(), tree.capacity());
+        check(&tree);
+        assert_eq!(tree.remove(&0), Some(0));
+        assert_eq!(tree.insert(4, 40), Ok(None));
+        assert!(tree.iter().map(|(key, _)| *key).eq(1..5));
+        tree.clear();
+        assert!(tree.is_empty());
+        assert_eq!(tree.smallest(), None);
+        assert_eq!(tree.check_rules(), Ok(0));
+    }
+
+    #[test]
+    fn srb_test_slot_reuse() {
+        let mut tree: StaticRBTree<u32, u32, 64> = StaticRBTree::new();
+        // the tree never holds more than 64 keys, so the freed slots are reused
+        for round in 0..50 {
+            for key in 0..64 {
+                assert_eq!(tree.insert(key, round), Ok(None));
+            }
+            check(&tree);
+            assert!(tree.insert(64, round).is_err());
+            for key in (0..64).rev() {
+                assert_eq!(tree.remove(&key), Some(round));
+            }
+            assert!(tree.is_empty());
+        }
+    }
+
+    /// the differential test, RBTree and StaticRBTree must agree on every result
+    impl TestMap for StaticRBTree<u32, u32, 200> {
+        fn insert(&mut self, key: u32, value: u32) -> Option<u32> {
+            StaticRBTree::insert(self, key, value).expect("unexpected full tree")
+        }
+
+        fn try_insert(&mut self, key: u32, value: u32) -> Result<Option<u32>, (u32, u32)> {
+            StaticRBTree::insert(self, key, value).map_err(|err| (err.key, err.value))
+        }
+
+        fn remove(&mut self, key: &u32) -> Option<u32> {
+            StaticRBTree::remove(self, key)
+        }
+
+        fn find(&self, key: &u32) -> Option<&u32> {
+            StaticRBTree::find(self, key)
+        }
+
+        fn find_mut(&mut self, key: &u32) -> Option<&mut u32> {
+            StaticRBTree::find_mut(self, key)
+        }
+
+        fn smaller(&self, key: &u32) -> Option<(&u32, &u32)> {
+            StaticRBTree::smaller(self, key)
+        }
+
+        fn larger(&self, key: &u32) -> Option<(&u32, &u32)> {
+            StaticRBTree::larger(self, key)
+        }
+
+        fn smallest(&self) -> Option<(&u32, &u32)> {
+            StaticRBTree::smallest(self)
+        }
+
+        fn largest(&self) -> Option<(&u32, &u32)> {
+            StaticRBTree::largest(self)
+        }
+
+        fn entries(&self) -> Vec<(u32, u32)> {
+            self.iter().map(|(key, value)| (*key, *value)).collect()
+        }
+
+        fn check(&self) {
+            // 2 log2(200 + 1)
+            assert!(check(self) <= 9);
+        }
+    }
+
+    #[test]
+    fn srb_test_against_rbtree() {
+        let (tree, rb_tree, refused) =
+            test_map::check_against_rbtree::<StaticRBTree<u32, u32, 200>>(17, 20000, 400, 0.6);
+        assert!(refused > 0);
+        assert_eq!(tree.len(), rb_tree.iter().count());
+    }
+}
@@ -0,0 +1,51 @@
+use crate::tree::rb_tree::Color;
+
+/// the index of a missing node, it stands for the black nil leaves
+pub(crate) const NIL: usize = usize::MAX;
+
+pub struct StaticNode<K: PartialOrd, V> {
+    pub key: K,
+    pub value: V,
+    pub color: Color,
+    /// the indices of the linked nodes, NIL if there is none
+    pub parent: usize,
+    pub smaller: usize,
+    pub larger: usize,
+}
+
+impl<K: PartialOrd, V> StaticNode<K, V> {
+    /// a red node below parent
+    pub fn new(key: K, value: V, parent: usize) -> StaticNode<K, V> {
+        StaticNode {
+            key,
+            value,
+            color: Color::Red,
+            parent,
+            smaller: NIL,
+            larger: NIL,
+        }
+    }
+
+    /// the child on the larger side if larger is true
+    pub fn child(&self, larger: bool) -> usize {
+        if larger {
+            self.larger
+        } else {
+            self.smaller
+        }
+    }
+
+    pub fn child_mut(&mut self, larger: bool) -> &mut usize {
+        if larger {
+            &mut self.larger
+        } else {
+            &mut self.smaller
+        }
+    }
+}
+
+pub(crate) enum Slot<K: PartialOrd, V> {
+    Used(StaticNode<K, V>),
+    /// a slot given back by remove, holds the index of the next free one or NIL
+    Free(usize),
+}
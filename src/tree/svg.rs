@@ -0,0 +1,386 @@
+use crate::prelude::*;
+use crate::tree::rb_tree::Color;
+use crate::tree::TreeNode;
+use core::fmt::Debug;
+
+const RADIUS: f64 = 16.0;
+// distance between neighbouring nodes and between levels
+const X_UNIT: f64 = 36.0;
+const Y_UNIT: f64 = 56.0;
+const MARGIN: f64 = 20.0;
+const TITLE_HEIGHT: f64 = 24.0;
+
+/// Options for the SVG export of a tree
+#[derive(Clone, Debug, PartialEq)]
+pub struct SvgOptions<K> {
+    /// outline the nodes with these keys
+    pub highlight: Vec<K>,
+    /// a line of text above the tree
+    pub title: Option<String>,
+}
+
+impl<K> Default for SvgOptions<K> {
+    fn default() -> Self {
+        SvgOptions {
+            highlight: Vec::new(),
+            title: None,
+        }
+    }
+}
+
+impl<K> SvgOptions<K> {
+    pub fn new() -> SvgOptions<K> {
+        SvgOptions::default()
+    }
+
+    pub fn highlight(mut self, keys: impl IntoIterator<Item = K>) -> Self {
+        self.highlight.extend(keys);
+        self
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+}
+
+/// A node placed by layout()
+pub(crate) struct Placed<'a, N> {
+    pub node: &'a N,
+    pub parent: Option<usize>,
+    pub depth: usize,
+    /// horizontal position, neighbouring nodes are at least 1.0 apart
+    pub x: f64,
+}
+
+/// Left and right outline of a subtree, one entry per level.
+/// The levels are stored deepest first so a parent level is pushed at the end,
+/// all entries are relative to the subtree root after adding shift.
+struct Contour {
+    levels: Vec<(f64, f64)>,
+    shift: f64,
+}
+
+impl Contour {
+    fn height(&self) -> usize {
+        self.levels.len()
+    }
+
+    fn left(&self, level: usize) -> f64 {
+        self.levels[self.levels.len() - 1 - level].0 + self.shift
+    }
+
+    fn right(&self, level: usize) -> f64 {
+        self.levels[self.levels.len() - 1 - level].1 + self.shift
+    }
+
+    fn set(&mut self, level: usize, left: f64, right: f64) {
+        let index = self.levels.len() - 1 - level;
+        self.levels[index] = (left - self.shift, right - self.shift);
+    }
+
+    /// add the parent level on top, offset is this subtree root relative to the parent
+    fn push_parent(mut self, offset: f64) -> Contour {
+        self.shift += offset;
+        let parent = -self.shift;
+        self.levels.push((parent, parent));
+        self
+    }
+}
+
+/// Tidy tree layout in the style of Reingold and Tilford:
+/// subtrees are pushed apart until their contours are at least 1.0 apart on every level
+/// and a parent is centered above its children. A single child is placed half a unit
+/// to its side, so smaller and larger children can be told apart.
+/// Works without recursion and in O(n) as merging contours only walks the lower subtree.
+/// The nodes are returned in pre-order, the smallest x is 0.0.
+pub(crate) fn layout<K, V, N: TreeNode<K, V>>(root: Option<&N>) -> Vec<Placed<'_, N>> {
+    let mut placed: Vec<Placed<N>> = Vec::new();
+    // index of the smaller and larger child for every placed node
+    let mut children: Vec<(Option<usize>, Option<usize>)> = Vec::new();
+    let mut stack: Vec<(&N, Option<usize>, bool)> =
+        root.map(|root| (root, None, true)).into_iter().collect();
+    while let Some((node, parent, smaller)) = stack.pop() {
+        let index = placed.len();
+        if let Some(parent) = parent {
+            if smaller {
+                children[parent].0 = Some(index);
+            } else {
+                children[parent].1 = Some(index);
+            }
+        }
+        placed.push(Placed {
+            node,
+            parent,
+            depth: parent.map_or(0, |parent| placed[parent].depth + 1),
+            x: 0.0,
+        });
+        children.push((None, None));
+        if let Some(larger) = node.larger() {
+            stack.push((larger, Some(index), false));
+        }
+        if let Some(smaller) = node.smaller() {
+            stack.push((smaller, Some(index), true));
+        }
+    }
+
+    // children come after their parent in pre-order, so walking backwards
+    // finds the contours of both children before they are merged into the parent,
+    // x holds the offset to the parent for now
+    let mut contours: Vec<Option<Contour>> = (0..placed.len()).map(|_| None).collect();
+    for index in (0..placed.len()).rev() {
+        let (smaller, larger) = children[index];
+        let smaller = smaller.map(|child| {
+            (
+                child,
+                contours[child].take().expect("child contour missing"),
+            )
+        });
+        let larger = larger.map(|child| {
+            (
+                child,
+                contours[child].take().expect("child contour missing"),
+            )
+        });
+        let contour = match (smaller, larger) {
+            (None, None) => Contour {
+                levels: vec![(0.0, 0.0)],
+                shift: 0.0,
+            },
+            (Some((child, contour)), None) => {
+                placed[child].x = -0.5;
+                contour.push_parent(-0.5)
+            }
+            (None, Some((child, contour))) => {
+                placed[child].x = 0.5;
+                contour.push_parent(0.5)
+            }
+            (Some((small, mut left)), Some((large, mut right))) => {
+                let common = left.height().min(right.height());
+                let sep = (0..common)
+                    .map(|level| left.right(level) - right.left(level) + 1.0)
+                    .fold(1.0, f64::max);
+                placed[small].x = -sep / 2.0;
+                placed[large].x = sep / 2.0;
+                left.shift -= sep / 2.0;
+                right.shift += sep / 2.0;
+                // keep the deeper contour and take the outer sides of the common levels
+                let merged = if left.height() >= right.height() {
+                    for level in 0..common {
+                        let right_side = right.right(level);
+                        left.set(level, left.left(level), right_side);
+                    }
+                    left
+                } else {
+                    for level in 0..common {
+                        let left_side = left.left(level);
+                        right.set(level, left_side, right.right(level));
+                    }
+                    right
+                };
+                merged.push_parent(0.0)
+            }
+        };
+        contours[index] = Some(contour);
+    }
+
+    for index in 1..placed.len() {
+        let parent = placed[index].parent.expect("only the root has no parent");
+        placed[index].x += placed[parent].x;
+    }
+    let min = placed
+        .iter()
+        .map(|node| node.x)
+        .fold(f64::INFINITY, f64::min);
+    for node in &mut placed {
+        node.x -= min;
+    }
+    placed
+}
+
+/// Renders the tree as a standalone SVG document with the keys as node labels.
+pub(crate) fn to_svg<K, V, N>(root: Option<&N>, options: &SvgOptions<K>) -> String
+where
+    K: PartialEq + Debug,
+    N: TreeNode<K, V>,
+{
+    let placed = layout(root);
+    let top = MARGIN
+        + if options.title.is_some() {
+            TITLE_HEIGHT
+        } else {
+            0.0
+        };
+    let width = placed.iter().map(|node| node.x).fold(0.0, f64::max) * X_UNIT + 2.0 * MARGIN;
+    let height =
+        placed.iter().map(|node| node.depth).max().unwrap_or(0) as f64 * Y_UNIT + top + MARGIN;
+    let pos = |node: &Placed<N>| (MARGIN + node.x * X_UNIT, top + node.depth as f64 * Y_UNIT);
+
+    let mut buffer = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n",
+        w = coord(width),
+        h = coord(height)
+    );
+    if let Some(title) = &options.title {
+        buffer.push_str(&format!(
+            "<text x=\"{}\" y=\"{}\" font-family=\"sans-serif\" font-size=\"14\">{}</text>\n",
+            coord(MARGIN / 2.0),
+            coord(MARGIN),
+            escape(title)
+        ));
+    }
+    buffer.push_str("<g stroke=\"black\">\n");
+    for node in &placed {
+        if let Some(parent) = node.parent {
+            let (x1, y1) = pos(&placed[parent]);
+            let (x2, y2) = pos(node);
+            buffer.push_str(&format!(
+                "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"/>\n",
+                coord(x1),
+                coord(y1),
+                coord(x2),
+                coord(y2)
+            ));
+        }
+    }
+    buffer.push_str("</g>\n");
+
+    buffer.push_str(
+        "<g font-family=\"monospace\" font-size=\"12\" text-anchor=\"middle\" dominant-baseline=\"central\">\n",
+    );
+    for node in &placed {
+        let (x, y) = pos(node);
+        let (fill, text) = match node.node.color() {
+            Some(Color::Red) => ("#d32f2f", "white"),
+            Some(Color::Black) => ("black", "white"),
+            None => ("white", "black"),
+        };
+        let stroke = if options.highlight.contains(node.node.key()) {
+            "gold\" stroke-width=\"3"
+        } else {
+            "black"
+        };
+        buffer.push_str(&format!(
+            "<circle cx=\"{x}\" cy=\"{y}\" r=\"{}\" fill=\"{}\" stroke=\"{}\"/>\
+             <text x=\"{x}\" y=\"{y}\" fill=\"{}\">{}</text>\n",
+            coord(RADIUS),
+            fill,
+            stroke,
+            text,
+            escape(&format!("{:?}", node.node.key())),
+            x = coord(x),
+            y = coord(y)
+        ));
+    }
+    buffer.push_str("</g>\n</svg>\n");
+    buffer
+}
+
+/// round to one decimal, whole numbers are printed without decimals
+fn coord(value: f64) -> String {
+    // half away from zero like f64::round, which core does not have
+    let scaled = value * 10.0;
+    let rounded = if scaled < 0.0 {
+        scaled - 0.5
+    } else {
+        scaled + 0.5
+    } as i64;
+    format!("{}", rounded as f64 / 10.0)
+}
+
+/// escape text for XML content
+fn escape(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            _ => res.push(ch),
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tree::binary_tree::BTree;
+    use crate::tree::rb_tree::RBTree;
+    use rand::Rng;
+
+    #[test]
+    fn svg_test_golden() {
+        let tree: RBTree<u32, ()> = (1..=7).map(|key| (key, ())).collect();
+        assert_eq!(tree.to_svg(), include_str!("golden/rb_1_to_7.svg"));
+
+        let tree = BTree::from([
+            (4u32, ()),
+            (2, ()),
+            (1, ()),
+            (3, ()),
+            (6, ()),
+            (5, ()),
+            (7, ()),
+            (8, ()),
+        ]);
+        assert_eq!(tree.to_svg(), include_str!("golden/bt_balanced.svg"));
+
+        let tree = BTree::from([(3u32, ()), (1, ()), (2, ())]);
+        assert_eq!(tree.to_svg(), include_str!("golden/bt_zigzag.svg"));
+
+        assert_eq!(
+            BTree::<u32, ()>::new().to_svg(),
+            include_str!("golden/empty.svg")
+        );
+
+        let tree = BTree::from([("<&>", ())]);
+        assert!(tree.to_svg().contains(">&quot;&lt;&amp;&gt;&quot;</text>"));
+    }
+
+    /// nodes on one level are ordered by key and at least one unit apart
+    fn check_layout<N: crate::tree::TreeNode<u32, ()>>(root: Option<&N>, count: usize) {
+        let placed = super::layout(root);
+        assert_eq!(placed.len(), count);
+        let mut levels: Vec<Vec<(f64, u32)>> = Vec::new();
+        for node in &placed {
+            if levels.len() <= node.depth {
+                levels.resize(node.depth + 1, Vec::new());
+            }
+            levels[node.depth].push((node.x, *node.node.key()));
+        }
+        for level in &mut levels {
+            level.sort_by(|a, b| a.0.total_cmp(&b.0));
+            for pair in level.windows(2) {
+                assert!(pair[1].0 - pair[0].0 >= 1.0 - 1e-9);
+                assert!(pair[0].1 < pair[1].1);
+            }
+        }
+        for node in &placed[1..] {
+            let parent = &placed[node.parent.expect("parent missing")];
+            assert_eq!(node.x < parent.x, node.node.key() < parent.node.key());
+        }
+    }
+
+    #[test]
+    fn svg_test_large() {
+        let mut rng = rand::thread_rng();
+        let mut tree = RBTree::new();
+        let mut btree = BTree::new();
+        for _ in 0..5000 {
+            let key = rng.gen_range(0..100_000u32);
+            tree.insert(key, ());
+            btree.insert(key, ());
+        }
+        let count = tree.iter().count();
+        check_layout(tree.root.as_deref(), count);
+        check_layout(btree.root.as_deref(), count);
+        assert_eq!(tree.to_svg().matches("<circle").count(), count);
+
+        let mut chain = BTree::new();
+        for key in 0..2000u32 {
+            chain.insert(key, ());
+        }
+        check_layout(chain.root.as_deref(), 2000);
+    }
+}
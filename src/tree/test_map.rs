@@ -0,0 +1,176 @@
+//! Checks shared by the tests of the trees, over the operations they have in common.
+
+use crate::prelude::*;
+use crate::tree::rb_tree::RBTree;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// The map operations the shared checks need, implemented by the test module of
+/// every tree that uses them
+pub(crate) trait TestMap: Default {
+    fn insert(&mut self, key: u32, value: u32) -> Option<u32>;
+    fn remove(&mut self, key: &u32) -> Option<u32>;
+    fn find(&self, key: &u32) -> Option<&u32>;
+    fn find_mut(&mut self, key: &u32) -> Option<&mut u32>;
+    fn smaller(&self, key: &u32) -> Option<(&u32, &u32)>;
+    fn larger(&self, key: &u32) -> Option<(&u32, &u32)>;
+    fn smallest(&self) -> Option<(&u32, &u32)>;
+    fn largest(&self) -> Option<(&u32, &u32)>;
+    /// all entries in ascending order
+    fn entries(&self) -> Vec<(u32, u32)>;
+    /// panics if the tree breaks its rules
+    fn check(&self);
+
+    /// insert for maps that can be full, Err hands back the entry that did not fit
+    fn try_insert(&mut self, key: u32, value: u32) -> Result<Option<u32>, (u32, u32)> {
+        Ok(self.insert(key, value))
+    }
+}
+
+/// Implements TestMap for a tree type whose methods have the same names and signatures,
+/// check is a function that panics if the tree breaks its rules.
+macro_rules! test_map_impl {
+    ($tree:ty, $check:path) => {
+        impl $crate::tree::test_map::TestMap for $tree {
+            fn insert(&mut self, key: u32, value: u32) -> Option<u32> {
+                <$tree>::insert(self, key, value)
+            }
+
+            fn remove(&mut self, key: &u32) -> Option<u32> {
+                <$tree>::remove(self, key)
+            }
+
+            fn find(&self, key: &u32) -> Option<&u32> {
+                <$tree>::find(self, key)
+            }
+
+            fn find_mut(&mut self, key: &u32) -> Option<&mut u32> {
+                <$tree>::find_mut(self, key)
+            }
+
+            fn smaller(&self, key: &u32) -> Option<(&u32, &u32)> {
+                <$tree>::smaller(self, key)
+            }
+
+            fn larger(&self, key: &u32) -> Option<(&u32, &u32)> {
+                <$tree>::larger(self, key)
+            }
+
+            fn smallest(&self) -> Option<(&u32, &u32)> {
+                <$tree>::smallest(self)
+            }
+
+            fn largest(&self) -> Option<(&u32, &u32)> {
+                <$tree>::largest(self)
+            }
+
+            fn entries(&self) -> Vec<(u32, u32)> {
+                self.iter().map(|(key, value)| (*key, *value)).collect()
+            }
+
+            fn check(&self) {
+                $check(self);
+            }
+        }
+    };
+}
+pub(crate) use test_map_impl;
+
+/// Runs steps random inserts, with a chance of insert_share, and removes of keys below
+/// key_range on a new map and on an RBTree. Every result, the neighbors of every key and
+/// the final entries must agree, map.check() runs every 50 steps. Returns both maps for
+/// the checks of the tree under test, and the number of inserts try_insert refused.
+pub(crate) fn check_against_rbtree<T: TestMap>(
+    seed: u64,
+    steps: u32,
+    key_range: u32,
+    insert_share: f64,
+) -> (T, RBTree<u32, u32>, usize) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut map = T::default();
+    let mut reference: RBTree<u32, u32> = RBTree::new();
+    let mut refused = 0;
+    for step in 0..steps {
+        let key = rng.gen_range(0..key_range);
+        if rng.gen_bool(insert_share) {
+            match map.try_insert(key, step) {
+                Ok(res) => assert_eq!(res, reference.insert(key, step)),
+                Err(entry) => {
+                    // only a new key may be refused, when the map is full
+                    assert_eq!(entry, (key, step));
+                    assert!(!reference.contains(&key));
+                    assert_eq!(map.entries().len(), reference.iter().count());
+                    refused += 1;
+                }
+            }
+        } else {
+            assert_eq!(map.remove(&key), reference.remove(&key));
+        }
+        if step % 50 == 0 {
+            map.check();
+        }
+        if let Some(value) = map.find_mut(&key) {
+            *value += 1;
+            *reference.find_mut(&key).expect("missing key in RBTree") += 1;
+        }
+        assert_eq!(map.find(&key), reference.find(&key));
+        assert_eq!(map.smaller(&key), reference.smaller(&key));
+        assert_eq!(map.larger(&key), reference.larger(&key));
+    }
+    map.check();
+    let entries: Vec<(u32, u32)> = reference
+        .iter()
+        .map(|(key, value)| (*key, *value))
+        .collect();
+    assert_eq!(map.entries(), entries);
+    assert_eq!(map.smallest(), reference.smallest());
+    assert_eq!(map.largest(), reference.largest());
+    (map, reference, refused)
+}
+
+/// A clone shares all nodes with the original. Changes to the clone copy only the
+/// nodes on the paths they touch, a find_mut or remove miss copies nothing, and the
+/// original keeps its entries. node_ptrs lists the addresses of the nodes of a tree.
+pub(crate) fn check_clone<T: TestMap + Clone>(node_ptrs: impl Fn(&T) -> Vec<usize>) {
+    let mut rng = StdRng::seed_from_u64(3);
+    let mut tree = T::default();
+    for _ in 1..=1000 {
+        let key = rng.gen_range(1..=1000u32);
+        tree.insert(key, key);
+    }
+    let smallest = *tree.smallest().expect("empty tree").0;
+    let largest = *tree.largest().expect("empty tree").0;
+
+    let mut clone = tree.clone();
+    let ptrs = node_ptrs(&tree);
+    assert_eq!(node_ptrs(&clone), ptrs);
+
+    assert_eq!(clone.find_mut(&2000), None);
+    assert_eq!(node_ptrs(&clone), ptrs);
+    assert_eq!(clone.remove(&2000), None);
+    assert_eq!(node_ptrs(&clone), ptrs);
+
+    assert_eq!(clone.insert(2000, 2000), None);
+    assert_eq!(clone.remove(&smallest), Some(smallest));
+    *clone.find_mut(&largest).expect("missing key") += 1;
+
+    // the original is unaffected by the changes to the clone
+    assert_eq!(tree.find(&2000), None);
+    assert_eq!(tree.find(&smallest), Some(&smallest));
+    assert_eq!(tree.find(&largest), Some(&largest));
+    assert_eq!(clone.find(&largest), Some(&(largest + 1)));
+    tree.check();
+    clone.check();
+
+    // only the nodes on the touched paths have been copied
+    let copied = node_ptrs(&clone)
+        .iter()
+        .filter(|ptr| !ptrs.contains(ptr))
+        .count();
+    assert!(copied > 0);
+    assert!(
+        copied < ptrs.len() / 4,
+        "copied {} of {} nodes",
+        copied,
+        ptrs.len()
+    );
+}
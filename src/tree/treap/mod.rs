@@ -0,0 +1,415 @@
+use crate::tree::metrics::{self, ShapeReport};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::Ordering;
+use std::fmt::{Debug, Formatter};
+use std::ops::Index;
+use std::sync::Arc;
+use treap_node::TreapNode;
+
+type SubNode<K, V> = Option<Arc<TreapNode<K, V>>>;
+
+mod treap_node;
+
+/// A binary search tree that is also a heap of random priorities, which keeps it
+/// balanced in expectation. Insert, remove, split and merge take expected O(log n).
+///
+/// The priorities come from a StdRng, a treap built with with_seed has the same shape
+/// for the same operations, new() seeds from the operating system.
+#[derive(Clone)]
+pub struct Treap<K: PartialOrd, V> {
+    pub(crate) root: SubNode<K, V>,
+    // boxed, a StdRng takes a few hundred bytes and treaps are moved around by split
+    rng: Box<StdRng>,
+}
+
+impl<K: PartialOrd, V> Default for Treap<K, V> {
+    fn default() -> Self {
+        Treap::new()
+    }
+}
+
+impl<K: PartialOrd, V> Treap<K, V> {
+    pub fn new() -> Treap<K, V> {
+        Treap {
+            root: None,
+            rng: Box::new(StdRng::from_entropy()),
+        }
+    }
+
+    /// a treap with reproducible priorities
+    pub fn with_seed(seed: u64) -> Treap<K, V> {
+        Treap {
+            root: None,
+            rng: Box::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.find(key).is_some()
+    }
+
+    pub fn find(&self, key: &K) -> Option<&V> {
+        let mut node = self.root.as_deref();
+        while let Some(curr) = node {
+            if *key < curr.key {
+                node = curr.smaller.as_deref();
+            } else if *key > curr.key {
+                node = curr.larger.as_deref();
+            } else {
+                return Some(&curr.value);
+            }
+        }
+        None
+    }
+
+    pub fn smallest(&self) -> Option<(&K, &V)> {
+        let mut curr = self.root.as_deref()?;
+        while let Some(subnode) = &curr.smaller {
+            curr = subnode;
+        }
+        Some((&curr.key, &curr.value))
+    }
+
+    /// the entry with the largest key below key
+    pub fn smaller(&self, key: &K) -> Option<(&K, &V)> {
+        let mut candidate = None;
+        let mut node = self.root.as_deref();
+        while let Some(curr) = node {
+            if curr.key < *key {
+                candidate = Some(curr);
+                node = curr.larger.as_deref();
+            } else {
+                node = curr.smaller.as_deref();
+            }
+        }
+        candidate.map(|node| (&node.key, &node.value))
+    }
+
+    pub fn largest(&self) -> Option<(&K, &V)> {
+        let mut curr = self.root.as_deref()?;
+        while let Some(subnode) = &curr.larger {
+            curr = subnode;
+        }
+        Some((&curr.key, &curr.value))
+    }
+
+    /// the entry with the smallest key above key
+    pub fn larger(&self, key: &K) -> Option<(&K, &V)> {
+        let mut candidate = None;
+        let mut node = self.root.as_deref();
+        while let Some(curr) = node {
+            if curr.key > *key {
+                candidate = Some(curr);
+                node = curr.smaller.as_deref();
+            } else {
+                node = curr.larger.as_deref();
+            }
+        }
+        candidate.map(|node| (&node.key, &node.value))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// height, node count and nodes per level, the black height is always None
+    pub fn shape_report(&self) -> ShapeReport {
+        metrics::shape_report::<K, V, _>(self.root.as_deref())
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.push_smaller(self.root.as_deref());
+        iter
+    }
+
+    /// a new treap for one side of a split, seeded from this one to stay reproducible
+    fn with_root(&mut self, root: SubNode<K, V>) -> Treap<K, V> {
+        Treap {
+            root,
+            rng: Box::new(StdRng::seed_from_u64(self.rng.gen())),
+        }
+    }
+}
+
+impl<K: PartialOrd + Debug, V> Treap<K, V> {
+    /// checks the order of the keys and the heap order of the priorities, returns the height
+    pub fn check_rules(&self) -> std::result::Result<usize, String> {
+        match &self.root {
+            Some(root) => root.check_rules(None, None, u64::MAX),
+            None => Ok(0),
+        }
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> Treap<K, V> {
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        // a miss copies the shared nodes on the path, the split below needs them copied too
+        if let Some(curr) = self.path_mut(&key) {
+            return Some(std::mem::replace(curr, value));
+        }
+        // split at the key and merge the new node in between, its priority decides
+        // how far up it ends
+        let node = TreapNode::new(key, value, self.rng.gen());
+        let (smaller, larger) = TreapNode::split(self.root.take(), &node.key);
+        let smaller = TreapNode::merge(smaller, Some(Arc::new(node)));
+        self.root = TreapNode::merge(smaller, larger);
+        None
+    }
+
+    pub fn find_mut(&mut self, key: &K) -> Option<&mut V> {
+        // search first, a miss must not copy the path shared with a clone
+        self.find(key)?;
+        self.path_mut(key)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        // search first, a miss must not copy the path shared with a clone
+        self.find(key)?;
+        TreapNode::remove(&mut self.root, key)
+    }
+
+    /// the value of key, the shared nodes on the search path are copied even on a miss
+    fn path_mut(&mut self, key: &K) -> Option<&mut V> {
+        let mut link = self.root.as_mut();
+        while let Some(node) = link {
+            let curr = Arc::make_mut(node);
+            if *key < curr.key {
+                link = curr.smaller.as_mut();
+            } else if *key > curr.key {
+                link = curr.larger.as_mut();
+            } else {
+                return Some(&mut curr.value);
+            }
+        }
+        None
+    }
+
+    /// Splits the treap into the entries with keys below key and those from key on.
+    /// Nodes a clone still shares are copied, all others are reused.
+    pub fn split(mut self, key: &K) -> (Treap<K, V>, Treap<K, V>) {
+        let (smaller, larger) = TreapNode::split(self.root.take(), key);
+        let larger = self.with_root(larger);
+        self.root = smaller;
+        (self, larger)
+    }
+
+    /// Appends other, whose keys must all be above the keys of self.
+    /// Otherwise other is returned unchanged.
+    pub fn merge(&mut self, other: Treap<K, V>) -> Result<(), (Treap<K, V>, &'static str)> {
+        if let (Some((largest, _)), Some((smallest, _))) = (self.largest(), other.smallest()) {
+            if largest.partial_cmp(smallest) != Some(Ordering::Less) {
+                return Err((other, "cannot merge - the keys overlap"));
+            }
+        }
+        self.root = TreapNode::merge(self.root.take(), other.root);
+        Ok(())
+    }
+}
+
+/// ascending iterator over the entries of a Treap
+pub struct Iter<'a, K: PartialOrd, V> {
+    // nodes still to be visited, the next one on top
+    stack: Vec<&'a TreapNode<K, V>>,
+}
+
+impl<'a, K: PartialOrd, V> Iter<'a, K, V> {
+    fn push_smaller(&mut self, mut node: Option<&'a TreapNode<K, V>>) {
+        while let Some(curr) = node {
+            self.stack.push(curr);
+            node = curr.smaller.as_deref();
+        }
+    }
+}
+
+impl<'a, K: PartialOrd, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_smaller(node.larger.as_deref());
+        Some((&node.key, &node.value))
+    }
+}
+
+impl<'a, K: PartialOrd, V> IntoIterator for &'a Treap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: PartialOrd, V: PartialEq> PartialEq for Treap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl<K: PartialOrd, V> Index<&K> for Treap<K, V> {
+    type Output = V;
+
+    fn index(&self, key: &K) -> &V {
+        self.find(key).expect("key not found in Treap")
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> Extend<(K, V)> for Treap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: PartialOrd + Clone, V: Clone> FromIterator<(K, V)> for Treap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tree = Treap::new();
+        tree.extend(iter);
+        tree
+    }
+}
+
+impl<K: PartialOrd + Debug, V: Debug> Debug for Treap<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tree::shape;
+    use crate::tree::test_map::{self, test_map_impl};
+    use rand::seq::SliceRandom;
+
+    fn check(tree: &Treap<u32, u32>) -> usize {
+        tree.check_rules()
+            .unwrap_or_else(|err| panic!("{}\n{:?}", err, tree))
+    }
+
+    fn keys(tree: &Treap<u32, u32>) -> Vec<u32> {
+        tree.iter().map(|(key, _)| *key).collect()
+    }
+
+    fn build(seed: u64, keys: impl IntoIterator<Item = u32>) -> Treap<u32, u32> {
+        let mut tree = Treap::with_seed(seed);
+        tree.extend(keys.into_iter().map(|key| (key, key)));
+        tree
+    }
+
+    #[test]
+    fn treap_test_insert_remove() {
+        // ascending keys degenerate an unbalanced tree, the priorities keep this one shallow
+        let mut tree = build(1, 0..2000);
+        let height = check(&tree);
+        assert!(height < 40, "height {}", height);
+        assert_eq!(tree.insert(7, 70), Some(7));
+        assert_eq!(tree[&7], 70);
+        assert_eq!(keys(&tree), (0..2000).collect::<Vec<u32>>());
+
+        let mut removed: Vec<u32> = (0..2000).collect();
+        removed.shuffle(&mut StdRng::seed_from_u64(2));
+        for key in &removed[..1500] {
+            assert_eq!(tree.remove(key), Some(if *key == 7 { 70 } else { *key }));
+            assert_eq!(tree.remove(key), None);
+        }
+        check(&tree);
+        let mut rest = removed[1500..].to_vec();
+        rest.sort();
+        assert_eq!(keys(&tree), rest);
+        assert_eq!(tree.smallest().map(|(key, _)| *key), rest.first().copied());
+        assert_eq!(tree.largest().map(|(key, _)| *key), rest.last().copied());
+    }
+
+    test_map_impl!(Treap<u32, u32>, check);
+
+    fn node_ptrs<K: PartialOrd, V>(link: &SubNode<K, V>, ptrs: &mut Vec<usize>) {
+        if let Some(node) = link {
+            ptrs.push(Arc::as_ptr(node) as usize);
+            node_ptrs(&node.smaller, ptrs);
+            node_ptrs(&node.larger, ptrs);
+        }
+    }
+
+    #[test]
+    fn treap_test_clone() {
+        test_map::check_clone(|tree: &Treap<u32, u32>| {
+            let mut ptrs = Vec::new();
+            node_ptrs(&tree.root, &mut ptrs);
+            ptrs
+        });
+    }
+
+    #[test]
+    fn treap_test_against_rbtree() {
+        let (tree, rb_tree, _) =
+            test_map::check_against_rbtree::<Treap<u32, u32>>(23, 5000, 400, 0.55);
+        assert_eq!(tree.shape_report().nodes, rb_tree.iter().count());
+    }
+
+    #[test]
+    fn treap_test_seed() {
+        let first = build(42, [5, 3, 8, 1, 4, 7, 9, 2, 6]);
+        let second = build(42, [5, 3, 8, 1, 4, 7, 9, 2, 6]);
+        let first_shape = shape::encode::<u32, u32, _>(first.root.as_deref());
+        assert_eq!(
+            first_shape,
+            shape::encode::<u32, u32, _>(second.root.as_deref())
+        );
+        // the same entries, but another seed gives another shape in all likelihood
+        let other = build(43, [5, 3, 8, 1, 4, 7, 9, 2, 6]);
+        assert_eq!(first, other);
+        assert_ne!(
+            first_shape,
+            shape::encode::<u32, u32, _>(other.root.as_deref())
+        );
+    }
+
+    #[test]
+    fn treap_test_split_merge() {
+        let tree = build(3, (0..1000).map(|key| key * 2));
+        let copy = tree.clone();
+        let (mut smaller, larger) = tree.split(&500);
+        check(&smaller);
+        check(&larger);
+        assert_eq!(
+            keys(&smaller),
+            (0..250).map(|key| key * 2).collect::<Vec<u32>>()
+        );
+        assert_eq!(
+            keys(&larger),
+            (250..1000).map(|key| key * 2).collect::<Vec<u32>>()
+        );
+        // a split at a missing key
+        let (low, high) = larger.clone().split(&1001);
+        assert_eq!(low.largest().map(|(key, _)| *key), Some(1000));
+        assert_eq!(high.smallest().map(|(key, _)| *key), Some(1002));
+
+        // overlapping keys are refused, nothing is lost
+        let (rejected, msg) = smaller
+            .merge(copy.clone())
+            .expect_err("merged overlapping treaps");
+        assert_eq!(msg, "cannot merge - the keys overlap");
+        assert_eq!(rejected, copy);
+
+        smaller.merge(larger).expect("failed to merge");
+        check(&smaller);
+        assert_eq!(smaller, copy);
+        // splitting shares the nodes off the split path with the clone, which is untouched
+        assert_eq!(
+            keys(&copy),
+            (0..1000).map(|key| key * 2).collect::<Vec<u32>>()
+        );
+        check(&copy);
+
+        let mut empty = Treap::with_seed(4);
+        empty.merge(copy.clone()).expect("failed to merge");
+        assert_eq!(empty, copy);
+        let (none, all) = copy.split(&0);
+        assert!(none.is_empty());
+        assert_eq!(all.iter().count(), 1000);
+    }
+}
@@ -0,0 +1,144 @@
+use super::SubNode;
+use crate::tree::rb_tree::Color;
+use crate::tree::TreeNode;
+use std::cmp::Ordering;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct TreapNode<K: PartialOrd, V> {
+    pub key: K,
+    pub value: V,
+    /// random, no child has a higher priority than its parent
+    pub priority: u64,
+    pub smaller: SubNode<K, V>,
+    pub larger: SubNode<K, V>,
+}
+
+impl<K: PartialOrd, V> TreapNode<K, V> {
+    pub fn new(key: K, value: V, priority: u64) -> TreapNode<K, V> {
+        TreapNode {
+            key,
+            value,
+            priority,
+            smaller: None,
+            larger: None,
+        }
+    }
+}
+
+/// all modifications go through Arc::make_mut, nodes shared with a cloned treap
+/// are copied before they are changed
+impl<K: PartialOrd + Clone, V: Clone> TreapNode<K, V> {
+    /// Splits the subtree into the keys below key and the keys from key on.
+    /// Only the nodes on the search path for key are touched.
+    pub(crate) fn split(link: SubNode<K, V>, key: &K) -> (SubNode<K, V>, SubNode<K, V>) {
+        let Some(mut node) = link else {
+            return (None, None);
+        };
+        let curr = Arc::make_mut(&mut node);
+        if curr.key < *key {
+            let (smaller, larger) = Self::split(curr.larger.take(), key);
+            curr.larger = smaller;
+            (Some(node), larger)
+        } else {
+            let (smaller, larger) = Self::split(curr.smaller.take(), key);
+            curr.smaller = larger;
+            (smaller, Some(node))
+        }
+    }
+
+    /// Joins two subtrees, all keys in smaller must be below all keys in larger.
+    /// The root with the higher priority stays on top, along the spine of the other side.
+    pub(crate) fn merge(smaller: SubNode<K, V>, larger: SubNode<K, V>) -> SubNode<K, V> {
+        match (smaller, larger) {
+            (None, larger) => larger,
+            (smaller, None) => smaller,
+            (Some(mut smaller), Some(mut larger)) => {
+                if smaller.priority > larger.priority {
+                    let node = Arc::make_mut(&mut smaller);
+                    node.larger = Self::merge(node.larger.take(), Some(larger));
+                    Some(smaller)
+                } else {
+                    let node = Arc::make_mut(&mut larger);
+                    node.smaller = Self::merge(Some(smaller), node.smaller.take());
+                    Some(larger)
+                }
+            }
+        }
+    }
+
+    /// remove the node with key from the subtree hanging off link, its children are
+    /// merged into its place. The nodes on the path are copied if shared, so look for
+    /// key first.
+    pub(crate) fn remove(link: &mut SubNode<K, V>, key: &K) -> Option<V> {
+        let node = Arc::make_mut(link.as_mut()?);
+        if *key < node.key {
+            Self::remove(&mut node.smaller, key)
+        } else if *key > node.key {
+            Self::remove(&mut node.larger, key)
+        } else {
+            let mut node = Arc::unwrap_or_clone(link.take().expect("unexpected empty link"));
+            *link = Self::merge(node.smaller.take(), node.larger.take());
+            Some(node.value)
+        }
+    }
+}
+
+impl<K: PartialOrd + Debug, V> TreapNode<K, V> {
+    /// Checks the order of the keys and that no node has a higher priority than its
+    /// parent. Returns the height of the subtree.
+    pub fn check_rules(
+        &self,
+        lower: Option<&K>,
+        upper: Option<&K>,
+        parent_priority: u64,
+    ) -> Result<usize, String> {
+        let below = |smaller: &K, larger: &K| smaller.partial_cmp(larger) == Some(Ordering::Less);
+        if lower.is_some_and(|lower| !below(lower, &self.key))
+            || upper.is_some_and(|upper| !below(&self.key, upper))
+        {
+            return Err(format!(
+                "treap violation: key {:?} out of order between {:?} and {:?}",
+                self.key, lower, upper
+            ));
+        }
+        if self.priority > parent_priority {
+            return Err(format!(
+                "treap violation: priority {} @{:?} above its parent's {}",
+                self.priority, self.key, parent_priority
+            ));
+        }
+        let smaller = match &self.smaller {
+            Some(node) => node.check_rules(lower, Some(&self.key), self.priority)?,
+            None => 0,
+        };
+        let larger = match &self.larger {
+            Some(node) => node.check_rules(Some(&self.key), upper, self.priority)?,
+            None => 0,
+        };
+        Ok(smaller.max(larger) + 1)
+    }
+}
+
+impl<K: PartialOrd, V> TreeNode<K, V> for TreapNode<K, V> {
+    fn key(&self) -> &K {
+        &self.key
+    }
+
+    fn value(&self) -> &V {
+        &self.value
+    }
+
+    fn smaller(&self) -> Option<&Self> {
+        self.smaller.as_deref()
+    }
+
+    fn larger(&self) -> Option<&Self> {
+        self.larger.as_deref()
+    }
+
+    fn color(&self) -> Option<Color> {
+        None
+    }
+}